@@ -6,10 +6,56 @@
 
 namespace kick_synth {
 
+enum class CurveTarget {
+  Amp,
+  Pitch,
+};
+
+enum class DiagnosticEventKind {
+  MidiMessageQueued,
+  MidiMessageDropped,
+  Clip,
+  Allocation,
+};
+
+enum class EnvelopeStage {
+  Off,
+  Attack,
+  Decay,
+  Release,
+  Hold,
+  Sustain,
+  Curve,
+};
+
+struct Diagnostics;
+
 struct KickSynth;
 
 struct MidiMessage;
 
+struct ParamStore;
+
+struct SynthState;
+
+struct DiagnosticEvent {
+  DiagnosticEventKind kind;
+  int32_t timestamp;
+  float value;
+};
+
+struct SynthStateSnapshot {
+  float frequency;
+  float amp_envelope_value;
+  EnvelopeStage amp_envelope_stage;
+  int32_t pitch_envelope_segment;
+  uint32_t voice_count;
+  float peak_output;
+  float saturation_gain_reduction_db;
+  float tempo;
+  double ppq_position;
+};
+
 extern "C" {
 
 KickSynth *create(uintptr_t sample_rate);
@@ -22,7 +68,20 @@ void process_mono(KickSynth *kick_synth, float *block, uintptr_t num_samples);
 
 void process_midi_message(KickSynth *kick_synth, const MidiMessage *midi_message);
 
-void update_param(KickSynth *kick_synth, const char *parameter_id, float new_value);
+void process_raw_midi_message(KickSynth *kick_synth,
+                              int32_t timestamp,
+                              const uint8_t *raw_midi_data,
+                              uint8_t note_pitch,
+                              uint8_t velocity);
+
+/// Passes the tempo in bpm and the position in quarter notes of the host, call it before processing every block.
+/// Synced times follow the tempo from that block onwards.
+void set_transport(KickSynth *kick_synth, double tempo, double ppq_position);
+
+/// The new value is not applied straight away, it is picked up at the start of the next `process` call
+/// and used from that block onwards. Returns false and ignores the new value when the parameter id or the value is invalid.
+/// To update parameters while the audio thread is running, use `param_store_update` instead.
+bool update_param(KickSynth *kick_synth, const char *parameter_id, float new_value);
 
 const MidiMessage *create_midi_message(int32_t timestamp,
                                        const uint8_t *raw_midi_data,
@@ -31,6 +90,142 @@ const MidiMessage *create_midi_message(int32_t timestamp,
 
 void destroy_midi_message(const MidiMessage *midi_message);
 
+/// Returns a handle to the diagnostics of the kick synth, which stays valid until `destroy_diagnostics` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+const Diagnostics *create_diagnostics(const KickSynth *kick_synth);
+
+void destroy_diagnostics(const Diagnostics *diagnostics);
+
+/// Calls `callback` for every logged event since the last drain and returns the number of events.
+/// Only one thread may drain at a time, never call this from the audio thread.
+uintptr_t drain_diagnostics(const Diagnostics *diagnostics,
+                            void (*callback)(const DiagnosticEvent *event, void *user_data),
+                            void *user_data);
+
+uint64_t diagnostics_dropped_midi_messages(const Diagnostics *diagnostics);
+
+uint64_t diagnostics_dropped_log_events(const Diagnostics *diagnostics);
+
+uint64_t diagnostics_clip_events(const Diagnostics *diagnostics);
+
+/// The number of blocks that allocated, always 0 unless gruvahlib is built with the alloc_guard feature
+uint64_t diagnostics_allocation_events(const Diagnostics *diagnostics);
+
+/// Returns a handle to the parameters of the kick synth, which stays valid until `destroy_param_store` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+const ParamStore *create_param_store(const KickSynth *kick_synth);
+
+void destroy_param_store(const ParamStore *param_store);
+
+/// Returns the latest value set for the parameter, or `default_value` when it was never set.
+float param_store_get(const ParamStore *param_store, const char *parameter_id, float default_value);
+
+/// Can be called from any thread, the new value is used from the next processed block onwards.
+/// Returns false and ignores the new value when the parameter id or the value is invalid.
+bool param_store_update(const ParamStore *param_store, const char *parameter_id, float new_value);
+
+/// Loads a Scala scale, and optionally a keyboard mapping, as the tuning of every note.
+/// Pass a null `kbm` to map the scale like Scala does without a .kbm file, with its root on middle C and A4 at 440 Hz.
+/// Returns false and keeps the current tuning when either file is invalid.
+bool param_store_load_tuning(const ParamStore *param_store, const char *scl, const char *kbm);
+
+/// Goes back to 12 tone equal temperament, tuned by the a4_frequency parameter.
+void param_store_reset_tuning(const ParamStore *param_store);
+
+/// Returns a handle to the published state of the kick synth, which stays valid until `destroy_synth_state` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+const SynthState *create_synth_state(const KickSynth *kick_synth);
+
+void destroy_synth_state(const SynthState *synth_state);
+
+/// Copies the state as it was after the last processed block, can be called from any thread.
+void read_synth_state(const SynthState *synth_state, SynthStateSnapshot *snapshot);
+
+/// Renders the amplitude of the next note into `output`, one point every `samples_per_point` samples.
+void render_amp_envelope(const ParamStore *param_store,
+                         uintptr_t sample_rate,
+                         float *output,
+                         uintptr_t num_points,
+                         uintptr_t samples_per_point);
+
+/// Renders the oscillator frequency in Hz of the next note into `output`, one point every `samples_per_point` samples.
+void render_pitch_envelope(const ParamStore *param_store,
+                           uintptr_t sample_rate,
+                           float *output,
+                           uintptr_t num_points,
+                           uintptr_t samples_per_point);
+
+/// Renders one hit with the current parameters into `output`, on a separate kick synth.
+/// Never call this from the audio thread.
+void render_kick_preview(const ParamStore *param_store,
+                         uintptr_t sample_rate,
+                         float *output,
+                         uintptr_t num_samples);
+
+/// Writes `fft_size / 2 + 1` magnitudes in dB per frame into `output`, with a frame every `hop_size` samples.
+/// Returns the number of frames written.
+uintptr_t preview_spectrogram(const float *signal,
+                              uintptr_t num_samples,
+                              uintptr_t fft_size,
+                              uintptr_t hop_size,
+                              float *output,
+                              uintptr_t output_size);
+
+/// Writes the fundamental frequency in Hz of every frame into `output`, 0 for silent frames.
+/// Returns the number of frames written.
+uintptr_t preview_fundamental_trace(const float *signal,
+                                    uintptr_t num_samples,
+                                    uintptr_t sample_rate,
+                                    uintptr_t fft_size,
+                                    uintptr_t hop_size,
+                                    float *output,
+                                    uintptr_t output_size);
+
+uintptr_t preview_num_frames(uintptr_t num_samples, uintptr_t hop_size);
+
+/// Writes the nul terminated point list into `output` when it fits, and returns its length without the nul.
+/// Call it with an `output_size` of 0 first to find out how much space is needed.
+uintptr_t pitch_points_serialize(const ParamStore *param_store, char *output, uintptr_t output_size);
+
+/// Replaces the whole point list by calling `set_param` for every parameter that has to change.
+/// Nothing is written to the store, set the changes on the plugin parameters, which pass them on to the store.
+/// Returns false and calls nothing when the list is invalid.
+bool pitch_points_deserialize(const char *serialized_points,
+                              void (*set_param)(const char *parameter_id, float new_value, void *user_data),
+                              void *user_data);
+
+/// Inserts a point like "octave,note,timing,curve,tension,cents,hz,sync" at `index`, counted from 0,
+/// by calling `set_param` for every parameter that has to change, like `pitch_points_deserialize`.
+/// Returns false when the point is invalid, the index is out of range or the envelope is full.
+bool pitch_points_insert(const ParamStore *param_store,
+                         uintptr_t index,
+                         const char *serialized_point,
+                         void (*set_param)(const char *parameter_id, float new_value, void *user_data),
+                         void *user_data);
+
+/// Removes the point at `index`, counted from 0, by calling `set_param` for every parameter that has to change,
+/// like `pitch_points_deserialize`. Returns false when the index is out of range or it is the only point.
+bool pitch_points_remove(const ParamStore *param_store,
+                         uintptr_t index,
+                         void (*set_param)(const char *parameter_id, float new_value, void *user_data),
+                         void *user_data);
+
+/// Writes the nul terminated point list of the curve into `output` when it fits, and returns its length without the nul.
+/// Call it with an `output_size` of 0 first to find out how much space is needed.
+uintptr_t curve_envelope_serialize(const ParamStore *param_store,
+                                   CurveTarget target,
+                                   char *output,
+                                   uintptr_t output_size);
+
+/// Replaces the points of the curve with a list like "0,1,0;20,0.5,-2;300,0", time in ms, value and curvature.
+/// Amp values are levels, pitch values are frequencies in Hz. Returns false and changes nothing when the list is invalid,
+/// an empty list is valid and switches the curve off.
+bool curve_envelope_deserialize(const ParamStore *param_store, CurveTarget target, const char *serialized_points);
+
+/// Returns the latency in samples that the current parameters add, report it to the host.
+/// The latency only changes with the oversampling and the limiter parameters.
+uintptr_t get_latency_samples(const ParamStore *param_store, uintptr_t sample_rate);
+
 } // extern "C"
 
 } // namespace kick_synth
@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+// With the alloc_guard feature, and in the tests, every allocation made while an AllocGuard is alive is counted,
+// this is how we make sure process_block never allocates on the audio thread. The count is logged to the
+// diagnostics instead of panicking, a panic can't unwind through the extern "C" functions that call process_block.
+// Without the feature the global allocator is left alone and the guard does nothing.
+
+#[cfg(any(test, feature = "alloc_guard"))]
+mod checked {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        static ALLOCATION_FORBIDDEN: Cell<bool> = const { Cell::new(false) };
+        static FORBIDDEN_ALLOCATIONS: Cell<u32> = const { Cell::new(0) };
+    }
+
+    struct CheckedAllocator;
+
+    #[global_allocator]
+    static ALLOCATOR: CheckedAllocator = CheckedAllocator;
+
+    // Counting only touches thread locals, so it never allocates itself
+    fn check_allocation_allowed() {
+        let forbidden = ALLOCATION_FORBIDDEN.try_with(|forbidden| forbidden.get()).unwrap_or(false);
+
+        if forbidden {
+            let _ = FORBIDDEN_ALLOCATIONS.try_with(|count| count.set(count.get().saturating_add(1)));
+        }
+    }
+
+    unsafe impl GlobalAlloc for CheckedAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            check_allocation_allowed();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            check_allocation_allowed();
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            check_allocation_allowed();
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            check_allocation_allowed();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    pub(crate) struct AllocGuard {
+        was_forbidden: bool,
+        allocations_before: u32,
+    }
+
+    impl AllocGuard {
+        pub(crate) fn new() -> Self {
+            let was_forbidden = ALLOCATION_FORBIDDEN.with(|forbidden| forbidden.replace(true));
+            let allocations_before = FORBIDDEN_ALLOCATIONS.with(|count| count.get());
+            Self { was_forbidden, allocations_before }
+        }
+
+        // The allocations since the guard was created
+        pub(crate) fn get_allocations(&self) -> u32 {
+            FORBIDDEN_ALLOCATIONS.with(|count| count.get()) - self.allocations_before
+        }
+    }
+
+    impl Drop for AllocGuard {
+        fn drop(&mut self) {
+            ALLOCATION_FORBIDDEN.with(|forbidden| forbidden.set(self.was_forbidden));
+        }
+    }
+}
+
+#[cfg(not(any(test, feature = "alloc_guard")))]
+mod checked {
+    pub(crate) struct AllocGuard;
+
+    impl AllocGuard {
+        pub(crate) fn new() -> Self {
+            AllocGuard
+        }
+
+        pub(crate) fn get_allocations(&self) -> u32 {
+            0
+        }
+    }
+}
+
+pub(crate) use checked::AllocGuard;
+
+#[cfg(test)]
+mod test {
+    use crate::alloc_guard::AllocGuard;
+    use crate::midi_message::MidiMessage;
+    use crate::KickSynth;
+
+    #[test]
+    fn test_allocation_outside_guard() {
+        let guard = AllocGuard::new();
+        drop(guard);
+
+        let buffer: Vec<f32> = Vec::with_capacity(16);
+        assert_eq!(buffer.capacity(), 16);
+    }
+
+    #[test]
+    fn test_allocation_inside_guard() {
+        let guard = AllocGuard::new();
+        let buffer: Vec<f32> = Vec::with_capacity(16);
+        assert_eq!(buffer.capacity(), 16);
+        drop(buffer);
+
+        // allocating and freeing
+        assert_eq!(guard.get_allocations(), 2);
+    }
+
+    #[test]
+    fn test_process_block_does_not_allocate() {
+        let mut kick_synth = KickSynth::new(48000);
+        // the changed parameters are applied inside process_block, so they are covered by the guard as well
+        kick_synth.set_param("waveType", 1.0);
+        kick_synth.set_param("amp_release", 100.0);
+
+        let mut block = [0.0_f32; 512];
+
+        let guard = AllocGuard::new();
+        kick_synth.queue_midi_message(&MidiMessage::new(0, &0x90, 36, 100));
+        kick_synth.process_block(&mut block);
+        kick_synth.process_block(&mut block);
+
+        assert_eq!(guard.get_allocations(), 0);
+        assert_eq!(kick_synth.get_diagnostics().get_allocation_events(), 0);
+    }
+}
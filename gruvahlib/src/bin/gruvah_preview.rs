@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+// Renders one kick and prints its fundamental frequency and level over time, for example:
+// gruvah_preview --length-ms 300 --wav kick.wav octave_1=7 amp_release=250
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use gruvahlib::preview::{compute_fundamental_trace, compute_spectrogram, render_preview, spectrogram_num_bins, spectrogram_num_frames};
+
+// Same defaults as the plugin
+const DEFAULT_PARAMS: [(&str, f32); 21] = [
+    ("octave_1", 8.0), ("note_1", 0.0), ("timing_1", 0.0),
+    ("octave_2", 4.0), ("note_2", 7.0), ("timing_2", 2.12),
+    ("octave_3", 3.0), ("note_3", 5.0), ("timing_3", 16.55),
+    ("octave_4", 1.0), ("note_4", 9.0), ("timing_4", 69.09),
+    ("amp_attack", 0.65),
+    ("amp_decay", 10.0),
+    ("amp_sustain", 100.0),
+    ("amp_release", 419.43),
+    ("amp_exponential_factor_a", 4.31),
+    ("phase", 0.0),
+    ("waveType", 0.0),
+    ("driveDb", 0.0),
+    ("saturationType", 0.0),
+];
+
+struct Options {
+    sample_rate: usize,
+    length_ms: f32,
+    fft_size: usize,
+    hop_size: usize,
+    wav_path: Option<String>,
+    params: Vec<(String, f32)>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        sample_rate: 48000,
+        length_ms: 500.0,
+        fft_size: 4096,
+        hop_size: 1024,
+        wav_path: None,
+        params: DEFAULT_PARAMS.iter().map(|(id, value)| (id.to_string(), *value)).collect(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut next_value = || args.next().unwrap_or_else(|| panic!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--sample-rate" => options.sample_rate = next_value().parse().expect("Invalid sample rate"),
+            "--length-ms" => options.length_ms = next_value().parse().expect("Invalid length"),
+            "--fft-size" => options.fft_size = next_value().parse().expect("Invalid fft size"),
+            "--hop-size" => options.hop_size = next_value().parse().expect("Invalid hop size"),
+            "--wav" => options.wav_path = Some(next_value()),
+            param => {
+                let (parameter_id, new_value) = param.split_once('=')
+                    .unwrap_or_else(|| panic!("Expected parameter_id=value, got: {}", param));
+                let new_value = new_value.parse().unwrap_or_else(|_| panic!("Invalid value for {}", parameter_id));
+                options.params.push((parameter_id.to_string(), new_value));
+            }
+        }
+    }
+
+    options
+}
+
+// 32 bit float mono wav
+fn write_wav(path: &str, sample_rate: usize, samples: &[f32]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let data_size = (samples.len() * 4) as u32;
+
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16_u32.to_le_bytes())?;
+    file.write_all(&3_u16.to_le_bytes())?;
+    file.write_all(&1_u16.to_le_bytes())?;
+    file.write_all(&(sample_rate as u32).to_le_bytes())?;
+    file.write_all(&(sample_rate as u32 * 4).to_le_bytes())?;
+    file.write_all(&4_u16.to_le_bytes())?;
+    file.write_all(&32_u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())?;
+
+    for sample in samples {
+        file.write_all(&sample.to_le_bytes())?;
+    }
+
+    file.flush()
+}
+
+fn main() {
+    let options = parse_options();
+
+    let num_samples = (options.length_ms / 1000.0 * options.sample_rate as f32) as usize;
+    let mut output = vec![0.0; num_samples];
+
+    let params: Vec<(&str, f32)> = options.params.iter().map(|(id, value)| (id.as_str(), *value)).collect();
+    render_preview(&params, options.sample_rate, &mut output);
+
+    let num_frames = spectrogram_num_frames(num_samples, options.hop_size);
+    let num_bins = spectrogram_num_bins(options.fft_size);
+
+    let mut fundamental_trace = vec![0.0; num_frames];
+    compute_fundamental_trace(&output, options.sample_rate, options.fft_size, options.hop_size, &mut fundamental_trace);
+
+    let mut spectrogram = vec![0.0; num_frames * num_bins];
+    compute_spectrogram(&output, options.fft_size, options.hop_size, &mut spectrogram);
+
+    println!("{:>10} {:>16} {:>12}", "time (ms)", "fundamental (Hz)", "peak (dB)");
+    for frame in 0..num_frames {
+        let time_ms = (frame * options.hop_size) as f32 / options.sample_rate as f32 * 1000.0;
+        let peak_db = spectrogram[frame * num_bins..(frame + 1) * num_bins].iter().fold(f32::MIN, |peak, x| peak.max(*x));
+
+        println!("{:>10.1} {:>16.2} {:>12.1}", time_ms, fundamental_trace[frame], peak_db);
+    }
+
+    let peak = output.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+    println!("peak sample: {:.3}", peak);
+
+    if let Some(wav_path) = options.wav_path {
+        write_wav(&wav_path, options.sample_rate, &output).expect("Could not write wav file");
+        println!("written to {}", wav_path);
+    }
+}
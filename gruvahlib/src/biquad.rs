@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+// the Q of a second order Butterworth filter, two of them in a row are a 4th order Linkwitz-Riley filter
+pub(crate) const BUTTERWORTH_Q: f32 = FRAC_1_SQRT_2;
+
+// A second order filter with the coefficients from the Audio EQ Cookbook, as transposed direct form II.
+// Setting new coefficients keeps the state, so the filter can change while it is running.
+#[derive(Clone, Copy)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    // Passes everything unchanged until it gets coefficients
+    pub(crate) fn new() -> Self {
+        Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+
+        output
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    // Returns cos(w0) and alpha of the cookbook, the frequency stays below Nyquist
+    fn get_cos_and_alpha(sample_rate: f32, frequency: f32, q: f32) -> (f32, f32) {
+        let w0 = 2.0 * PI * frequency.clamp(1.0, sample_rate * 0.49) / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn set_coefficients(&mut self, b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) {
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    pub(crate) fn set_lowpass(&mut self, sample_rate: f32, frequency: f32, q: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        self.set_coefficients((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+    }
+
+    pub(crate) fn set_highpass(&mut self, sample_rate: f32, frequency: f32, q: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        self.set_coefficients((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+    }
+
+    pub(crate) fn set_allpass(&mut self, sample_rate: f32, frequency: f32, q: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        self.set_coefficients(1.0 - alpha, -2.0 * cos, 1.0 + alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+    }
+
+    // The gain is in dB, at 0 dB the peak and the shelves pass everything unchanged
+    pub(crate) fn set_peak(&mut self, sample_rate: f32, frequency: f32, q: f32, gain: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        let a = 10.0_f32.powf(gain / 40.0);
+        self.set_coefficients(1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a);
+    }
+
+    pub(crate) fn set_low_shelf(&mut self, sample_rate: f32, frequency: f32, q: f32, gain: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        let a = 10.0_f32.powf(gain / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        self.set_coefficients(
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            (a + 1.0) + (a - 1.0) * cos + beta,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - beta,
+        );
+    }
+
+    pub(crate) fn set_high_shelf(&mut self, sample_rate: f32, frequency: f32, q: f32, gain: f32) {
+        let (cos, alpha) = Self::get_cos_and_alpha(sample_rate, frequency, q);
+        let a = 10.0_f32.powf(gain / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        self.set_coefficients(
+            a * ((a + 1.0) + (a - 1.0) * cos + beta),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            (a + 1.0) - (a - 1.0) * cos + beta,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - beta,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::biquad::{Biquad, BUTTERWORTH_Q};
+    use crate::test_utils::get_sine_gain;
+
+    fn get_gain(biquad: &mut Biquad, frequency: f32, sample_rate: f32) -> f32 {
+        get_sine_gain(|x| biquad.process_sample(x), frequency, sample_rate)
+    }
+
+    #[test]
+    fn test_butterworth_is_3_db_down_at_cutoff() {
+        let mut lowpass = Biquad::new();
+        lowpass.set_lowpass(48000.0, 1000.0, BUTTERWORTH_Q);
+        assert_approx_eq!(get_gain(&mut lowpass, 1000.0, 48000.0), BUTTERWORTH_Q, 1.0e-3);
+        assert_approx_eq!(get_gain(&mut lowpass, 50.0, 48000.0), 1.0, 1.0e-3);
+
+        let mut highpass = Biquad::new();
+        highpass.set_highpass(48000.0, 1000.0, BUTTERWORTH_Q);
+        assert_approx_eq!(get_gain(&mut highpass, 1000.0, 48000.0), BUTTERWORTH_Q, 1.0e-3);
+        assert!(get_gain(&mut highpass, 50.0, 48000.0) < 0.01);
+    }
+
+    #[test]
+    fn test_allpass() {
+        let mut allpass = Biquad::new();
+        allpass.set_allpass(48000.0, 1000.0, BUTTERWORTH_Q);
+
+        for frequency in [50.0, 1000.0, 8000.0] {
+            assert_approx_eq!(get_gain(&mut allpass, frequency, 48000.0), 1.0, 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn test_peak_and_shelves() {
+        let mut peak = Biquad::new();
+        peak.set_peak(48000.0, 1000.0, 1.0, 6.0206);
+        assert_approx_eq!(get_gain(&mut peak, 1000.0, 48000.0), 2.0, 1.0e-2);
+        assert_approx_eq!(get_gain(&mut peak, 50.0, 48000.0), 1.0, 1.0e-2);
+
+        let mut low_shelf = Biquad::new();
+        low_shelf.set_low_shelf(48000.0, 1000.0, BUTTERWORTH_Q, -6.0206);
+        assert_approx_eq!(get_gain(&mut low_shelf, 50.0, 48000.0), 0.5, 1.0e-2);
+        assert_approx_eq!(get_gain(&mut low_shelf, 16000.0, 48000.0), 1.0, 1.0e-2);
+
+        let mut high_shelf = Biquad::new();
+        high_shelf.set_high_shelf(48000.0, 1000.0, BUTTERWORTH_Q, -6.0206);
+        assert_approx_eq!(get_gain(&mut high_shelf, 50.0, 48000.0), 1.0, 1.0e-2);
+        assert_approx_eq!(get_gain(&mut high_shelf, 16000.0, 48000.0), 0.5, 1.0e-2);
+    }
+}
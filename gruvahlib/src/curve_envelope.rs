@@ -0,0 +1,446 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+use std::ffi::c_char;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::param_store::ParamStore;
+
+// An envelope drawn as a list of points, which can replace the amp envelope or the pitch envelope.
+// A point is serialized as "time,value,curvature", with the time in ms from the note on and the points separated by ';'.
+// The curvature bends the segment towards the point, 0 is a straight line, above 0 most of the change happens at the end
+// and below 0 at the start.
+
+pub(crate) const MAX_CURVE_POINTS: usize = 64;
+const CURVE_POINT_FIELDS: usize = 3;
+// Beyond this the bend is a step, and exp_m1 overflows to NaN above about 88
+const MAX_CURVATURE: f32 = 20.0;
+// An amp curve that doesn't end at 0 would hold its last level forever, it fades out to 0 over this time instead
+const AMP_CURVE_FADE_OUT_MS: f32 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvelopeSource {
+    // the amp envelope or the pitch points
+    Envelope,
+    Curve,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveTarget {
+    Amp,
+    Pitch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub(crate) struct CurvePoint {
+    time: f32,
+    value: f32,
+    curvature: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CurvePoints {
+    points: [CurvePoint; MAX_CURVE_POINTS],
+    num_points: usize,
+}
+
+impl CurvePoints {
+    pub(crate) fn new() -> Self {
+        Self {
+            points: [Default::default(); MAX_CURVE_POINTS],
+            num_points: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[CurvePoint] {
+        &self.points[..self.num_points]
+    }
+}
+
+// Missing curvatures are 0, so "10,0.5" is a valid point
+fn parse_point(serialized_point: &str) -> Option<CurvePoint> {
+    let mut fields = serialized_point.split(',').map(str::trim);
+
+    let time: f32 = fields.next()?.parse().ok()?;
+    let value: f32 = fields.next()?.parse().ok()?;
+    let curvature: f32 = match fields.next() {
+        Some(curvature) if !curvature.is_empty() => curvature.parse().ok()?,
+        _ => 0.0,
+    };
+
+    if fields.next().is_some() || !time.is_finite() || !value.is_finite() || !curvature.is_finite() {
+        return None;
+    }
+
+    Some(CurvePoint { time, value, curvature: curvature.clamp(-MAX_CURVATURE, MAX_CURVATURE) })
+}
+
+// Points have to be in order of time, pitch curves only take positive frequencies.
+// Amp curves always end at 0, a fade out is added when the last point is above it.
+pub(crate) fn deserialize_curve(serialized_points: &str, target: CurveTarget) -> Option<CurvePoints> {
+    let mut curve_points = CurvePoints::new();
+
+    for serialized_point in serialized_points.split(';').filter(|serialized_point| !serialized_point.trim().is_empty()) {
+        let point = parse_point(serialized_point)?;
+
+        let previous_time = curve_points.as_slice().last().map_or(0.0, |previous: &CurvePoint| previous.time);
+        if curve_points.num_points == MAX_CURVE_POINTS || point.time < previous_time {
+            return None;
+        }
+
+        if target == CurveTarget::Pitch && point.value <= 0.0 {
+            return None;
+        }
+
+        curve_points.points[curve_points.num_points] = point;
+        curve_points.num_points += 1;
+    }
+
+    let last_point = *curve_points.as_slice().last()?;
+    if target == CurveTarget::Amp && last_point.value != 0.0 {
+        if curve_points.num_points == MAX_CURVE_POINTS {
+            return None;
+        }
+
+        curve_points.points[curve_points.num_points] = CurvePoint { time: last_point.time + AMP_CURVE_FADE_OUT_MS, value: 0.0, curvature: 0.0 };
+        curve_points.num_points += 1;
+    }
+
+    Some(curve_points)
+}
+
+pub(crate) fn serialize_curve(curve_points: &CurvePoints) -> String {
+    curve_points.as_slice().iter()
+        .map(|point| format!("{},{},{}", point.time, point.value, point.curvature))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// The points of a curve, which any thread can replace, handed to the audio thread like the parameters
+pub(crate) struct CurveStore {
+    values: [AtomicU32; MAX_CURVE_POINTS * CURVE_POINT_FIELDS],
+    num_points: AtomicUsize,
+    changed: AtomicBool,
+}
+
+impl CurveStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            values: std::array::from_fn(|_| AtomicU32::new(0)),
+            num_points: AtomicUsize::new(0),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set(&self, curve_points: &CurvePoints) {
+        for (values, point) in self.values.chunks(CURVE_POINT_FIELDS).zip(curve_points.as_slice()) {
+            values[0].store(point.time.to_bits(), Ordering::Relaxed);
+            values[1].store(point.value.to_bits(), Ordering::Relaxed);
+            values[2].store(point.curvature.to_bits(), Ordering::Relaxed);
+        }
+
+        self.num_points.store(curve_points.num_points, Ordering::Relaxed);
+        self.changed.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn get(&self) -> CurvePoints {
+        let mut curve_points = CurvePoints::new();
+        curve_points.num_points = self.num_points.load(Ordering::Acquire);
+
+        for (point, values) in curve_points.points.iter_mut().zip(self.values.chunks(CURVE_POINT_FIELDS)) {
+            point.time = f32::from_bits(values[0].load(Ordering::Relaxed));
+            point.value = f32::from_bits(values[1].load(Ordering::Relaxed));
+            point.curvature = f32::from_bits(values[2].load(Ordering::Relaxed));
+        }
+
+        curve_points
+    }
+
+    // Only the audio thread should call this. Points replaced while they are read are picked up again on the next call.
+    pub(crate) fn apply_changes(&self, apply: impl FnOnce(&CurvePoints)) {
+        if self.changed.swap(false, Ordering::Acquire) {
+            apply(&self.get());
+        }
+    }
+}
+
+// Plays the points back sample by sample, with the same timing as Envelope::process_sample
+#[derive(Clone, Debug)]
+pub(crate) struct CurveEnvelope {
+    sample_rate: usize,
+    points: CurvePoints,
+    // pitch curves move in octaves, so a straight line sounds like the exponential pitch curve
+    logarithmic: bool,
+    current_sample: usize,
+    current_point: usize,
+    active: bool,
+}
+
+impl CurveEnvelope {
+    pub(crate) fn new(sample_rate: usize, logarithmic: bool) -> Self {
+        Self {
+            sample_rate,
+            points: CurvePoints::new(),
+            logarithmic,
+            current_sample: 0,
+            current_point: 0,
+            active: false,
+        }
+    }
+
+    pub(crate) fn set_points(&mut self, curve_points: &CurvePoints) {
+        self.points = *curve_points;
+
+        if self.logarithmic {
+            for point in self.points.points.iter_mut() {
+                point.value = point.value.max(f32::MIN_POSITIVE).log2();
+            }
+        }
+
+        // a running note continues from the segment it is in with the new points
+        self.current_point = 0;
+    }
+
+    // without points the envelope or the pitch points are used instead
+    pub(crate) fn has_points(&self) -> bool {
+        self.points.num_points > 0
+    }
+
+    pub(crate) fn note_on(&mut self) {
+        self.current_sample = 0;
+        self.current_point = 0;
+        self.active = true;
+    }
+
+    // the curve keeps the value of its last point once it has passed it
+    pub(crate) fn is_finished(&self) -> bool {
+        !self.active || self.current_point >= self.points.num_points
+    }
+
+    // index of the point the curve is currently moving towards
+    pub(crate) fn get_current_segment(&self) -> i32 {
+        self.current_point as i32
+    }
+
+    fn get_sample_timing(&self, point: &CurvePoint) -> usize {
+        (point.time.max(0.0) / 1000.0 * self.sample_rate as f32) as usize
+    }
+
+    pub(crate) fn process_sample(&mut self) -> f32 {
+        let points = self.points.as_slice();
+        let Some(last_point) = points.last() else {
+            return 0.0;
+        };
+
+        if !self.active {
+            return 0.0;
+        }
+
+        while self.current_point < points.len() && self.get_sample_timing(&points[self.current_point]) <= self.current_sample {
+            self.current_point += 1;
+        }
+
+        let value = match self.current_point {
+            0 => points[0].value,
+            current_point if current_point == points.len() => last_point.value,
+            current_point => {
+                let previous = points[current_point - 1];
+                let next = points[current_point];
+                let segment_start = self.get_sample_timing(&previous);
+                let segment_length = self.get_sample_timing(&next) - segment_start;
+                let fraction = (self.current_sample - segment_start) as f32 / segment_length as f32;
+
+                previous.value + (next.value - previous.value) * get_curved_fraction(fraction, next.curvature)
+            }
+        };
+
+        self.current_sample += 1;
+
+        match self.logarithmic {
+            true => 2.0_f32.powf(value),
+            false => value,
+        }
+    }
+
+    // Renders the next note like render_curve of the other envelopes
+    pub(crate) fn render_curve(&self, output: &mut [f32], samples_per_point: usize) {
+        let mut curve_envelope = self.clone();
+        curve_envelope.note_on();
+
+        for x in output.iter_mut() {
+            *x = curve_envelope.process_sample();
+
+            for _ in 1..samples_per_point {
+                curve_envelope.process_sample();
+            }
+        }
+    }
+}
+
+fn get_curved_fraction(fraction: f32, curvature: f32) -> f32 {
+    if curvature.abs() < 1.0e-3 {
+        return fraction;
+    }
+
+    let curvature = curvature.clamp(-MAX_CURVATURE, MAX_CURVATURE);
+    (curvature * fraction).exp_m1() / curvature.exp_m1()
+}
+
+/// Writes the nul terminated point list of the curve into `output` when it fits, and returns its length without the nul.
+/// Call it with an `output_size` of 0 first to find out how much space is needed.
+///
+/// # Safety
+/// `output` must point to at least `output_size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn curve_envelope_serialize(
+    param_store: &ParamStore,
+    target: CurveTarget,
+    output: *mut c_char,
+    output_size: usize,
+) -> usize {
+    let serialized_points = serialize_curve(&param_store.get_curve_store(target).get());
+
+    if serialized_points.len() < output_size {
+        let output = std::slice::from_raw_parts_mut(output as *mut u8, output_size);
+        output[..serialized_points.len()].copy_from_slice(serialized_points.as_bytes());
+        output[serialized_points.len()] = 0;
+    }
+
+    serialized_points.len()
+}
+
+/// Replaces the points of the curve with a list like "0,1,0;20,0.5,-2;300,0", time in ms, value and curvature.
+/// Amp values are levels, pitch values are frequencies in Hz. Returns false and changes nothing when the list is invalid,
+/// an empty list is valid and switches the curve off.
+///
+/// # Safety
+/// `serialized_points` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn curve_envelope_deserialize(param_store: &ParamStore, target: CurveTarget, serialized_points: *const c_char) -> bool {
+    let serialized_points = std::ffi::CStr::from_ptr(serialized_points).to_string_lossy();
+
+    let curve_points = match serialized_points.trim().is_empty() {
+        true => CurvePoints::new(),
+        false => match deserialize_curve(&serialized_points, target) {
+            Some(curve_points) => curve_points,
+            None => return false,
+        },
+    };
+
+    param_store.get_curve_store(target).set(&curve_points);
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::curve_envelope::{deserialize_curve, serialize_curve, CurveEnvelope, CurveStore, CurveTarget};
+
+    #[test]
+    fn test_round_trip() {
+        let curve_points = deserialize_curve("0,0;1.5,1,2; 300,0,-1.5;", CurveTarget::Amp).unwrap();
+        assert_eq!(serialize_curve(&curve_points), "0,0,0;1.5,1,2;300,0,-1.5");
+    }
+
+    #[test]
+    fn test_invalid_curves() {
+        assert!(deserialize_curve("", CurveTarget::Amp).is_none());
+        assert!(deserialize_curve("0,1;10", CurveTarget::Amp).is_none());
+        assert!(deserialize_curve("10,1;5,0", CurveTarget::Amp).is_none());
+        assert!(deserialize_curve("0,1,0,1", CurveTarget::Amp).is_none());
+        assert!(deserialize_curve("0,1000;50,0", CurveTarget::Pitch).is_none());
+        assert!(deserialize_curve(&"0,1;".repeat(65), CurveTarget::Amp).is_none());
+        // no room left for the fade out
+        assert!(deserialize_curve(&"0,1;".repeat(64), CurveTarget::Amp).is_none());
+    }
+
+    #[test]
+    fn test_amp_curve_ends_at_zero() {
+        let curve_points = deserialize_curve("0,1;10,0.5", CurveTarget::Amp).unwrap();
+        assert_eq!(serialize_curve(&curve_points), "0,1,0;10,0.5,0;15,0,0");
+
+        let mut curve_envelope = CurveEnvelope::new(1000, false);
+        curve_envelope.set_points(&curve_points);
+        curve_envelope.note_on();
+
+        let curve: Vec<f32> = (0..20).map(|_| curve_envelope.process_sample()).collect();
+        assert_approx_eq!(curve[12], 0.3);
+        assert_eq!(curve[19], 0.0);
+        assert!(curve_envelope.is_finished());
+
+        // pitch curves hold their last frequency
+        let curve_points = deserialize_curve("0,400;10,100", CurveTarget::Pitch).unwrap();
+        assert_eq!(serialize_curve(&curve_points), "0,400,0;10,100,0");
+    }
+
+    #[test]
+    fn test_large_curvature() {
+        let curve_points = deserialize_curve("0,0;10,1,500;20,0,-1e30", CurveTarget::Amp).unwrap();
+        assert_eq!(serialize_curve(&curve_points), "0,0,0;10,1,20;20,0,-20");
+
+        let mut curve_envelope = CurveEnvelope::new(1000, false);
+        curve_envelope.set_points(&curve_points);
+
+        let mut curve = [0.0_f32; 25];
+        curve_envelope.render_curve(&mut curve, 1);
+        assert!(curve.iter().all(|x| x.is_finite() && (0.0..=1.0).contains(x)));
+        // bent all the way towards the end of the segment
+        assert!(curve[9] < 0.2);
+        assert_eq!(curve[10], 1.0);
+    }
+
+    #[test]
+    fn test_sample_accurate_timing() {
+        let mut curve_envelope = CurveEnvelope::new(1000, false);
+        curve_envelope.set_points(&deserialize_curve("0,0;10,1;20,1;30,0,2", CurveTarget::Amp).unwrap());
+
+        let mut curve = [0.0_f32; 40];
+        curve_envelope.render_curve(&mut curve, 1);
+
+        assert_eq!(curve[0], 0.0);
+        assert_approx_eq!(curve[5], 0.5);
+        assert_eq!(curve[10], 1.0);
+        assert_eq!(curve[19], 1.0);
+        // bent towards the end of the segment
+        assert_approx_eq!(curve[25], 1.0 - 1.0_f32.exp_m1() / 2.0_f32.exp_m1());
+        assert_eq!(curve[30], 0.0);
+        assert_eq!(curve[39], 0.0);
+    }
+
+    #[test]
+    fn test_pitch_curve_moves_in_octaves() {
+        let mut curve_envelope = CurveEnvelope::new(1000, true);
+        curve_envelope.set_points(&deserialize_curve("0,400;10,100", CurveTarget::Pitch).unwrap());
+        curve_envelope.note_on();
+
+        let frequencies: Vec<f32> = (0..12).map(|_| curve_envelope.process_sample()).collect();
+
+        assert_approx_eq!(frequencies[0], 400.0, 0.01);
+        assert_approx_eq!(frequencies[5], 200.0, 0.01);
+        assert_approx_eq!(frequencies[11], 100.0, 0.01);
+        assert!(curve_envelope.is_finished());
+    }
+
+    #[test]
+    fn test_store_applies_latest_points_once() {
+        let curve_store = CurveStore::new();
+        curve_store.set(&deserialize_curve("0,1;10,0", CurveTarget::Amp).unwrap());
+
+        let mut applied = vec![];
+        curve_store.apply_changes(|curve_points| applied.push(serialize_curve(curve_points)));
+        curve_store.apply_changes(|_| panic!("Nothing changed"));
+
+        assert_eq!(applied, vec!["0,1,0;10,0,0"]);
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::curve_envelope::{CurveEnvelope, CurveTarget};
+use crate::envelope::Envelope;
+use crate::param_store::ParamStore;
+use crate::params::Param;
+use crate::pitch_envelope::PitchEnvelope;
+use crate::tempo_sync::TempoSync;
+
+// The editor draws the envelopes from the parameter store instead of the running kick synth,
+// this way it never touches anything the audio thread is using.
+
+pub(crate) fn amp_envelope_from_params(param_store: &ParamStore, sample_rate: usize) -> Envelope {
+    let mut envelope = Envelope::new(sample_rate);
+    let mut tempo_sync = TempoSync::new();
+    tempo_sync.set_tempo(param_store.get_tempo());
+
+    param_store.for_each_param(|param| {
+        let Some(param) = tempo_sync.resolve(param) else {
+            return;
+        };
+
+        match param {
+            Param::AmpAttack(attack) => envelope.set_attack(attack),
+            Param::AmpDecay(decay) => envelope.set_decay(decay),
+            Param::AmpSustain(sustain) => envelope.set_sustain(sustain),
+            Param::AmpRelease(release) => envelope.set_release(release),
+            Param::AmpExponentialFactorA(factor) => envelope.set_exponential_factor_a(factor),
+            Param::AmpHold(hold) => envelope.set_hold(hold),
+            Param::AmpSustainTime(sustain_time) => envelope.set_sustain_time(sustain_time),
+            Param::AmpAttackCurve(curve) => envelope.set_attack_curve(curve),
+            Param::AmpAttackTension(tension) => envelope.set_attack_tension(tension),
+            Param::AmpDecayCurve(curve) => envelope.set_decay_curve(curve),
+            Param::AmpDecayTension(tension) => envelope.set_decay_tension(tension),
+            Param::AmpReleaseCurve(curve) => envelope.set_release_curve(curve),
+            Param::Length(length) => envelope.set_length(length),
+            Param::LengthKeepAttack(keep_attack) => envelope.set_keep_attack(keep_attack),
+            _ => {}
+        }
+    });
+
+    envelope
+}
+
+pub(crate) fn pitch_envelope_from_params(param_store: &ParamStore, sample_rate: usize) -> PitchEnvelope {
+    let mut pitch_envelope = PitchEnvelope::new(sample_rate);
+    let mut tempo_sync = TempoSync::new();
+    tempo_sync.set_tempo(param_store.get_tempo());
+
+    param_store.for_each_param(|param| {
+        let Some(param) = tempo_sync.resolve(param) else {
+            return;
+        };
+
+        match param {
+            Param::Pitch(note_number, pitch_param) => pitch_envelope.set_pitch(note_number, pitch_param),
+            Param::PitchPoints(num_points) => pitch_envelope.set_num_points(num_points),
+            Param::PitchStartFrequency(start_frequency) => pitch_envelope.set_start_frequency(start_frequency),
+            Param::PitchEndMode(end_mode) => pitch_envelope.set_end_mode(end_mode),
+            Param::PitchReturnTime(return_time) => pitch_envelope.set_return_time(return_time),
+            Param::FineTune(fine_tune) => pitch_envelope.set_fine_tune(fine_tune),
+            Param::A4Frequency(a4_frequency) => pitch_envelope.set_a4_frequency(a4_frequency),
+            Param::Length(length) => pitch_envelope.set_length(length),
+            Param::LengthKeepAttack(keep_attack) => pitch_envelope.set_keep_attack(keep_attack),
+            _ => {}
+        }
+    });
+    pitch_envelope.set_note_frequencies(param_store.get_note_frequencies());
+
+    pitch_envelope
+}
+
+// The drawn curve when it replaces the envelope, like in KickSynth
+fn curve_from_params(param_store: &ParamStore, target: CurveTarget, sample_rate: usize) -> Option<CurveEnvelope> {
+    let (source_id, logarithmic) = match target {
+        CurveTarget::Amp => ("amp_source", false),
+        CurveTarget::Pitch => ("pitch_source", true),
+    };
+
+    if param_store.get(source_id) != Some(1.0) {
+        return None;
+    }
+
+    let mut curve_envelope = CurveEnvelope::new(sample_rate, logarithmic);
+    curve_envelope.set_points(&param_store.get_curve_store(target).get());
+    Some(curve_envelope).filter(CurveEnvelope::has_points)
+}
+
+pub(crate) fn render_amp_curve(param_store: &ParamStore, sample_rate: usize, output: &mut [f32], samples_per_point: usize) {
+    match curve_from_params(param_store, CurveTarget::Amp, sample_rate) {
+        Some(curve_envelope) => curve_envelope.render_curve(output, samples_per_point),
+        None => amp_envelope_from_params(param_store, sample_rate).render_curve(output, samples_per_point),
+    }
+}
+
+pub(crate) fn render_pitch_curve(param_store: &ParamStore, sample_rate: usize, output: &mut [f32], samples_per_point: usize) {
+    match curve_from_params(param_store, CurveTarget::Pitch, sample_rate) {
+        Some(curve_envelope) => curve_envelope.render_curve(output, samples_per_point),
+        None => pitch_envelope_from_params(param_store, sample_rate).render_curve(output, samples_per_point),
+    }
+}
+
+/// Renders the amplitude of the next note into `output`, one point every `samples_per_point` samples.
+///
+/// # Safety
+/// `output` must point to at least `num_points` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn render_amp_envelope(
+    param_store: &ParamStore,
+    sample_rate: usize,
+    output: *mut f32,
+    num_points: usize,
+    samples_per_point: usize,
+) {
+    let output = std::slice::from_raw_parts_mut(output, num_points);
+    render_amp_curve(param_store, sample_rate, output, samples_per_point);
+}
+
+/// Renders the oscillator frequency in Hz of the next note into `output`, one point every `samples_per_point` samples.
+///
+/// # Safety
+/// `output` must point to at least `num_points` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn render_pitch_envelope(
+    param_store: &ParamStore,
+    sample_rate: usize,
+    output: *mut f32,
+    num_points: usize,
+    samples_per_point: usize,
+) {
+    let output = std::slice::from_raw_parts_mut(output, num_points);
+    render_pitch_curve(param_store, sample_rate, output, samples_per_point);
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::curve_envelope::{deserialize_curve, CurveTarget};
+    use crate::curve_render::{amp_envelope_from_params, pitch_envelope_from_params, render_amp_curve};
+    use crate::param_store::ParamStore;
+
+    #[test]
+    fn test_amp_envelope_curve() {
+        let param_store = ParamStore::new();
+        param_store.set("amp_attack", 10.0);
+        param_store.set("amp_decay", 10.0);
+        param_store.set("amp_sustain", 50.0);
+        param_store.set("amp_release", 100.0);
+        param_store.set("amp_exponential_factor_a", 2.0);
+
+        let mut curve = [0.0_f32; 130];
+        amp_envelope_from_params(&param_store, 1000).render_curve(&mut curve, 1);
+
+        assert_approx_eq!(curve[5], 0.5);
+        assert_approx_eq!(curve[15], 0.75);
+        assert_approx_eq!(curve[20], 0.5);
+        // halfway through the release, bent by exponential_factor_a
+        assert_approx_eq!(curve[70], 0.5 * 0.5_f32.powf(2.0));
+        assert_eq!(curve[125], 0.0);
+    }
+
+    #[test]
+    fn test_samples_per_point() {
+        let param_store = ParamStore::new();
+        param_store.set("amp_attack", 10.0);
+        param_store.set("amp_release", 100.0);
+
+        let mut curve = [0.0_f32; 4];
+        amp_envelope_from_params(&param_store, 1000).render_curve(&mut curve, 2);
+
+        assert_approx_eq!(curve[1], 0.2);
+        assert_approx_eq!(curve[3], 0.6);
+    }
+
+    #[test]
+    fn test_pitch_envelope_curve_matches_get_frequency() {
+        let param_store = ParamStore::new();
+        param_store.set("octave_1", 8.0);
+        param_store.set("timing_1", 2.0);
+        param_store.set("octave_2", 4.0);
+        param_store.set("timing_2", 20.0);
+
+        let mut pitch_envelope = pitch_envelope_from_params(&param_store, 1000);
+
+        let mut curve = [0.0_f32; 40];
+        pitch_envelope.render_curve(&mut curve, 1);
+
+        pitch_envelope.note_on();
+        for point in curve {
+            assert_eq!(point, pitch_envelope.get_frequency());
+        }
+    }
+
+    #[test]
+    fn test_amp_curve_replaces_envelope() {
+        let param_store = ParamStore::new();
+        param_store.set("amp_attack", 10.0);
+        param_store.set("amp_release", 100.0);
+        param_store.get_curve_store(CurveTarget::Amp).set(&deserialize_curve("0,1;10,0.8", CurveTarget::Amp).unwrap());
+
+        let mut curve = [0.0_f32; 2];
+        render_amp_curve(&param_store, 1000, &mut curve, 5);
+        assert_approx_eq!(curve[1], 0.5);
+
+        param_store.set("amp_source", 1.0);
+        render_amp_curve(&param_store, 1000, &mut curve, 5);
+        assert_approx_eq!(curve[0], 1.0);
+        assert_approx_eq!(curve[1], 0.9);
+    }
+
+    #[test]
+    fn test_synced_release_follows_tempo() {
+        let param_store = ParamStore::new();
+        param_store.set("amp_attack", 1.0);
+        param_store.set("amp_sustain", 100.0);
+        param_store.set("amp_exponential_factor_a", 1.0);
+        param_store.set("amp_release", 1000.0);
+        // a sixteenth, 125 ms at 120 bpm
+        param_store.set("amp_release_sync", 4.0);
+
+        let mut curve = [0.0_f32; 2];
+        amp_envelope_from_params(&param_store, 1000).render_curve(&mut curve, 63);
+        assert_approx_eq!(curve[1], 0.5, 0.01);
+
+        param_store.set_tempo(60.0);
+        amp_envelope_from_params(&param_store, 1000).render_curve(&mut curve, 126);
+        assert_approx_eq!(curve[1], 0.5, 0.01);
+    }
+}
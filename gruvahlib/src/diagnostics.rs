@@ -0,0 +1,253 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::KickSynth;
+
+const DIAGNOSTICS_LOG_SIZE: usize = 256;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticEventKind {
+    MidiMessageQueued,
+    MidiMessageDropped,
+    Clip,
+    Allocation,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DiagnosticEvent {
+    pub kind: DiagnosticEventKind,
+    // sample offset within the block for clips, midi timestamp for midi events
+    pub timestamp: i32,
+    // clipped sample value for clips, note pitch for midi events, number of allocations for allocations
+    pub value: f32,
+}
+
+impl Default for DiagnosticEvent {
+    fn default() -> Self {
+        Self {
+            kind: DiagnosticEventKind::MidiMessageQueued,
+            timestamp: 0,
+            value: 0.0,
+        }
+    }
+}
+
+// Single producer (the audio thread), single consumer (whoever drains the log) ring buffer.
+// Nothing in here locks or allocates, so the audio thread can log without worrying about it.
+pub struct Diagnostics {
+    log: UnsafeCell<[DiagnosticEvent; DIAGNOSTICS_LOG_SIZE]>,
+    write_index: AtomicUsize,
+    read_index: AtomicUsize,
+    dropped_midi_messages: AtomicU64,
+    dropped_log_events: AtomicU64,
+    clip_events: AtomicU64,
+    allocation_events: AtomicU64,
+}
+
+// The log is only written by the producer at write_index and only read by the consumer at read_index,
+// the atomic indices make sure these never touch the same slot at the same time
+unsafe impl Sync for Diagnostics {}
+
+impl Diagnostics {
+    pub(crate) fn new() -> Self {
+        Self {
+            log: UnsafeCell::new([DiagnosticEvent::default(); DIAGNOSTICS_LOG_SIZE]),
+            write_index: AtomicUsize::new(0),
+            read_index: AtomicUsize::new(0),
+            dropped_midi_messages: AtomicU64::new(0),
+            dropped_log_events: AtomicU64::new(0),
+            clip_events: AtomicU64::new(0),
+            allocation_events: AtomicU64::new(0),
+        }
+    }
+}
+
+// Producer side, only to be called from the audio thread
+impl Diagnostics {
+    fn push(&self, event: DiagnosticEvent) {
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        let read_index = self.read_index.load(Ordering::Acquire);
+
+        if write_index.wrapping_sub(read_index) >= DIAGNOSTICS_LOG_SIZE {
+            // nobody is draining the log, rather lose the event than block the audio thread
+            self.dropped_log_events.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        unsafe {
+            (*self.log.get())[write_index % DIAGNOSTICS_LOG_SIZE] = event;
+        }
+
+        self.write_index.store(write_index.wrapping_add(1), Ordering::Release);
+    }
+
+    pub(crate) fn midi_message_queued(&self, timestamp: i32, note_pitch: u8) {
+        self.push(DiagnosticEvent {
+            kind: DiagnosticEventKind::MidiMessageQueued,
+            timestamp,
+            value: note_pitch as f32,
+        });
+    }
+
+    pub(crate) fn midi_message_dropped(&self, timestamp: i32, note_pitch: u8) {
+        self.dropped_midi_messages.fetch_add(1, Ordering::Relaxed);
+        self.push(DiagnosticEvent {
+            kind: DiagnosticEventKind::MidiMessageDropped,
+            timestamp,
+            value: note_pitch as f32,
+        });
+    }
+
+    pub(crate) fn clip(&self, sample_index: i32, value: f32) {
+        self.clip_events.fetch_add(1, Ordering::Relaxed);
+        self.push(DiagnosticEvent {
+            kind: DiagnosticEventKind::Clip,
+            timestamp: sample_index,
+            value,
+        });
+    }
+
+    // Only logged with the alloc_guard feature, see alloc_guard.rs
+    pub(crate) fn allocation(&self, allocations: u32) {
+        self.allocation_events.fetch_add(1, Ordering::Relaxed);
+        self.push(DiagnosticEvent {
+            kind: DiagnosticEventKind::Allocation,
+            timestamp: 0,
+            value: allocations as f32,
+        });
+    }
+}
+
+// Consumer side, to be called from a non real-time thread
+impl Diagnostics {
+    pub(crate) fn drain(&self, mut callback: impl FnMut(&DiagnosticEvent)) -> usize {
+        let read_index = self.read_index.load(Ordering::Relaxed);
+        let write_index = self.write_index.load(Ordering::Acquire);
+        let available = write_index.wrapping_sub(read_index);
+
+        for i in 0..available {
+            let event = unsafe { (*self.log.get())[read_index.wrapping_add(i) % DIAGNOSTICS_LOG_SIZE] };
+            callback(&event);
+        }
+
+        self.read_index.store(write_index, Ordering::Release);
+        available
+    }
+
+    pub(crate) fn get_dropped_midi_messages(&self) -> u64 {
+        self.dropped_midi_messages.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn get_dropped_log_events(&self) -> u64 {
+        self.dropped_log_events.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn get_clip_events(&self) -> u64 {
+        self.clip_events.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn get_allocation_events(&self) -> u64 {
+        self.allocation_events.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns a handle to the diagnostics of the kick synth, which stays valid until `destroy_diagnostics` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+#[no_mangle]
+pub extern "C" fn create_diagnostics(kick_synth: &KickSynth) -> *const Diagnostics {
+    Arc::into_raw(kick_synth.get_diagnostics())
+}
+
+/// # Safety
+/// `diagnostics` must be a handle returned by `create_diagnostics`, it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn destroy_diagnostics(diagnostics: *const Diagnostics) {
+    assert!(!diagnostics.is_null());
+    drop(Arc::from_raw(diagnostics));
+}
+
+/// Calls `callback` for every logged event since the last drain and returns the number of events.
+/// Only one thread may drain at a time, never call this from the audio thread.
+#[no_mangle]
+pub extern "C" fn drain_diagnostics(
+    diagnostics: &Diagnostics,
+    callback: extern "C" fn(event: &DiagnosticEvent, user_data: *mut c_void),
+    user_data: *mut c_void,
+) -> usize {
+    diagnostics.drain(|event| callback(event, user_data))
+}
+
+#[no_mangle]
+pub extern "C" fn diagnostics_dropped_midi_messages(diagnostics: &Diagnostics) -> u64 {
+    diagnostics.get_dropped_midi_messages()
+}
+
+#[no_mangle]
+pub extern "C" fn diagnostics_dropped_log_events(diagnostics: &Diagnostics) -> u64 {
+    diagnostics.get_dropped_log_events()
+}
+
+#[no_mangle]
+pub extern "C" fn diagnostics_clip_events(diagnostics: &Diagnostics) -> u64 {
+    diagnostics.get_clip_events()
+}
+
+/// The number of blocks that allocated, always 0 unless gruvahlib is built with the alloc_guard feature
+#[no_mangle]
+pub extern "C" fn diagnostics_allocation_events(diagnostics: &Diagnostics) -> u64 {
+    diagnostics.get_allocation_events()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostics::{DiagnosticEventKind, Diagnostics, DIAGNOSTICS_LOG_SIZE};
+
+    #[test]
+    fn test_drain_in_order() {
+        let diagnostics = Diagnostics::new();
+
+        diagnostics.midi_message_queued(3, 60);
+        diagnostics.clip(10, 1.6);
+
+        let mut events = vec![];
+        let drained = diagnostics.drain(|event| events.push(*event));
+
+        assert_eq!(drained, 2);
+        assert_eq!(events[0].kind, DiagnosticEventKind::MidiMessageQueued);
+        assert_eq!(events[0].timestamp, 3);
+        assert_eq!(events[1].kind, DiagnosticEventKind::Clip);
+        assert_eq!(events[1].value, 1.6);
+        assert_eq!(diagnostics.get_clip_events(), 1);
+
+        assert_eq!(diagnostics.drain(|_| {}), 0);
+    }
+
+    #[test]
+    fn test_full_log_drops_events() {
+        let diagnostics = Diagnostics::new();
+
+        for i in 0..DIAGNOSTICS_LOG_SIZE + 10 {
+            diagnostics.midi_message_dropped(i as i32, 36);
+        }
+
+        assert_eq!(diagnostics.get_dropped_midi_messages(), DIAGNOSTICS_LOG_SIZE as u64 + 10);
+        assert_eq!(diagnostics.get_dropped_log_events(), 10);
+        assert_eq!(diagnostics.drain(|_| {}), DIAGNOSTICS_LOG_SIZE);
+    }
+}
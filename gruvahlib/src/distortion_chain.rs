@@ -0,0 +1,238 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::PI;
+
+use crate::saturation::{SaturationType, Waveshaper};
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::utils::db_to_linear;
+
+pub(crate) const MAX_DISTORTION_STAGES: usize = 4;
+
+pub enum DistortionParam {
+    Type(SaturationType),
+    Drive(f32),
+    Emphasis(f32),
+    EmphasisFrequency(f32),
+    Output(f32),
+    Mix(f32),
+}
+
+// A first order shelf that raises everything above its frequency by a gain, as a one pole lowpass
+// that splits the signal in a low and a high part. The coefficient is the prewarped cutoff.
+#[derive(Clone, Copy)]
+struct Shelf {
+    coefficient: f32,
+    high_gain: f32,
+    state: f32,
+}
+
+impl Shelf {
+    fn new() -> Self {
+        Self {
+            coefficient: 0.0,
+            high_gain: 1.0,
+            state: 0.0,
+        }
+    }
+
+    fn process_sample(&mut self, input: f32) -> f32 {
+        let v = (input - self.state) * self.coefficient / (1.0 + self.coefficient);
+        let low = v + self.state;
+        self.state = low + v;
+
+        low + (input - low) * self.high_gain
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DistortionStage {
+    waveshaper: Waveshaper,
+    drive: TargetCurrentPair<f32>,
+    // in dB, how much the highs are raised before the waveshaper and lowered again after it
+    emphasis: f32,
+    emphasis_frequency: f32,
+    pre_emphasis: Shelf,
+    de_emphasis: Shelf,
+    output_gain: TargetCurrentPair<f32>,
+    mix: TargetCurrentPair<f32>,
+}
+
+impl DistortionStage {
+    fn new(sample_rate: usize) -> Self {
+        let smoothed = |value| TargetCurrentPair::new(value).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear);
+
+        Self {
+            waveshaper: Waveshaper::new(SaturationType::Soft),
+            drive: smoothed(1.0),
+            emphasis: 0.0,
+            emphasis_frequency: 1000.0,
+            pre_emphasis: Shelf::new(),
+            de_emphasis: Shelf::new(),
+            output_gain: smoothed(1.0),
+            mix: smoothed(1.0),
+        }
+    }
+
+    fn process_sample(&mut self, input: f32) -> f32 {
+        let emphasized = self.pre_emphasis.process_sample(input);
+        let driven = emphasized * self.waveshaper.get_drive_gain(self.drive.get());
+        let saturated = self.waveshaper.saturate(driven);
+        let wet = self.de_emphasis.process_sample(saturated) * self.output_gain.get();
+
+        input + (wet - input) * self.mix.get()
+    }
+
+    // The de-emphasis shelf has its cutoff lower by the gain, which makes it the exact inverse of the pre-emphasis
+    fn update_emphasis(&mut self, sample_rate: f32) {
+        let coefficient = (PI * self.emphasis_frequency.min(sample_rate * 0.49) / sample_rate).tan();
+        let gain = db_to_linear(self.emphasis);
+
+        self.pre_emphasis.coefficient = coefficient;
+        self.pre_emphasis.high_gain = gain;
+        self.de_emphasis.coefficient = coefficient / gain;
+        self.de_emphasis.high_gain = 1.0 / gain;
+    }
+
+    fn update_smoothing(&mut self) {
+        self.drive.update_to_target();
+        self.output_gain.update_to_target();
+        self.mix.update_to_target();
+    }
+
+    fn settle(&mut self) {
+        self.drive.settle();
+        self.output_gain.settle();
+        self.mix.settle();
+    }
+}
+
+// Serial distortion stages after the saturation, each with its own waveshaper, drive, emphasis, output gain and mix.
+// The chain runs inside the oversampling of the saturation, at the oversampled rate.
+pub(crate) struct DistortionChain {
+    stages: [DistortionStage; MAX_DISTORTION_STAGES],
+    num_stages: usize,
+    sample_rate: f32,
+}
+
+impl DistortionChain {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let mut distortion_chain = Self {
+            stages: [DistortionStage::new(sample_rate); MAX_DISTORTION_STAGES],
+            num_stages: 0,
+            sample_rate: sample_rate as f32,
+        };
+        distortion_chain.set_sample_rate(sample_rate as f32);
+        distortion_chain
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        self.stages[..self.num_stages].iter_mut().fold(input, |x, stage| stage.process_sample(x))
+    }
+
+    // Once per sample at the base sample rate, before the oversampled samples are processed
+    pub(crate) fn update_smoothing(&mut self) {
+        for stage in self.stages[..self.num_stages].iter_mut() {
+            stage.update_smoothing();
+        }
+    }
+
+    pub(crate) fn settle(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.settle();
+        }
+    }
+
+    // The oversampled rate the chain runs at
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+
+        for stage in self.stages.iter_mut() {
+            stage.update_emphasis(sample_rate);
+        }
+    }
+
+    pub(crate) fn get_waveshapers_mut(&mut self) -> impl Iterator<Item = &mut Waveshaper> {
+        self.stages.iter_mut().map(|stage| &mut stage.waveshaper)
+    }
+
+    pub(crate) fn set_num_stages(&mut self, num_stages: usize) {
+        self.num_stages = num_stages.min(MAX_DISTORTION_STAGES);
+    }
+
+    pub(crate) fn set_stage(&mut self, stage_number: usize, distortion_param: DistortionParam) {
+        let sample_rate = self.sample_rate;
+        let stage = &mut self.stages[stage_number];
+
+        match distortion_param {
+            DistortionParam::Type(saturation_type) => stage.waveshaper.set_saturation_type(saturation_type),
+            DistortionParam::Drive(drive) => stage.drive.set_target(db_to_linear(drive)),
+            DistortionParam::Emphasis(emphasis) => {
+                stage.emphasis = emphasis;
+                stage.update_emphasis(sample_rate);
+            }
+            DistortionParam::EmphasisFrequency(emphasis_frequency) => {
+                stage.emphasis_frequency = emphasis_frequency.max(1.0);
+                stage.update_emphasis(sample_rate);
+            }
+            DistortionParam::Output(output) => stage.output_gain.set_target(db_to_linear(output)),
+            DistortionParam::Mix(mix) => stage.mix.set_target((mix / 100.0).clamp(0.0, 1.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+    use assert_approx_eq::assert_approx_eq;
+    use crate::distortion_chain::{DistortionChain, DistortionParam};
+    use crate::saturation::SaturationType;
+
+    #[test]
+    fn test_emphasis_cancels_out() {
+        let mut distortion_chain = DistortionChain::new(48000);
+        distortion_chain.set_num_stages(1);
+        distortion_chain.set_stage(0, DistortionParam::Type(SaturationType::None));
+        distortion_chain.set_stage(0, DistortionParam::Emphasis(12.0));
+        distortion_chain.set_stage(0, DistortionParam::EmphasisFrequency(500.0));
+
+        for n in 0..1000 {
+            let x = (2.0 * PI * 3000.0 * n as f32 / 48000.0).sin() + 0.3 * (2.0 * PI * 80.0 * n as f32 / 48000.0).sin();
+            assert_approx_eq!(distortion_chain.process_sample(x), x, 1.0e-4);
+        }
+    }
+
+    #[test]
+    fn test_stages_are_serial() {
+        let mut distortion_chain = DistortionChain::new(1000);
+        assert_eq!(distortion_chain.process_sample(2.0), 2.0);
+
+        distortion_chain.set_num_stages(2);
+        distortion_chain.set_stage(0, DistortionParam::Type(SaturationType::Clip));
+        distortion_chain.set_stage(0, DistortionParam::Output(-6.0206));
+        distortion_chain.set_stage(1, DistortionParam::Type(SaturationType::Clip));
+        distortion_chain.set_stage(1, DistortionParam::Drive(3.0));
+        // clipped to 1 and halved by the first stage, then driven by 3 dB in the second
+        assert_approx_eq!(distortion_chain.process_sample(2.0), 0.5 * 1.4125, 1.0e-3);
+        assert_approx_eq!(distortion_chain.process_sample(0.5), 0.25 * 1.4125, 1.0e-3);
+    }
+
+    #[test]
+    fn test_mix() {
+        let mut distortion_chain = DistortionChain::new(1000);
+        distortion_chain.set_num_stages(1);
+        distortion_chain.set_stage(0, DistortionParam::Type(SaturationType::Clip));
+        distortion_chain.set_stage(0, DistortionParam::Mix(25.0));
+
+        assert_approx_eq!(distortion_chain.process_sample(3.0), 0.75 * 3.0 + 0.25 * 1.0);
+    }
+}
@@ -10,7 +10,7 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::target_pair::TargetCurrentPair;
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EnvelopeState {
@@ -18,30 +18,119 @@ pub enum EnvelopeState {
     NoteOff,
 }
 
+// Hold, Sustain and Curve were added later, they come last so the values of the other stages stay the same over ffi
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvelopeStage {
+    Off,
+    Attack,
+    Decay,
+    Release,
+    Hold,
+    Sustain,
+    // following a drawn amp curve instead of the envelope
+    Curve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvelopeCurve {
+    // straight line in amplitude
+    Linear,
+    // straight line in dB, starting from or ending at EXPONENTIAL_FLOOR instead of silence
+    Exponential,
+    // bent by the tension, a tension above 1 moves most of the change to the start of the segment
+    Power,
+}
+
+// -60 dB, silence can't be reached in dB
+const EXPONENTIAL_FLOOR: f32 = 0.001;
+
+impl EnvelopeCurve {
+    // Level at fraction of the way from start_level to end_level
+    fn interpolate(&self, start_level: f32, end_level: f32, fraction: f32, tension: f32) -> f32 {
+        match self {
+            EnvelopeCurve::Linear => start_level + (end_level - start_level) * fraction,
+            EnvelopeCurve::Exponential => {
+                let start_level = start_level.max(EXPONENTIAL_FLOOR);
+                let end_level = end_level.max(EXPONENTIAL_FLOOR);
+                start_level * (end_level / start_level).powf(fraction)
+            }
+            EnvelopeCurve::Power => end_level + (start_level - end_level) * (1.0 - fraction).powf(tension),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct EnvelopeSegment {
+    stage: EnvelopeStage,
+    sample_timing: i32,
+    start_level: f32,
+    end_level: f32,
+    curve: EnvelopeCurve,
+    tension: f32,
+}
 
+// attack, hold, decay, sustain and release
+const NUM_SEGMENTS: usize = 5;
 
+#[derive(Clone)]
 pub(crate) struct Envelope {
     sample_rate: usize,
     current_sample: i32,
     attack_sample_timing: TargetCurrentPair<i32>,
+    hold_sample_timing: TargetCurrentPair<i32>,
     decay_sample_timing: TargetCurrentPair<i32>,
     sustain: TargetCurrentPair<f32>,
+    sustain_sample_timing: TargetCurrentPair<i32>,
+    // the tension of the release, named after the bounce of the first version of the envelope
     exponential_factor_a: TargetCurrentPair<f32>,
     release_sample_timing: TargetCurrentPair<i32>,
+    attack_curve: EnvelopeCurve,
+    attack_tension: TargetCurrentPair<f32>,
+    decay_curve: EnvelopeCurve,
+    decay_tension: TargetCurrentPair<f32>,
+    release_curve: EnvelopeCurve,
+    // the times as they were set, before the length macro scales them
+    attack_ms: f32,
+    hold_ms: f32,
+    decay_ms: f32,
+    sustain_time_ms: f32,
+    release_ms: f32,
+    length: f32,
+    keep_attack: bool,
     envelope_state: EnvelopeState,
 }
 
 
 impl Envelope {
     pub fn new(sample_rate: usize) -> Self {
+        // times move in a straight line, levels and tensions like a one pole filter
+        let timing = || TargetCurrentPair::default().with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear);
+        let smoothed = |value| TargetCurrentPair::new(value).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::OnePole);
+
         Envelope {
             sample_rate,
             current_sample: 0,
-            attack_sample_timing: Default::default(),
-            decay_sample_timing: Default::default(),
-            sustain: Default::default(),
-            exponential_factor_a: Default::default(),
-            release_sample_timing: Default::default(),
+            attack_sample_timing: timing(),
+            hold_sample_timing: timing(),
+            decay_sample_timing: timing(),
+            sustain: smoothed(0.0),
+            sustain_sample_timing: timing(),
+            exponential_factor_a: smoothed(0.0),
+            release_sample_timing: timing(),
+            // the curves of the envelope before every segment had its own
+            attack_curve: EnvelopeCurve::Linear,
+            attack_tension: smoothed(1.0),
+            decay_curve: EnvelopeCurve::Linear,
+            decay_tension: smoothed(1.0),
+            release_curve: EnvelopeCurve::Power,
+            attack_ms: 0.0,
+            hold_ms: 0.0,
+            decay_ms: 0.0,
+            sustain_time_ms: 0.0,
+            release_ms: 0.0,
+            length: 1.0,
+            keep_attack: false,
             envelope_state: EnvelopeState::NoteOff,
         }
     }
@@ -57,26 +146,91 @@ impl Envelope {
         output
     }
 
+    // for the flat segments, which can't click and are skipped at 0
+    fn convert_ms_to_samples_or_zero(&self, ms: f32) -> i32 {
+        (ms.max(0.0) * (1.0 / 1000.0) * self.sample_rate as f32) as i32
+    }
+
+    // The attack and hold are the transient of the kick, keep_attack leaves them at their own times
+    fn get_attack_length(&self) -> f32 {
+        match self.keep_attack {
+            true => 1.0,
+            false => self.length,
+        }
+    }
+
     pub fn set_attack(&mut self, attack_ms: f32) {
-        self.attack_sample_timing.set_target(self.convert_ms_to_samples(attack_ms));
+        self.attack_ms = attack_ms;
+        self.attack_sample_timing.set_target(self.convert_ms_to_samples(attack_ms * self.get_attack_length()));
+    }
+
+    pub fn set_hold(&mut self, hold_ms: f32) {
+        self.hold_ms = hold_ms;
+        self.hold_sample_timing.set_target(self.convert_ms_to_samples_or_zero(hold_ms * self.get_attack_length()));
     }
 
     pub fn set_decay(&mut self, decay_ms: f32) {
-        self.decay_sample_timing.set_target(self.convert_ms_to_samples(decay_ms));
+        self.decay_ms = decay_ms;
+        self.decay_sample_timing.set_target(self.convert_ms_to_samples(decay_ms * self.length));
     }
 
     pub fn set_sustain(&mut self, sustain: f32) {
         self.sustain.set_target(sustain / 100.0);
     }
 
+    pub fn set_sustain_time(&mut self, sustain_time_ms: f32) {
+        self.sustain_time_ms = sustain_time_ms;
+        self.sustain_sample_timing.set_target(self.convert_ms_to_samples_or_zero(sustain_time_ms * self.length));
+    }
+
     pub fn set_release(&mut self, release_ms: f32) {
-        self.release_sample_timing.set_target(self.convert_ms_to_samples(release_ms));
+        self.release_ms = release_ms;
+        self.release_sample_timing.set_target(self.convert_ms_to_samples(release_ms * self.length));
+    }
+
+    // Scales every time by length, the times that were set stay as they are
+    pub fn set_length(&mut self, length: f32) {
+        self.length = length.max(0.01);
+        self.update_times();
+    }
+
+    pub fn set_keep_attack(&mut self, keep_attack: bool) {
+        self.keep_attack = keep_attack;
+        self.update_times();
+    }
+
+    fn update_times(&mut self) {
+        self.set_attack(self.attack_ms);
+        self.set_hold(self.hold_ms);
+        self.set_decay(self.decay_ms);
+        self.set_sustain_time(self.sustain_time_ms);
+        self.set_release(self.release_ms);
     }
 
     pub fn set_exponential_factor_a(&mut self, exponential_factor_a: f32) {
         self.exponential_factor_a.set_target(exponential_factor_a);
     }
 
+    pub fn set_attack_curve(&mut self, curve: EnvelopeCurve) {
+        self.attack_curve = curve;
+    }
+
+    pub fn set_attack_tension(&mut self, tension: f32) {
+        self.attack_tension.set_target(tension.max(0.01));
+    }
+
+    pub fn set_decay_curve(&mut self, curve: EnvelopeCurve) {
+        self.decay_curve = curve;
+    }
+
+    pub fn set_decay_tension(&mut self, tension: f32) {
+        self.decay_tension.set_target(tension.max(0.01));
+    }
+
+    pub fn set_release_curve(&mut self, curve: EnvelopeCurve) {
+        self.release_curve = curve;
+    }
+
     pub fn note_on(&mut self) {
         self.current_sample = 0;
         self.envelope_state = EnvelopeState::NoteOn;
@@ -86,13 +240,80 @@ impl Envelope {
         // ignore this
     }
 
+    fn get_segments(&self) -> [EnvelopeSegment; NUM_SEGMENTS] {
+        let sustain = self.sustain.get();
+        let flat = |stage, sample_timing: &TargetCurrentPair<i32>, level| EnvelopeSegment {
+            stage,
+            sample_timing: sample_timing.get(),
+            start_level: level,
+            end_level: level,
+            curve: EnvelopeCurve::Linear,
+            tension: 1.0,
+        };
+
+        [
+            EnvelopeSegment {
+                stage: EnvelopeStage::Attack,
+                sample_timing: self.attack_sample_timing.get(),
+                start_level: 0.0,
+                end_level: 1.0,
+                curve: self.attack_curve,
+                tension: self.attack_tension.get(),
+            },
+            flat(EnvelopeStage::Hold, &self.hold_sample_timing, 1.0),
+            EnvelopeSegment {
+                stage: EnvelopeStage::Decay,
+                sample_timing: self.decay_sample_timing.get(),
+                start_level: 1.0,
+                end_level: sustain,
+                curve: self.decay_curve,
+                tension: self.decay_tension.get(),
+            },
+            flat(EnvelopeStage::Sustain, &self.sustain_sample_timing, sustain),
+            EnvelopeSegment {
+                stage: EnvelopeStage::Release,
+                sample_timing: self.release_sample_timing.get(),
+                start_level: sustain,
+                end_level: 0.0,
+                curve: self.release_curve,
+                tension: self.exponential_factor_a.get(),
+            },
+        ]
+    }
+
+    // The segment the current sample is in, and how many samples into it, None once the release is over
+    fn get_current_segment(&self) -> Option<(EnvelopeSegment, i32)> {
+        let mut segment_start = 0;
+
+        for segment in self.get_segments() {
+            if self.current_sample < segment_start + segment.sample_timing {
+                return Some((segment, self.current_sample - segment_start));
+            }
+
+            segment_start += segment.sample_timing;
+        }
+
+        None
+    }
+
+    pub(crate) fn get_stage(&self) -> EnvelopeStage {
+        if self.envelope_state == EnvelopeState::NoteOff {
+            return EnvelopeStage::Off;
+        }
+
+        match self.get_current_segment() {
+            Some((segment, _)) => segment.stage,
+            // the last sample of the release, the envelope switches off when it is processed
+            None => EnvelopeStage::Release,
+        }
+    }
+
     pub(crate) fn process_sample(&mut self) -> f32 {
         if self.envelope_state == EnvelopeState::NoteOff {
             return 0.0
         }
 
         let output = self.get_output();
-        self.update_to_target();
 
         self.current_sample += 1;
 
@@ -104,59 +325,186 @@ impl Envelope {
     }
 
     fn get_output(&mut self) -> f32 {
-        let output= match self.current_sample {
-            sample if sample < self.attack_sample_timing.get() => {
-                let attack_sample = self.attack_sample_timing.get() as f32;
+        let Some((segment, current_sample_delta)) = self.get_current_segment() else {
+            self.envelope_state = EnvelopeState::NoteOff;
+            return 0.0;
+        };
 
-                if attack_sample == 0.0 {
-                    return 1.0
-                }
+        let fraction = current_sample_delta as f32 / segment.sample_timing as f32;
+        segment.curve.interpolate(segment.start_level, segment.end_level, fraction, segment.tension)
+    }
 
-                let current_sample = self.current_sample as f32;
-                current_sample / attack_sample
-            },
-            sample if sample < self.attack_sample_timing.get() + self.decay_sample_timing.get() => {
-                let current_sample_delta = self.current_sample - self.attack_sample_timing.get();
+    // Renders the curve the next note will get once all parameters reached their targets,
+    // using the same process_sample as the audio thread. Output points are samples_per_point samples apart.
+    pub(crate) fn render_curve(&self, output: &mut [f32], samples_per_point: usize) {
+        let mut envelope = self.clone();
+        envelope.settle();
+        envelope.note_on();
 
-                if self.decay_sample_timing.get() == 0 {
-                    return self.sustain.get()
-                }
+        for x in output.iter_mut() {
+            *x = envelope.process_sample();
 
-                let decay_ratio = 1.0 - current_sample_delta as f32 / self.decay_sample_timing.get() as f32;
-                let output = self.sustain.get() + (1.0 - self.sustain.get()) * decay_ratio;
-                output
-            },
-            sample if sample < self.attack_sample_timing.get() + self.decay_sample_timing.get() + self.release_sample_timing.get() => {
-                let current_sample_delta = self.current_sample - self.attack_sample_timing.get() - self.decay_sample_timing.get();
-
-                if self.release_sample_timing.get() == 0 {
-                    return 0.0
-                }
-
-                let release_ratio = 1.0 - current_sample_delta as f32 / self.release_sample_timing.get() as f32;
-                let release_ratio = release_ratio.powf(self.exponential_factor_a.get());
-                // println!("release_ratio: {}", release_ratio);
-                let output = self.sustain.get() * release_ratio;
-                output
-            }
-            _ =>  {
-                self.envelope_state = EnvelopeState::NoteOff;
-                0.0
+            for _ in 1..samples_per_point {
+                envelope.process_sample();
             }
-        };
+        }
+    }
 
-        // if self.current_sample % 100 == 0 {
-        //     println!("attack: {}, decay: {}, sustain: {}, release: {}, current_sample: {}, output: {}", self.attack_sample_timing, self.decay_sample_timing, self.sustain, self.release_sample_timing, self.current_sample, output);
-        // }
+    pub(crate) fn settle(&mut self) {
+        self.attack_sample_timing.settle();
+        self.hold_sample_timing.settle();
+        self.decay_sample_timing.settle();
+        self.sustain.settle();
+        self.sustain_sample_timing.settle();
+        self.release_sample_timing.settle();
+        self.exponential_factor_a.settle();
+        self.attack_tension.settle();
+        self.decay_tension.settle();
+    }
 
-        output
+    pub(crate) fn is_smoothing(&self) -> bool {
+        self.attack_sample_timing.is_smoothing()
+            || self.hold_sample_timing.is_smoothing()
+            || self.decay_sample_timing.is_smoothing()
+            || self.sustain.is_smoothing()
+            || self.sustain_sample_timing.is_smoothing()
+            || self.release_sample_timing.is_smoothing()
+            || self.exponential_factor_a.is_smoothing()
+            || self.attack_tension.is_smoothing()
+            || self.decay_tension.is_smoothing()
+    }
+
+    // Once per block, whether a note is playing or not, so the next note starts from the new parameters
+    pub(crate) fn update_smoothing(&mut self, num_samples: usize) {
+        if !self.is_smoothing() {
+            return;
+        }
+
+        self.attack_sample_timing.update_to_target_by(num_samples);
+        self.hold_sample_timing.update_to_target_by(num_samples);
+        self.decay_sample_timing.update_to_target_by(num_samples);
+        self.sustain.update_to_target_by(num_samples);
+        self.sustain_sample_timing.update_to_target_by(num_samples);
+        self.release_sample_timing.update_to_target_by(num_samples);
+        self.exponential_factor_a.update_to_target_by(num_samples);
+        self.attack_tension.update_to_target_by(num_samples);
+        self.decay_tension.update_to_target_by(num_samples);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::envelope::{Envelope, EnvelopeCurve, EnvelopeStage};
+
+    fn get_settled_envelope() -> Envelope {
+        let mut envelope = Envelope::new(1000);
+        envelope.set_attack(10.0);
+        envelope.set_decay(10.0);
+        envelope.set_sustain(50.0);
+        envelope.set_release(10.0);
+        envelope.set_exponential_factor_a(2.0);
+        envelope
+    }
+
+    #[test]
+    fn test_hold_and_sustain_time() {
+        let mut envelope = get_settled_envelope();
+        envelope.set_hold(5.0);
+        envelope.set_sustain_time(20.0);
+
+        let mut curve = [0.0_f32; 60];
+        envelope.render_curve(&mut curve, 1);
+
+        assert_approx_eq!(curve[5], 0.5);
+        assert_eq!(curve[10], 1.0);
+        assert_eq!(curve[14], 1.0);
+        assert_approx_eq!(curve[20], 0.75);
+        assert_eq!(curve[25], 0.5);
+        assert_eq!(curve[44], 0.5);
+        assert_approx_eq!(curve[50], 0.5 * 0.5_f32.powf(2.0));
+        assert_eq!(curve[55], 0.0);
+    }
+
+    #[test]
+    fn test_segment_curves() {
+        let mut envelope = get_settled_envelope();
+        envelope.set_attack_curve(EnvelopeCurve::Power);
+        envelope.set_attack_tension(2.0);
+        envelope.set_decay_curve(EnvelopeCurve::Exponential);
+        envelope.set_release_curve(EnvelopeCurve::Linear);
+
+        let mut curve = [0.0_f32; 30];
+        envelope.render_curve(&mut curve, 1);
+
+        assert_approx_eq!(curve[5], 0.75);
+        // halfway between 0 and -6 dB
+        assert_approx_eq!(curve[15], 0.5_f32.sqrt());
+        assert_approx_eq!(curve[25], 0.25);
     }
 
-    fn update_to_target(&mut self) {
-        self.attack_sample_timing.update_to_target();
-        self.decay_sample_timing.update_to_target();
-        self.sustain.update_to_target();
-        self.release_sample_timing.update_to_target();
-        self.exponential_factor_a.update_to_target();
+    #[test]
+    fn test_length() {
+        let mut envelope = get_settled_envelope();
+        envelope.set_length(2.0);
+        envelope.settle();
+
+        let mut curve = [0.0_f32; 60];
+        envelope.render_curve(&mut curve, 1);
+        assert_approx_eq!(curve[10], 0.5);
+        assert_eq!(curve[20], 1.0);
+        assert_approx_eq!(curve[30], 0.75);
+
+        envelope.set_keep_attack(true);
+        envelope.settle();
+        envelope.render_curve(&mut curve, 1);
+        assert_eq!(curve[10], 1.0);
+        assert_approx_eq!(curve[20], 0.75);
+
+        // the times that were set are kept
+        envelope.set_length(1.0);
+        envelope.set_keep_attack(false);
+        envelope.settle();
+        envelope.render_curve(&mut curve, 1);
+        assert_approx_eq!(curve[15], 0.75);
+    }
+
+    #[test]
+    fn test_smoothing_without_note() {
+        let mut envelope = get_settled_envelope();
+        envelope.set_attack(20.0);
+        assert!(envelope.is_smoothing());
+        assert_eq!(envelope.attack_sample_timing.get(), 10);
+
+        // 20 ms of smoothing at 1000 Hz
+        envelope.update_smoothing(10);
+        assert_eq!(envelope.attack_sample_timing.get(), 15);
+        envelope.update_smoothing(10);
+        assert_eq!(envelope.attack_sample_timing.get(), 20);
+        assert!(!envelope.is_smoothing());
+    }
+
+    #[test]
+    fn test_stages() {
+        let mut envelope = get_settled_envelope();
+        envelope.set_hold(5.0);
+        envelope.set_sustain_time(5.0);
+        envelope.settle();
+
+        assert_eq!(envelope.get_stage(), EnvelopeStage::Off);
+        envelope.note_on();
+
+        let mut stages = vec![];
+        for _ in 0..41 {
+            stages.push(envelope.get_stage());
+            envelope.process_sample();
+        }
+
+        assert_eq!(stages[0], EnvelopeStage::Attack);
+        assert_eq!(stages[12], EnvelopeStage::Hold);
+        assert_eq!(stages[17], EnvelopeStage::Decay);
+        assert_eq!(stages[27], EnvelopeStage::Sustain);
+        assert_eq!(stages[32], EnvelopeStage::Release);
+        assert_eq!(envelope.get_stage(), EnvelopeStage::Off);
     }
 }
@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+
+// A low shelf, two peaks and a high shelf, in that order
+pub(crate) const NUM_EQ_BANDS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EqBandType {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+const EQ_BAND_TYPES: [EqBandType; NUM_EQ_BANDS] = [EqBandType::LowShelf, EqBandType::Peak, EqBandType::Peak, EqBandType::HighShelf];
+const EQ_BAND_FREQUENCIES: [f32; NUM_EQ_BANDS] = [80.0, 250.0, 2500.0, 8000.0];
+
+pub enum EqParam {
+    Frequency(f32),
+    Gain(f32),
+    Q(f32),
+}
+
+#[derive(Clone, Copy)]
+struct EqBand {
+    band_type: EqBandType,
+    biquad: Biquad,
+    frequency: TargetCurrentPair<f32>,
+    // in dB
+    gain: TargetCurrentPair<f32>,
+    q: TargetCurrentPair<f32>,
+}
+
+impl EqBand {
+    fn new(sample_rate: usize, band_type: EqBandType, frequency: f32) -> Self {
+        let smoothed = |value| TargetCurrentPair::new(value).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::OnePole);
+        let q = match band_type {
+            EqBandType::Peak => 1.0,
+            _ => BUTTERWORTH_Q,
+        };
+
+        let mut band = Self {
+            band_type,
+            biquad: Biquad::new(),
+            frequency: smoothed(frequency),
+            gain: smoothed(0.0),
+            q: smoothed(q),
+        };
+        band.update_coefficients(sample_rate as f32);
+        band
+    }
+
+    fn is_smoothing(&self) -> bool {
+        self.frequency.is_smoothing() || self.gain.is_smoothing() || self.q.is_smoothing()
+    }
+
+    fn update_coefficients(&mut self, sample_rate: f32) {
+        let (frequency, q, gain) = (self.frequency.get(), self.q.get().max(0.1), self.gain.get());
+
+        match self.band_type {
+            EqBandType::LowShelf => self.biquad.set_low_shelf(sample_rate, frequency, q, gain),
+            EqBandType::Peak => self.biquad.set_peak(sample_rate, frequency, q, gain),
+            EqBandType::HighShelf => self.biquad.set_high_shelf(sample_rate, frequency, q, gain),
+        }
+    }
+
+    fn update_smoothing(&mut self, sample_rate: f32) {
+        if !self.is_smoothing() {
+            return;
+        }
+
+        self.frequency.update_to_target();
+        self.gain.update_to_target();
+        self.q.update_to_target();
+        self.update_coefficients(sample_rate);
+    }
+
+    fn settle(&mut self, sample_rate: f32) {
+        self.frequency.settle();
+        self.gain.settle();
+        self.q.settle();
+        self.update_coefficients(sample_rate);
+    }
+}
+
+// The EQ at the end of the kick, after the saturation and the filter. With every gain at 0 dB and the
+// high pass off the kick passes unchanged. The coefficients are only computed again while a parameter moves.
+pub(crate) struct Eq {
+    sample_rate: f32,
+    bands: [EqBand; NUM_EQ_BANDS],
+    high_pass_enabled: bool,
+    high_pass: Biquad,
+    high_pass_frequency: f32,
+    high_pass_q: f32,
+}
+
+impl Eq {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let mut eq = Self {
+            sample_rate: sample_rate as f32,
+            bands: std::array::from_fn(|i| EqBand::new(sample_rate, EQ_BAND_TYPES[i], EQ_BAND_FREQUENCIES[i])),
+            high_pass_enabled: false,
+            high_pass: Biquad::new(),
+            high_pass_frequency: 30.0,
+            high_pass_q: BUTTERWORTH_Q,
+        };
+        eq.update_high_pass();
+        eq
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        let mut output = input;
+
+        if self.high_pass_enabled {
+            output = self.high_pass.process_sample(output);
+        }
+
+        let sample_rate = self.sample_rate;
+        for band in self.bands.iter_mut() {
+            band.update_smoothing(sample_rate);
+            output = band.biquad.process_sample(output);
+        }
+
+        output
+    }
+
+    pub(crate) fn settle(&mut self) {
+        let sample_rate = self.sample_rate;
+        self.bands.iter_mut().for_each(|band| band.settle(sample_rate));
+    }
+
+    fn update_high_pass(&mut self) {
+        self.high_pass.set_highpass(self.sample_rate, self.high_pass_frequency, self.high_pass_q.max(0.1));
+    }
+
+    pub(crate) fn set_band(&mut self, band_number: usize, eq_param: EqParam) {
+        let band = &mut self.bands[band_number];
+
+        match eq_param {
+            EqParam::Frequency(frequency) => band.frequency.set_target(frequency),
+            EqParam::Gain(gain) => band.gain.set_target(gain),
+            EqParam::Q(q) => band.q.set_target(q),
+        }
+
+        // the first value of a parameter is set straight away, without smoothing
+        if !band.is_smoothing() {
+            band.update_coefficients(self.sample_rate);
+        }
+    }
+
+    pub(crate) fn set_high_pass_enabled(&mut self, enabled: bool) {
+        // the state is from the last time it was on
+        if enabled && !self.high_pass_enabled {
+            self.high_pass.reset();
+        }
+        self.high_pass_enabled = enabled;
+    }
+
+    pub(crate) fn set_high_pass_frequency(&mut self, frequency: f32) {
+        self.high_pass_frequency = frequency;
+        self.update_high_pass();
+    }
+
+    pub(crate) fn set_high_pass_q(&mut self, q: f32) {
+        self.high_pass_q = q;
+        self.update_high_pass();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::eq::{Eq, EqParam};
+    use crate::test_utils::get_sine_gain;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn get_gain(eq: &mut Eq, frequency: f32) -> f32 {
+        get_sine_gain(|x| eq.process_sample(x), frequency, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn test_flat_by_default() {
+        let mut eq = Eq::new(SAMPLE_RATE as usize);
+
+        for frequency in [20.0, 80.0, 1000.0, 8000.0, 16000.0] {
+            assert_approx_eq!(get_gain(&mut eq, frequency), 1.0, 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn test_bands() {
+        let mut eq = Eq::new(SAMPLE_RATE as usize);
+        eq.set_band(0, EqParam::Gain(6.0206));
+        eq.set_band(1, EqParam::Frequency(500.0));
+        eq.set_band(1, EqParam::Gain(-6.0206));
+        eq.set_band(1, EqParam::Q(4.0));
+        eq.settle();
+
+        assert_approx_eq!(get_gain(&mut eq, 20.0), 2.0, 2.0e-2);
+        assert_approx_eq!(get_gain(&mut eq, 500.0), 0.5, 1.0e-2);
+        assert_approx_eq!(get_gain(&mut eq, 5000.0), 1.0, 1.0e-2);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        let mut eq = Eq::new(SAMPLE_RATE as usize);
+        eq.set_high_pass_enabled(true);
+        eq.set_high_pass_frequency(100.0);
+
+        assert!(get_gain(&mut eq, 10.0) < 0.02);
+        assert_approx_eq!(get_gain(&mut eq, 2000.0), 1.0, 1.0e-2);
+    }
+}
@@ -0,0 +1,355 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+use crate::envelope::Envelope;
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::tuning::Tuning;
+
+// the highest Q, at full resonance
+const MAX_Q: f32 = 20.0;
+// the note the cutoff is set for, key tracking moves it from there
+const KEY_TRACKING_ROOT_NOTE: u8 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Off,
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterPosition {
+    PreSaturation,
+    PostSaturation,
+}
+
+pub enum FilterParam {
+    Mode(FilterMode),
+    Position(FilterPosition),
+    Cutoff(f32),
+    Resonance(f32),
+    KeyTracking(f32),
+    EnvelopeAmount(f32),
+    Attack(f32),
+    Decay(f32),
+    Sustain(f32),
+    Release(f32),
+    ReleaseTension(f32),
+}
+
+// A state variable filter with the trapezoidal integrators of Andrew Simper, which stays stable
+// while the cutoff moves every sample
+#[derive(Clone, Copy)]
+struct Svf {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Svf {
+    fn new() -> Self {
+        Self {
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+        }
+    }
+
+    // Returns the low pass, the band pass and the high pass output, g is the prewarped cutoff and k is 1 / Q
+    fn process_sample(&mut self, input: f32, g: f32, k: f32) -> (f32, f32, f32) {
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        (v2, v1, input - k * v1 - v2)
+    }
+
+    fn reset(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+}
+
+// The filter with the envelope that moves its cutoff, the cutoff also follows the note with key tracking
+pub(crate) struct Filter {
+    sample_rate: f32,
+    mode: FilterMode,
+    position: FilterPosition,
+    svf: Svf,
+    cutoff: TargetCurrentPair<f32>,
+    // 1 / Q
+    damping: TargetCurrentPair<f32>,
+    // how far the cutoff follows the note, 1 moves it as far as the frequency of the note moves
+    key_tracking: f32,
+    // in octaves at the top of the envelope
+    envelope_amount: TargetCurrentPair<f32>,
+    envelope: Envelope,
+    // the key tracking of the note that plays, as a factor on the cutoff
+    note_factor: f32,
+    // the frequency of the note in the tuning over the frequency of the root note
+    note_ratio: f32,
+}
+
+impl Filter {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let smoothed = |value| TargetCurrentPair::new(value).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::OnePole);
+        let mut envelope = Envelope::new(sample_rate);
+        envelope.set_decay(200.0);
+        envelope.set_exponential_factor_a(1.0);
+
+        Self {
+            sample_rate: sample_rate as f32,
+            mode: FilterMode::Off,
+            position: FilterPosition::PostSaturation,
+            svf: Svf::new(),
+            cutoff: smoothed(20000.0),
+            damping: smoothed(1.0 / FRAC_1_SQRT_2),
+            key_tracking: 0.0,
+            envelope_amount: smoothed(0.0),
+            envelope,
+            note_factor: 1.0,
+            note_ratio: 1.0,
+        }
+    }
+
+    pub(crate) fn get_position(&self) -> FilterPosition {
+        self.position
+    }
+
+    pub(crate) fn get_envelope_mut(&mut self) -> &mut Envelope {
+        &mut self.envelope
+    }
+
+    pub(crate) fn note_on(&mut self, note: u8, tuning: &Tuning) {
+        self.note_ratio = tuning.get_frequency(note as f32) / tuning.get_frequency(KEY_TRACKING_ROOT_NOTE as f32);
+        self.update_note_factor();
+        self.envelope.note_on();
+    }
+
+    pub(crate) fn note_off(&mut self) {
+        self.envelope.note_off();
+    }
+
+    fn update_note_factor(&mut self) {
+        self.note_factor = self.note_ratio.powf(self.key_tracking);
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        self.cutoff.update_to_target();
+        self.damping.update_to_target();
+        self.envelope_amount.update_to_target();
+        // the envelope runs with the filter off too, so switching it on halfway through a note lines up
+        let envelope_value = self.envelope.process_sample();
+
+        if self.mode == FilterMode::Off {
+            return input;
+        }
+
+        let cutoff = self.cutoff.get() * self.note_factor * 2.0_f32.powf(self.envelope_amount.get() * envelope_value);
+        let g = (PI * cutoff.clamp(20.0, self.sample_rate * 0.49) / self.sample_rate).tan();
+        let k = self.damping.get();
+        let (low, band, high) = self.svf.process_sample(input, g, k);
+
+        match self.mode {
+            FilterMode::Off => input,
+            FilterMode::LowPass => low,
+            FilterMode::HighPass => high,
+            FilterMode::BandPass => band,
+            FilterMode::Notch => low + high,
+        }
+    }
+
+    // Once per block, like the amp envelope
+    pub(crate) fn update_smoothing(&mut self, num_samples: usize) {
+        self.envelope.update_smoothing(num_samples);
+    }
+
+    pub(crate) fn settle(&mut self) {
+        self.cutoff.settle();
+        self.damping.settle();
+        self.envelope_amount.settle();
+        self.envelope.settle();
+    }
+
+    pub(crate) fn set_param(&mut self, filter_param: FilterParam) {
+        match filter_param {
+            FilterParam::Mode(mode) => {
+                // the state of the filter that was off is from long ago
+                if self.mode == FilterMode::Off {
+                    self.svf.reset();
+                }
+                self.mode = mode;
+            }
+            FilterParam::Position(position) => self.position = position,
+            FilterParam::Cutoff(cutoff) => self.cutoff.set_target(cutoff),
+            // 0 is a Butterworth filter, 1 rings at the cutoff
+            FilterParam::Resonance(resonance) => {
+                let q = FRAC_1_SQRT_2 * (MAX_Q / FRAC_1_SQRT_2).powf(resonance.clamp(0.0, 1.0));
+                self.damping.set_target(1.0 / q);
+            }
+            FilterParam::KeyTracking(key_tracking) => {
+                self.key_tracking = key_tracking;
+                self.update_note_factor();
+            }
+            FilterParam::EnvelopeAmount(envelope_amount) => self.envelope_amount.set_target(envelope_amount),
+            FilterParam::Attack(attack) => self.envelope.set_attack(attack),
+            FilterParam::Decay(decay) => self.envelope.set_decay(decay),
+            FilterParam::Sustain(sustain) => self.envelope.set_sustain(sustain),
+            FilterParam::Release(release) => self.envelope.set_release(release),
+            // 1 is a straight line, above 1 most of the release is at its start
+            FilterParam::ReleaseTension(tension) => self.envelope.set_exponential_factor_a(tension.max(0.01)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_1_SQRT_2;
+    use assert_approx_eq::assert_approx_eq;
+    use crate::filter::{Filter, FilterMode, FilterParam};
+    use crate::scala::parse_tuning;
+    use crate::tuning::Tuning;
+    use crate::test_utils::get_sine_gain;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn get_gain(filter: &mut Filter, frequency: f32) -> f32 {
+        get_sine_gain(|x| filter.process_sample(x), frequency, SAMPLE_RATE)
+    }
+
+    fn get_filter(mode: FilterMode) -> Filter {
+        let mut filter = Filter::new(SAMPLE_RATE as usize);
+        filter.set_param(FilterParam::Mode(mode));
+        filter.set_param(FilterParam::Cutoff(1000.0));
+        filter.set_param(FilterParam::Resonance(0.0));
+        filter
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut off = get_filter(FilterMode::Off);
+        assert_approx_eq!(get_gain(&mut off, 8000.0), 1.0, 1.0e-3);
+
+        let mut low_pass = get_filter(FilterMode::LowPass);
+        assert_approx_eq!(get_gain(&mut low_pass, 1000.0), FRAC_1_SQRT_2, 1.0e-2);
+        assert_approx_eq!(get_gain(&mut low_pass, 50.0), 1.0, 1.0e-2);
+        assert!(get_gain(&mut low_pass, 16000.0) < 0.01);
+
+        let mut high_pass = get_filter(FilterMode::HighPass);
+        assert_approx_eq!(get_gain(&mut high_pass, 1000.0), FRAC_1_SQRT_2, 1.0e-2);
+        assert!(get_gain(&mut high_pass, 50.0) < 0.01);
+
+        let mut band_pass = get_filter(FilterMode::BandPass);
+        assert!(get_gain(&mut band_pass, 1000.0) > get_gain(&mut band_pass, 8000.0) * 4.0);
+
+        let mut notch = get_filter(FilterMode::Notch);
+        assert!(get_gain(&mut notch, 1000.0) < 0.01);
+        assert_approx_eq!(get_gain(&mut notch, 50.0), 1.0, 1.0e-2);
+    }
+
+    #[test]
+    fn test_resonance_peaks_at_cutoff() {
+        let mut filter = get_filter(FilterMode::LowPass);
+        filter.set_param(FilterParam::Resonance(1.0));
+        filter.settle();
+
+        assert_approx_eq!(get_gain(&mut filter, 1000.0), 20.0, 0.5);
+    }
+
+    #[test]
+    fn test_key_tracking() {
+        let mut filter = get_filter(FilterMode::LowPass);
+        filter.set_param(FilterParam::KeyTracking(1.0));
+        // an octave above the root note, so the cutoff is at 2 kHz
+        filter.note_on(72, &Tuning::new());
+
+        assert_approx_eq!(get_gain(&mut filter, 2000.0), FRAC_1_SQRT_2, 1.0e-2);
+    }
+
+    #[test]
+    fn test_key_tracking_follows_the_tuning() {
+        let mut tuning = Tuning::new();
+        tuning.set_note_frequencies(parse_tuning("Whole tones\n6\n200.0\n400.0\n600.0\n800.0\n1000.0\n2/1\n", None));
+
+        let mut filter = get_filter(FilterMode::LowPass);
+        filter.set_param(FilterParam::KeyTracking(1.0));
+        // 6 whole tones above the root note is an octave in this tuning
+        filter.note_on(66, &tuning);
+
+        assert_approx_eq!(get_gain(&mut filter, 2000.0), FRAC_1_SQRT_2, 1.0e-2);
+    }
+
+    #[test]
+    fn test_envelope_opens_the_filter() {
+        let mut filter = get_filter(FilterMode::LowPass);
+        filter.set_param(FilterParam::EnvelopeAmount(3.0));
+        filter.set_param(FilterParam::Decay(500.0));
+        filter.settle();
+        filter.note_on(60, &Tuning::new());
+
+        // the envelope starts at the top, so the cutoff starts 3 octaves up at 8 kHz
+        let mut impulse_response = [0.0; 4];
+        for (n, x) in impulse_response.iter_mut().enumerate() {
+            *x = filter.process_sample(if n == 0 { 1.0 } else { 0.0 });
+        }
+
+        let mut closed = get_filter(FilterMode::LowPass);
+        assert!(impulse_response[0] > closed.process_sample(1.0) * 10.0);
+    }
+
+    #[test]
+    fn test_full_sustain_keeps_the_filter_open() {
+        let mut filter = get_filter(FilterMode::LowPass);
+        filter.set_param(FilterParam::EnvelopeAmount(3.0));
+        filter.set_param(FilterParam::Decay(10.0));
+        filter.set_param(FilterParam::Sustain(100.0));
+        filter.set_param(FilterParam::Release(1000.0));
+        filter.settle();
+        filter.note_on(60, &Tuning::new());
+
+        // long after the decay the cutoff is still 3 octaves up
+        for _ in 0..SAMPLE_RATE as usize / 20 {
+            filter.process_sample(0.0);
+        }
+
+        let mut closed = get_filter(FilterMode::LowPass);
+        assert!(filter.process_sample(1.0) > closed.process_sample(1.0) * 10.0);
+    }
+
+    #[test]
+    fn test_release_tension() {
+        // halfway through the release
+        let get_level = |tension| {
+            let mut filter = get_filter(FilterMode::LowPass);
+            filter.set_param(FilterParam::Decay(10.0));
+            filter.set_param(FilterParam::Sustain(100.0));
+            filter.set_param(FilterParam::Release(100.0));
+            filter.set_param(FilterParam::ReleaseTension(tension));
+            filter.settle();
+
+            let envelope = filter.get_envelope_mut();
+            envelope.note_on();
+            (0..SAMPLE_RATE as usize * 6 / 100).fold(0.0, |_, _| envelope.process_sample())
+        };
+
+        assert_approx_eq!(get_level(1.0), 0.5, 1.0e-2);
+        assert_approx_eq!(get_level(4.0), 0.0625, 1.0e-2);
+    }
+}
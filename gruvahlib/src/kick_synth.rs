@@ -10,33 +10,144 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::sync::Arc;
 use crate::midi_message::{MidiAction, MidiMessage};
 use crate::{envelope,  oscillator, pitch_envelope, saturation};
+use crate::alloc_guard::AllocGuard;
+use crate::curve_envelope::{CurveEnvelope, CurveTarget, EnvelopeSource};
+use crate::diagnostics::Diagnostics;
+use crate::envelope::EnvelopeStage;
+use crate::eq::Eq;
+use crate::filter::{Filter, FilterPosition};
 use crate::midi_message_queue::MidiMessageQueue;
+use crate::param_store::ParamStore;
+use crate::output_stage::OutputStage;
 use crate::params::Param;
+use crate::subsonic::Subsonic;
+use crate::synth_state::{SynthState, SynthStateSnapshot};
+use crate::tempo_sync::{TempoSync, TIME_PARAM_COUNT};
+use crate::transient_shaper::TransientShaper;
 
 
 pub struct KickSynth {
     oscillator: oscillator::Oscillator,
     amp_envelope: envelope::Envelope,
     pitch_envelope: pitch_envelope::PitchEnvelope,
+    amp_curve: CurveEnvelope,
+    pitch_curve: CurveEnvelope,
+    amp_source: EnvelopeSource,
+    pitch_source: EnvelopeSource,
+    tempo_sync: TempoSync,
+    // quarter notes since the start of the song at the start of the block, as the host reports it
+    ppq_position: f64,
     midi_message_queue: MidiMessageQueue,
     saturation: saturation::Saturation,
+    filter: Filter,
+    eq: Eq,
+    subsonic: Subsonic,
+    output_stage: OutputStage,
+    transient_shaper: TransientShaper,
+    diagnostics: Arc<Diagnostics>,
+    param_store: Arc<ParamStore>,
+    synth_state: Arc<SynthState>,
 }
 
 impl KickSynth {
     pub(crate) fn queue_midi_message(&mut self, midi_message: &MidiMessage) {
-        println!("Queuing midi message: {:?}", midi_message);
-        self.midi_message_queue.add_midi_message(midi_message.clone());
+        let timestamp = midi_message.get_timestamp();
+        let note_pitch = midi_message.get_note_pitch();
+
+        if self.midi_message_queue.add_midi_message(*midi_message) {
+            self.diagnostics.midi_message_queued(timestamp, note_pitch);
+        } else {
+            self.diagnostics.midi_message_dropped(timestamp, note_pitch);
+        }
+    }
+
+    pub(crate) fn get_diagnostics(&self) -> Arc<Diagnostics> {
+        self.diagnostics.clone()
+    }
+
+    pub(crate) fn get_synth_state(&self) -> Arc<SynthState> {
+        self.synth_state.clone()
     }
 }
 
 impl KickSynth {
+    pub(crate) fn get_param_store(&self) -> Arc<ParamStore> {
+        self.param_store.clone()
+    }
+
+    pub(crate) fn set_param(&self, parameter_id: &str, new_value: f32) -> bool {
+        self.param_store.set(parameter_id, new_value)
+    }
+
+    fn apply_param_changes(&mut self) {
+        let param_store = self.param_store.clone();
+        param_store.apply_changes(|param| self.update_param(param));
+        param_store.apply_tempo_changes(|tempo| {
+            if !self.tempo_sync.set_tempo(tempo) {
+                return;
+            }
+
+            for index in 0..TIME_PARAM_COUNT {
+                if let Some(param) = self.tempo_sync.get_synced_param(index) {
+                    self.apply_param(param);
+                }
+            }
+        });
+        param_store.apply_tuning_changes(|note_frequencies| self.pitch_envelope.set_note_frequencies(note_frequencies));
+        param_store.get_curve_store(CurveTarget::Amp).apply_changes(|curve_points| self.amp_curve.set_points(curve_points));
+        param_store.get_curve_store(CurveTarget::Pitch).apply_changes(|curve_points| self.pitch_curve.set_points(curve_points));
+    }
+
+    // Applies the pending parameter changes and skips the smoothing towards them,
+    // for instances that render offline and have no previous sound to avoid clicks with
+    pub(crate) fn settle_params(&mut self) {
+        self.apply_param_changes();
+        self.amp_envelope.settle();
+        self.saturation.settle();
+        self.filter.settle();
+        self.eq.settle();
+        self.output_stage.settle();
+        self.transient_shaper.settle();
+    }
+
+    // Call before process_block, from the audio thread
+    pub(crate) fn set_transport(&mut self, tempo: f64, ppq_position: f64) {
+        self.param_store.set_tempo(tempo as f32);
+        self.ppq_position = ppq_position;
+    }
+
     pub(crate) fn update_param(&mut self, param: Param) {
+        if let Some(param) = self.tempo_sync.resolve(param) {
+            self.apply_param(param);
+        }
+    }
+
+    fn apply_param(&mut self, param: Param) {
         match param {
             Param::Pitch(note_number, pitch_param) => {
                 self.pitch_envelope.set_pitch(note_number, pitch_param);
             }
+            Param::PitchPoints(num_points) => {
+                self.pitch_envelope.set_num_points(num_points);
+            }
+            Param::PitchStartFrequency(start_frequency) => {
+                self.pitch_envelope.set_start_frequency(start_frequency);
+            }
+            Param::PitchEndMode(end_mode) => {
+                self.pitch_envelope.set_end_mode(end_mode);
+            }
+            Param::PitchReturnTime(return_time) => {
+                self.pitch_envelope.set_return_time(return_time);
+            }
+            Param::FineTune(fine_tune) => {
+                self.pitch_envelope.set_fine_tune(fine_tune);
+            }
+            Param::A4Frequency(a4_frequency) => {
+                self.pitch_envelope.set_a4_frequency(a4_frequency);
+            }
 
             Param::AmpAttack(attack) => {
                 self.amp_envelope.set_attack(attack);
@@ -53,6 +164,45 @@ impl KickSynth {
             Param::AmpExponentialFactorA(factor) => {
                 self.amp_envelope.set_exponential_factor_a(factor);
             }
+            Param::AmpHold(hold) => {
+                self.amp_envelope.set_hold(hold);
+            }
+            Param::AmpSustainTime(sustain_time) => {
+                self.amp_envelope.set_sustain_time(sustain_time);
+            }
+            Param::AmpAttackCurve(curve) => {
+                self.amp_envelope.set_attack_curve(curve);
+            }
+            Param::AmpAttackTension(tension) => {
+                self.amp_envelope.set_attack_tension(tension);
+            }
+            Param::AmpDecayCurve(curve) => {
+                self.amp_envelope.set_decay_curve(curve);
+            }
+            Param::AmpDecayTension(tension) => {
+                self.amp_envelope.set_decay_tension(tension);
+            }
+            Param::AmpReleaseCurve(curve) => {
+                self.amp_envelope.set_release_curve(curve);
+            }
+            Param::AmpSource(source) => {
+                self.amp_source = source;
+            }
+            Param::PitchSource(source) => {
+                self.pitch_source = source;
+            }
+            // resolved into a time by tempo_sync
+            Param::Sync(..) => {}
+            Param::Length(length) => {
+                self.amp_envelope.set_length(length);
+                self.pitch_envelope.set_length(length);
+                self.filter.get_envelope_mut().set_length(length);
+            }
+            Param::LengthKeepAttack(keep_attack) => {
+                self.amp_envelope.set_keep_attack(keep_attack);
+                self.pitch_envelope.set_keep_attack(keep_attack);
+                self.filter.get_envelope_mut().set_keep_attack(keep_attack);
+            }
 
             Param::Phase(phase) => {
                 self.oscillator.set_phase(phase);
@@ -68,6 +218,93 @@ impl KickSynth {
             Param::Drive(drive) => {
                 self.saturation.set_drive(drive);
             }
+            Param::Oversampling(factor) => {
+                self.saturation.set_oversampling(factor);
+            }
+            Param::SaturationFolds(folds) => {
+                self.saturation.set_folds(folds);
+            }
+            Param::SaturationBias(bias) => {
+                self.saturation.set_bias(bias);
+            }
+            Param::BitcrushBits(bits) => {
+                self.saturation.set_bits(bits);
+            }
+            Param::BitcrushRate(crush_rate) => {
+                self.saturation.set_crush_rate(crush_rate);
+            }
+            Param::SaturationAutoGain(auto_gain) => {
+                self.saturation.set_auto_gain(auto_gain);
+            }
+            Param::SaturationOutput(output_gain) => {
+                self.saturation.set_output_gain(output_gain);
+            }
+            Param::Distortion(stage_number, distortion_param) => {
+                self.saturation.get_distortion_chain_mut().set_stage(stage_number, distortion_param);
+            }
+            Param::DistortionStages(num_stages) => {
+                self.saturation.get_distortion_chain_mut().set_num_stages(num_stages);
+            }
+            Param::Band(band_number, band_param) => {
+                self.saturation.get_multiband_mut().set_band(band_number, band_param);
+            }
+            Param::MultibandBands(num_bands) => {
+                self.saturation.get_multiband_mut().set_num_bands(num_bands);
+            }
+            Param::MultibandLowCrossover(frequency) => {
+                self.saturation.get_multiband_mut().set_low_crossover(frequency);
+            }
+            Param::MultibandHighCrossover(frequency) => {
+                self.saturation.get_multiband_mut().set_high_crossover(frequency);
+            }
+            Param::MultibandLowClean(low_clean) => {
+                self.saturation.get_multiband_mut().set_low_clean(low_clean);
+            }
+            Param::Filter(filter_param) => {
+                self.filter.set_param(filter_param);
+            }
+            Param::Eq(band_number, eq_param) => {
+                self.eq.set_band(band_number, eq_param);
+            }
+            Param::EqHighPass(enabled) => {
+                self.eq.set_high_pass_enabled(enabled);
+            }
+            Param::EqHighPassFrequency(frequency) => {
+                self.eq.set_high_pass_frequency(frequency);
+            }
+            Param::EqHighPassQ(q) => {
+                self.eq.set_high_pass_q(q);
+            }
+            Param::DcBlocker(enabled) => {
+                self.subsonic.set_dc_blocker_enabled(enabled);
+            }
+            Param::Subsonic(enabled) => {
+                self.subsonic.set_subsonic_enabled(enabled);
+            }
+            Param::SubsonicFrequency(frequency) => {
+                self.subsonic.set_subsonic_frequency(frequency);
+            }
+            Param::OutputGain(gain) => {
+                self.output_stage.set_gain(gain);
+            }
+            Param::Limiter(enabled) => {
+                self.output_stage.set_limiter_enabled(enabled);
+            }
+            Param::LimiterCeiling(ceiling) => {
+                self.output_stage.set_limiter_ceiling(ceiling);
+            }
+            Param::LimiterRelease(release) => {
+                self.output_stage.set_limiter_release(release);
+            }
+            Param::TransientAttack(attack) => {
+                self.transient_shaper.set_attack(attack);
+            }
+            Param::TransientSustain(sustain) => {
+                self.transient_shaper.set_sustain(sustain);
+            }
+            Param::Punch(punch) => {
+                self.transient_shaper.set_punch(punch);
+            }
         }
     }
 
@@ -90,11 +327,16 @@ impl KickSynth {
             MidiAction::NoteOn => {
                 self.oscillator.reset();
                 self.amp_envelope.note_on();
+                self.pitch_envelope.set_root_note(midi_message.get_note_pitch());
                 self.pitch_envelope.note_on();
+                self.amp_curve.note_on();
+                self.pitch_curve.note_on();
+                self.filter.note_on(midi_message.get_note_pitch(), self.pitch_envelope.get_tuning());
             }
             MidiAction::NoteOff => {
                 self.amp_envelope.note_off();
                 self.pitch_envelope.note_off();
+                self.filter.note_off();
             }
         }
 
@@ -108,33 +350,122 @@ impl KickSynth {
             oscillator: oscillator::new(sr),
             amp_envelope: envelope::Envelope::new(sr),
             pitch_envelope: pitch_envelope::PitchEnvelope::new(sr),
+            amp_curve: CurveEnvelope::new(sr, false),
+            pitch_curve: CurveEnvelope::new(sr, true),
+            amp_source: EnvelopeSource::Envelope,
+            pitch_source: EnvelopeSource::Envelope,
+            tempo_sync: TempoSync::new(),
+            ppq_position: 0.0,
             midi_message_queue: MidiMessageQueue::new(),
-            saturation: saturation::Saturation::new(),
+            saturation: saturation::Saturation::new(sr),
+            filter: Filter::new(sr),
+            eq: Eq::new(sr),
+            subsonic: Subsonic::new(sr),
+            output_stage: OutputStage::new(sr),
+            transient_shaper: TransientShaper::new(sr),
+            diagnostics: Arc::new(Diagnostics::new()),
+            param_store: Arc::new(ParamStore::new()),
+            synth_state: Arc::new(SynthState::new()),
         }
     }
 
     pub fn process_block(&mut self, block: &mut [f32]) {
+        let alloc_guard = AllocGuard::new();
+        self.apply_param_changes();
+        self.amp_envelope.update_smoothing(block.len());
+        self.filter.update_smoothing(block.len());
+
+        let mut frequency = 0.0;
+        let mut amp_envelope_value = 0.0;
+        let mut peak_output = 0.0_f32;
 
         for (i, x) in block.iter_mut().enumerate() {
             self.process_midi_messages(i as i32);
 
-            let frequency = self.pitch_envelope.get_frequency();
+            frequency = self.get_frequency();
             self.oscillator.set_frequency(frequency);
 
-            *x = self.oscillator.process_sample() * self.amp_envelope.process_sample();
+            amp_envelope_value = self.get_amp_envelope_value();
+            *x = self.oscillator.process_sample() * amp_envelope_value;
+            *x = self.transient_shaper.process_sample(*x);
 
             // attenuation by -12db
-            *x = *x * 0.25;
-            *x = self.saturation.process_sample(*x);
-
+            *x *= 0.25;
+            *x = match self.filter.get_position() {
+                FilterPosition::PreSaturation => self.saturation.process_sample(self.filter.process_sample(*x)),
+                FilterPosition::PostSaturation => self.filter.process_sample(self.saturation.process_sample(*x)),
+            };
+            *x = self.eq.process_sample(*x);
+            *x = self.subsonic.process_sample(*x);
+            *x = self.output_stage.process_sample(*x);
 
-            // Safety feature, shouldn't change the audio
+            // Last resort safety after the output stage, shouldn't change the audio
             if x.abs() > 1.5 {
-                eprintln!("Clipping! with value {}", x);
+                self.diagnostics.clip(i as i32, *x);
                 *x = x.clamp(-1.5, 1.5);
             }
 
+            peak_output = peak_output.max(x.abs());
+
+        }
+
+        self.publish_synth_state(frequency, amp_envelope_value, peak_output);
+
+        let allocations = alloc_guard.get_allocations();
+        if allocations > 0 {
+            self.diagnostics.allocation(allocations);
+        }
+    }
+
+    // A curve without points leaves the sound to the envelope it replaces
+    fn uses_amp_curve(&self) -> bool {
+        self.amp_source == EnvelopeSource::Curve && self.amp_curve.has_points()
+    }
+
+    fn uses_pitch_curve(&self) -> bool {
+        self.pitch_source == EnvelopeSource::Curve && self.pitch_curve.has_points()
+    }
+
+    fn get_frequency(&mut self) -> f32 {
+        match self.uses_pitch_curve() {
+            true => self.pitch_curve.process_sample(),
+            false => self.pitch_envelope.get_frequency(),
+        }
+    }
 
+    fn get_amp_envelope_value(&mut self) -> f32 {
+        match self.uses_amp_curve() {
+            true => self.amp_curve.process_sample(),
+            false => self.amp_envelope.process_sample(),
         }
     }
+
+    fn get_amp_envelope_stage(&self) -> EnvelopeStage {
+        match self.uses_amp_curve() {
+            true if self.amp_curve.is_finished() => EnvelopeStage::Off,
+            true => EnvelopeStage::Curve,
+            false => self.amp_envelope.get_stage(),
+        }
+    }
+
+    fn publish_synth_state(&mut self, frequency: f32, amp_envelope_value: f32, peak_output: f32) {
+        let amp_envelope_stage = self.get_amp_envelope_stage();
+        let pitch_envelope_segment = match self.uses_pitch_curve() {
+            true => self.pitch_curve.get_current_segment(),
+            false => self.pitch_envelope.get_current_segment(),
+        };
+
+        self.synth_state.publish(&SynthStateSnapshot {
+            frequency,
+            amp_envelope_value,
+            amp_envelope_stage,
+            pitch_envelope_segment,
+            // the kick is monophonic, there is one voice as long as the amp envelope is running
+            voice_count: (amp_envelope_stage != EnvelopeStage::Off) as u32,
+            peak_output,
+            saturation_gain_reduction_db: self.saturation.take_gain_reduction_db(),
+            tempo: self.param_store.get_tempo(),
+            ppq_position: self.ppq_position,
+        });
+    }
 }
@@ -0,0 +1,56 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::oversampling::OversamplingFactor;
+use crate::output_stage::get_limiter_latency;
+use crate::param_store::ParamStore;
+
+// Everything in the kick that delays it: the halfband filters of the oversampling and the lookahead of the limiter
+fn get_latency(param_store: &ParamStore, sample_rate: usize) -> usize {
+    let oversampling_latency = param_store.get("oversampling")
+        .and_then(OversamplingFactor::from_value)
+        .map_or(0, |factor| factor.get_latency());
+    let limiter_latency = match param_store.get("limiter").unwrap_or(0.0) >= 0.5 {
+        true => get_limiter_latency(sample_rate),
+        false => 0,
+    };
+
+    oversampling_latency + limiter_latency
+}
+
+/// Returns the latency in samples that the current parameters add, report it to the host.
+/// The latency only changes with the oversampling and the limiter parameters.
+#[no_mangle]
+pub extern "C" fn get_latency_samples(param_store: &ParamStore, sample_rate: usize) -> usize {
+    get_latency(param_store, sample_rate)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::latency::get_latency;
+    use crate::param_store::ParamStore;
+
+    #[test]
+    fn test_oversampling_and_limiter_add_up() {
+        let param_store = ParamStore::new();
+        assert_eq!(get_latency(&param_store, 48000), 0);
+
+        param_store.set("oversampling", 1.0);
+        assert_eq!(get_latency(&param_store, 48000), 24);
+
+        param_store.set("limiter", 1.0);
+        assert_eq!(get_latency(&param_store, 48000), 24 + 8 + 72);
+
+        param_store.set("oversampling", 0.0);
+        assert_eq!(get_latency(&param_store, 96000), 8 + 144);
+    }
+}
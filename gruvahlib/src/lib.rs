@@ -20,10 +20,32 @@ mod saturation;
 mod utils;
 mod pitch_envelope;
 mod target_pair;
+mod diagnostics;
+mod alloc_guard;
+mod param_store;
+mod synth_state;
+mod curve_render;
+mod pitch_points;
+mod tuning;
+mod scala;
+mod curve_envelope;
+mod tempo_sync;
+mod oversampling;
+mod distortion_chain;
+mod biquad;
+mod multiband;
+mod filter;
+mod eq;
+mod subsonic;
+mod output_stage;
+mod transient_shaper;
+mod latency;
+#[cfg(test)]
+mod test_utils;
+pub mod preview;
 
 use std::ffi::c_char;
 pub use kick_synth::KickSynth;
-use params::Param;
 
 
 #[no_mangle]
@@ -31,12 +53,16 @@ pub extern "C" fn create(sample_rate: usize) -> *mut KickSynth {
     Box::into_raw(Box::new(KickSynth::new(sample_rate)))
 }
 
+/// # Safety
+/// `kick_synth` must be a pointer returned by `create`, it must not be used afterwards.
 #[no_mangle]
 pub unsafe extern "C" fn destroy(kick_synth: *mut KickSynth) {
     assert!(!kick_synth.is_null());
     drop(Box::from_raw(kick_synth));
 }
 
+/// # Safety
+/// `block_left` and `block_right` must both point to at least `num_samples` writable floats.
 #[no_mangle]
 pub unsafe extern "C" fn process(
     kick_synth: &mut KickSynth,
@@ -50,11 +76,11 @@ pub unsafe extern "C" fn process(
     let block_right = std::slice::from_raw_parts_mut(block_right, num_samples);
 
     // mono copy left to right
-    for i in 0..num_samples {
-        block_right[i] = block_left[i];
-    }
+    block_right.copy_from_slice(block_left);
 }
 
+/// # Safety
+/// `block` must point to at least `num_samples` writable floats.
 #[no_mangle]
 pub unsafe extern "C" fn process_mono(
     kick_synth: &mut KickSynth,
@@ -70,11 +96,30 @@ pub extern "C" fn process_midi_message(kick_synth: &mut KickSynth, midi_message:
     kick_synth.queue_midi_message(midi_message);
 }
 
+// Same as process_midi_message, without boxing a MidiMessage first, so it is safe to call from the audio thread
 #[no_mangle]
-pub unsafe extern "C" fn update_param(kick_synth: &mut KickSynth, parameter_id: *const c_char, new_value: f32) {
+pub extern "C" fn process_raw_midi_message(kick_synth: &mut KickSynth, timestamp: i32, raw_midi_data: &u8, note_pitch: u8, velocity: u8) {
+    let midi_message = midi_message::MidiMessage::new(timestamp, raw_midi_data, note_pitch, velocity);
+    kick_synth.queue_midi_message(&midi_message);
+}
 
-    let parameter_id = std::ffi::CStr::from_ptr(parameter_id).to_str().unwrap();
-    // println!("Updating param: {} to {}", parameter_id, new_value);
-    let param = Param::new(parameter_id, new_value);
-    kick_synth.update_param(param);
+/// Passes the tempo in bpm and the position in quarter notes of the host, call it before processing every block.
+/// Synced times follow the tempo from that block onwards.
+#[no_mangle]
+pub extern "C" fn set_transport(kick_synth: &mut KickSynth, tempo: f64, ppq_position: f64) {
+    kick_synth.set_transport(tempo, ppq_position);
+}
+
+/// The new value is not applied straight away, it is picked up at the start of the next `process` call
+/// and used from that block onwards. Returns false and ignores the new value when the parameter id or the value is invalid.
+/// To update parameters while the audio thread is running, use `param_store_update` instead.
+///
+/// # Safety
+/// `parameter_id` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn update_param(kick_synth: &mut KickSynth, parameter_id: *const c_char, new_value: f32) -> bool {
+    match std::ffi::CStr::from_ptr(parameter_id).to_str() {
+        Ok(parameter_id) => kick_synth.set_param(parameter_id, new_value),
+        Err(_) => false,
+    }
 }
\ No newline at end of file
@@ -32,7 +32,7 @@ impl MidiMessage {
 }
 
 impl MidiMessage {
-    fn new(timestamp: i32, raw_midi_data: &u8, note_pitch: u8, velocity: u8) -> Self {
+    pub(crate) fn new(timestamp: i32, raw_midi_data: &u8, note_pitch: u8, velocity: u8) -> Self {
         let midi_action = raw_midi_data & 0xF0;
 
         // velocity = 0 means note off, at least in the standalone application
@@ -68,18 +68,22 @@ impl MidiMessage {
 
 
     pub fn get_midi_action(&self) -> MidiAction {
-        self.midi_action.clone()
+        self.midi_action
+    }
+
+    pub(crate) fn get_note_pitch(&self) -> u8 {
+        self.note_pitch
     }
 }
 
 pub fn get_midi_frequency_from_pitch(note_pitch: u8) -> f32 {
     let midi_note = note_pitch as f32;
-    let midi_frequency = 440.0 * 2.0_f32.powf((midi_note - 69.0) / 12.0);
-    midi_frequency
+    440.0 * 2.0_f32.powf((midi_note - 69.0) / 12.0)
 }
 
-pub fn get_midi_note_from_octave_and_note_number(octave: i32, note_number: i32) -> u8 {
-    (octave * 12 + note_number + 12) as u8
+// not limited to the midi range, so notes above G9 stay in tune instead of wrapping around
+pub fn get_midi_note_from_octave_and_note_number(octave: i32, note_number: i32) -> i32 {
+    octave * 12 + note_number + 12
 }
 
 #[no_mangle]
@@ -87,9 +91,12 @@ pub extern "C" fn create_midi_message(timestamp: i32, raw_midi_data: &u8, note_p
     Box::into_raw(Box::new(MidiMessage::new(timestamp, raw_midi_data, note_pitch, velocity)))
 }
 
+/// # Safety
+/// `midi_message` must be a pointer returned by `create_midi_message`, it must not be used afterwards.
 #[no_mangle]
-pub extern "C" fn destroy_midi_message(midi_message: *const MidiMessage) {
+pub unsafe extern "C" fn destroy_midi_message(midi_message: *const MidiMessage) {
     assert!(!midi_message.is_null());
+    drop(Box::from_raw(midi_message as *mut MidiMessage));
 }
 
 // midi note tests
@@ -25,15 +25,17 @@ impl MidiMessageQueue {
         }
     }
 
-    pub(crate) fn add_midi_message(&mut self, midi_message: MidiMessage) {
+    // Returns false when the queue is full and the midi message is dropped
+    pub(crate) fn add_midi_message(&mut self, midi_message: MidiMessage) -> bool {
         for midi_message_item in self.midi_messages.iter_mut() {
             if midi_message_item.is_none() {
                 *midi_message_item = Some((midi_message.get_timestamp(), midi_message));
-                return;
+                return true;
             }
         }
 
-        eprintln!("Do you really really need more than {} messages during one block???, dropping midi message: {:?}", MIDI_QUEUE_SIZE, midi_message);
+        // Do you really really need more than MIDI_QUEUE_SIZE messages during one block???
+        false
     }
 
     // If there are more than one midi messages with the same timestamp, this will return the first one, and drop the rest
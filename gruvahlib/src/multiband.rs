@@ -0,0 +1,274 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::saturation::{SaturationType, Waveshaper};
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::utils::db_to_linear;
+
+pub(crate) const MAX_BANDS: usize = 3;
+
+pub enum BandParam {
+    Type(SaturationType),
+    Drive(f32),
+    Level(f32),
+}
+
+// A 4th order Linkwitz-Riley filter, two Butterworth filters in a row
+#[derive(Clone, Copy)]
+struct LinkwitzRiley {
+    biquads: [Biquad; 2],
+}
+
+impl LinkwitzRiley {
+    fn new() -> Self {
+        Self {
+            biquads: [Biquad::new(); 2],
+        }
+    }
+
+    fn set_lowpass(&mut self, sample_rate: f32, frequency: f32) {
+        self.biquads.iter_mut().for_each(|biquad| biquad.set_lowpass(sample_rate, frequency, BUTTERWORTH_Q));
+    }
+
+    fn set_highpass(&mut self, sample_rate: f32, frequency: f32) {
+        self.biquads.iter_mut().for_each(|biquad| biquad.set_highpass(sample_rate, frequency, BUTTERWORTH_Q));
+    }
+
+    fn process_sample(&mut self, input: f32) -> f32 {
+        self.biquads.iter_mut().fold(input, |x, biquad| biquad.process_sample(x))
+    }
+
+    fn reset(&mut self) {
+        self.biquads.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Band {
+    waveshaper: Waveshaper,
+    drive: TargetCurrentPair<f32>,
+    level: TargetCurrentPair<f32>,
+}
+
+impl Band {
+    fn process_sample(&mut self, input: f32, clean: bool) -> f32 {
+        let saturated = match clean {
+            true => input,
+            false => self.waveshaper.saturate(input * self.waveshaper.get_drive_gain(self.drive.get())),
+        };
+
+        saturated * self.level.get()
+    }
+}
+
+// Splits the signal in 2 or 3 bands with Linkwitz-Riley crossovers and saturates every band on its own.
+// The low band also goes through the allpass of the high crossover, so without saturation the bands add up
+// to the input with only a change in phase, and a clean low band stays a clean sine.
+pub(crate) struct Multiband {
+    sample_rate: f32,
+    num_bands: usize,
+    low_crossover: f32,
+    high_crossover: f32,
+    low_clean: bool,
+    low_lowpass: LinkwitzRiley,
+    low_highpass: LinkwitzRiley,
+    high_lowpass: LinkwitzRiley,
+    high_highpass: LinkwitzRiley,
+    // a Linkwitz-Riley lowpass and highpass added together are a second order Butterworth allpass
+    low_allpass: Biquad,
+    bands: [Band; MAX_BANDS],
+}
+
+impl Multiband {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let smoothed = |value| TargetCurrentPair::new(value).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear);
+        let band = Band {
+            waveshaper: Waveshaper::new(SaturationType::Soft),
+            drive: smoothed(1.0),
+            level: smoothed(1.0),
+        };
+
+        let mut multiband = Self {
+            sample_rate: sample_rate as f32,
+            num_bands: 1,
+            low_crossover: 120.0,
+            high_crossover: 2000.0,
+            low_clean: false,
+            low_lowpass: LinkwitzRiley::new(),
+            low_highpass: LinkwitzRiley::new(),
+            high_lowpass: LinkwitzRiley::new(),
+            high_highpass: LinkwitzRiley::new(),
+            low_allpass: Biquad::new(),
+            bands: [band; MAX_BANDS],
+        };
+        multiband.update_crossovers();
+        multiband
+    }
+
+    // Returns the bands to saturate further and the clean low band apart, which has to go around the rest of the
+    // saturation to stay clean. With a single band the signal passes unchanged.
+    pub(crate) fn process_sample(&mut self, input: f32) -> (f32, f32) {
+        let (low, rest) = match self.num_bands {
+            2 => {
+                let low = self.low_lowpass.process_sample(input);
+                let high = self.low_highpass.process_sample(input);
+
+                (self.bands[0].process_sample(low, self.low_clean), self.bands[1].process_sample(high, false))
+            }
+            3 => {
+                let low = self.low_allpass.process_sample(self.low_lowpass.process_sample(input));
+                let rest = self.low_highpass.process_sample(input);
+                let mid = self.high_lowpass.process_sample(rest);
+                let high = self.high_highpass.process_sample(rest);
+
+                (
+                    self.bands[0].process_sample(low, self.low_clean),
+                    self.bands[1].process_sample(mid, false) + self.bands[2].process_sample(high, false),
+                )
+            }
+            _ => return (input, 0.0),
+        };
+
+        match self.low_clean {
+            true => (rest, low),
+            false => (low + rest, 0.0),
+        }
+    }
+
+    pub(crate) fn update_smoothing(&mut self) {
+        for band in self.bands[..self.num_bands].iter_mut() {
+            band.drive.update_to_target();
+            band.level.update_to_target();
+        }
+    }
+
+    pub(crate) fn settle(&mut self) {
+        for band in self.bands.iter_mut() {
+            band.drive.settle();
+            band.level.settle();
+        }
+    }
+
+    pub(crate) fn get_waveshapers_mut(&mut self) -> impl Iterator<Item = &mut Waveshaper> {
+        self.bands.iter_mut().map(|band| &mut band.waveshaper)
+    }
+
+    // The oversampled rate the bands run at
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_crossovers();
+    }
+
+    fn update_crossovers(&mut self) {
+        // the high crossover is never below the low one
+        let high_crossover = self.high_crossover.max(self.low_crossover);
+
+        self.low_lowpass.set_lowpass(self.sample_rate, self.low_crossover);
+        self.low_highpass.set_highpass(self.sample_rate, self.low_crossover);
+        self.high_lowpass.set_lowpass(self.sample_rate, high_crossover);
+        self.high_highpass.set_highpass(self.sample_rate, high_crossover);
+        self.low_allpass.set_allpass(self.sample_rate, high_crossover, BUTTERWORTH_Q);
+    }
+
+    pub(crate) fn set_num_bands(&mut self, num_bands: usize) {
+        if num_bands == self.num_bands {
+            return;
+        }
+
+        // the filters that weren't used hold old samples
+        self.num_bands = num_bands.clamp(1, MAX_BANDS);
+        self.low_lowpass.reset();
+        self.low_highpass.reset();
+        self.high_lowpass.reset();
+        self.high_highpass.reset();
+        self.low_allpass.reset();
+    }
+
+    pub(crate) fn set_low_crossover(&mut self, frequency: f32) {
+        self.low_crossover = frequency;
+        self.update_crossovers();
+    }
+
+    pub(crate) fn set_high_crossover(&mut self, frequency: f32) {
+        self.high_crossover = frequency;
+        self.update_crossovers();
+    }
+
+    pub(crate) fn set_low_clean(&mut self, low_clean: bool) {
+        self.low_clean = low_clean;
+    }
+
+    pub(crate) fn set_band(&mut self, band_number: usize, band_param: BandParam) {
+        let band = &mut self.bands[band_number];
+
+        match band_param {
+            BandParam::Type(saturation_type) => band.waveshaper.set_saturation_type(saturation_type),
+            BandParam::Drive(drive) => band.drive.set_target(db_to_linear(drive)),
+            BandParam::Level(level) => band.level.set_target(db_to_linear(level)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::multiband::{BandParam, Multiband};
+    use crate::saturation::SaturationType;
+    use crate::test_utils::get_sine_gain;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // The level of the saturated and the clean part together
+    fn get_level(multiband: &mut Multiband, frequency: f32) -> f32 {
+        get_sine_gain(|x| {
+            let (saturated, clean) = multiband.process_sample(x);
+            saturated + clean
+        }, frequency, SAMPLE_RATE)
+    }
+
+    fn get_multiband(num_bands: usize) -> Multiband {
+        let mut multiband = Multiband::new(SAMPLE_RATE as usize);
+        multiband.set_num_bands(num_bands);
+
+        for band_number in 0..num_bands {
+            multiband.set_band(band_number, BandParam::Type(SaturationType::None));
+        }
+
+        multiband
+    }
+
+    #[test]
+    fn test_bands_add_up_flat() {
+        for num_bands in [2, 3] {
+            let mut multiband = get_multiband(num_bands);
+
+            for frequency in [40.0, 120.0, 500.0, 2000.0, 8000.0] {
+                assert_approx_eq!(get_level(&mut multiband, frequency), 1.0, 1.0e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clean_low_band() {
+        let mut multiband = get_multiband(3);
+        multiband.set_band(0, BandParam::Type(SaturationType::Clip));
+        multiband.set_band(0, BandParam::Drive(24.0));
+        multiband.settle();
+
+        // the sub is clipped into a square, which has more energy than the sine
+        assert!(get_level(&mut multiband, 40.0) > 1.1);
+
+        multiband.set_low_clean(true);
+        assert_approx_eq!(get_level(&mut multiband, 40.0), 1.0, 2.0e-2);
+    }
+}
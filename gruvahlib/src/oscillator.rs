@@ -23,6 +23,7 @@ pub struct Oscillator {
     start_phase: f32,
     current_phase: f32,
     fft: Arc<dyn rustfft::Fft<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
     wave_table: [Complex<f32>; 1024],
 }
 
@@ -32,29 +33,30 @@ impl Oscillator {
         self.start_phase = phase;
     }
 
-    fn set_wave_table(&mut self, bins: Vec<(usize, Complex<f32>)>) {
+    // Called from the audio thread, so it uses the preallocated scratch buffer instead of letting rustfft allocate one
+    fn set_wave_table(&mut self, bins: &[(usize, Complex<f32>)]) {
         self.wave_table = [Complex::new(0.0, 0.0); 1024];
 
         for bin in bins {
             self.wave_table[bin.0] = bin.1;
         }
 
-        self.fft.process(&mut self.wave_table);
+        self.fft.process_with_scratch(&mut self.wave_table, &mut self.fft_scratch);
     }
 
     pub(crate) fn set_wave_type(&mut self, wave_type: WaveType) {
         match wave_type {
             WaveType::Sine => {
-                let bins = vec![(1, Complex::new(1.0, 0.0))];
-                self.set_wave_table(bins);
+                let bins = [(1, Complex::new(1.0, 0.0))];
+                self.set_wave_table(&bins);
             }
             WaveType::Wave909 => {
-                let bins = vec![
+                let bins = [
                     (1, Complex::new(1.0, 0.0)),
                     (2, Complex::new(0.2, 0.0)),
                     (2, Complex::new(0.1, 0.0)),
                 ];
-                self.set_wave_table(bins);
+                self.set_wave_table(&bins);
             }
         }
     }
@@ -102,13 +104,19 @@ pub fn new(sample_rate: usize) -> Oscillator {
     let wave_table = [Complex::new(0.0, 0.0); 1024];
 
     let fft = planner.plan_fft_inverse(wave_table.len());
+    let fft_scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
 
-    Oscillator {
+    let mut oscillator = Oscillator {
         sample_rate,
         frequency: 440.0,
         start_phase: 0.0,
         current_phase: 0.0,
         fft,
+        fft_scratch,
         wave_table,
-    }
+    };
+
+    // same default as the waveType parameter
+    oscillator.set_wave_type(WaveType::Sine);
+    oscillator
 }
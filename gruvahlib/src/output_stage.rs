@@ -0,0 +1,342 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::PI;
+
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::utils::db_to_linear;
+
+// How far ahead the limiter looks, it turns the gain down over this time so a peak never gets through
+const LOOKAHEAD_MS: f32 = 1.5;
+// enough for 1.5 ms at 192 kHz and more
+const MAX_LOOKAHEAD: usize = 512;
+
+// The true peak is estimated at 4x the sample rate, the 3 points in between two samples are interpolated
+// with a windowed sinc of INTERPOLATION_TAPS taps, which delays the peak by half of them
+const INTERPOLATION_PHASES: usize = 3;
+const INTERPOLATION_TAPS: usize = 16;
+const INTERPOLATION_DELAY: usize = INTERPOLATION_TAPS / 2;
+
+// In samples at the sample rate, the limiter only adds latency while it is on
+pub(crate) fn get_limiter_latency(sample_rate: usize) -> usize {
+    INTERPOLATION_DELAY + get_lookahead(sample_rate)
+}
+
+fn get_lookahead(sample_rate: usize) -> usize {
+    ((LOOKAHEAD_MS / 1000.0 * sample_rate as f32).round() as usize).clamp(1, MAX_LOOKAHEAD)
+}
+
+// The taps for the points a quarter, a half and three quarters of the way to the next sample, newest sample first
+fn get_interpolation_taps() -> [[f32; INTERPOLATION_TAPS]; INTERPOLATION_PHASES] {
+    let mut taps = [[0.0; INTERPOLATION_TAPS]; INTERPOLATION_PHASES];
+    let half_length = INTERPOLATION_DELAY as f32;
+
+    for (phase, phase_taps) in taps.iter_mut().enumerate() {
+        let fraction = (phase + 1) as f32 / (INTERPOLATION_PHASES + 1) as f32;
+
+        for (i, tap) in phase_taps.iter_mut().enumerate() {
+            let offset = i as f32 - half_length + fraction;
+            let sinc = (PI * offset).sin() / (PI * offset);
+            // Blackman window, 0 at half_length on either side
+            let window_phase = PI * offset / half_length;
+            let window = 0.42 + 0.5 * window_phase.cos() + 0.08 * (2.0 * window_phase).cos();
+
+            *tap = sinc * window;
+        }
+
+        // a constant stays the same in between the samples
+        let sum: f32 = phase_taps.iter().sum();
+        phase_taps.iter_mut().for_each(|tap| *tap /= sum);
+    }
+
+    taps
+}
+
+// The lowest gain of the last window gains, without looking at all of them for every new one.
+// Gains that can never be the lowest again, because a lower one came after them, are dropped,
+// so the gains that are kept rise from the front to the back and the front is the lowest.
+struct WindowMinimum {
+    window: usize,
+    // a ring buffer of the sample count a gain came at and the gain
+    entries: [(usize, f32); MAX_LOOKAHEAD + 1],
+    front: usize,
+    len: usize,
+    sample_count: usize,
+}
+
+impl WindowMinimum {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            entries: [(0, 1.0); MAX_LOOKAHEAD + 1],
+            front: 0,
+            len: 0,
+            sample_count: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.front = 0;
+        self.len = 0;
+        self.sample_count = 0;
+    }
+
+    // Adds the gain of the new sample and returns the lowest gain of the window, 1 before any gain is below it
+    fn push(&mut self, gain: f32) -> f32 {
+        let capacity = self.entries.len();
+
+        // the gains that leave the window go first, so there is always room for the new one
+        while self.len > 0 && self.sample_count.wrapping_sub(self.entries[self.front].0) >= self.window {
+            self.front = (self.front + 1) % capacity;
+            self.len -= 1;
+        }
+
+        while self.len > 0 && self.entries[(self.front + self.len - 1) % capacity].1 >= gain {
+            self.len -= 1;
+        }
+        self.entries[(self.front + self.len) % capacity] = (self.sample_count, gain);
+        self.len += 1;
+        self.sample_count = self.sample_count.wrapping_add(1);
+
+        self.entries[self.front].1.min(1.0)
+    }
+}
+
+// A lookahead limiter that keeps the true peak, the peak in between the samples too, below the ceiling
+struct Limiter {
+    lookahead: usize,
+    ceiling: f32,
+    release_coefficient: f32,
+    interpolation_taps: [[f32; INTERPOLATION_TAPS]; INTERPOLATION_PHASES],
+    // the last input samples, newest first
+    history: [f32; INTERPOLATION_TAPS],
+    // the samples that wait for the gain, and the lowest gain the last lookahead + 1 of them need to stay below the ceiling
+    delay_line: [f32; MAX_LOOKAHEAD],
+    required_gain: WindowMinimum,
+    // the gain after the release, the gain that is applied is its average over the lookahead
+    released_gains: [f32; MAX_LOOKAHEAD],
+    released_sum: f32,
+    released_gain: f32,
+    position: usize,
+}
+
+impl Limiter {
+    fn new(sample_rate: usize) -> Self {
+        let lookahead = get_lookahead(sample_rate);
+        let mut limiter = Self {
+            lookahead,
+            ceiling: db_to_linear(-1.0),
+            release_coefficient: 1.0,
+            interpolation_taps: get_interpolation_taps(),
+            history: [0.0; INTERPOLATION_TAPS],
+            delay_line: [0.0; MAX_LOOKAHEAD],
+            required_gain: WindowMinimum::new(lookahead + 1),
+            released_gains: [1.0; MAX_LOOKAHEAD],
+            released_sum: lookahead as f32,
+            released_gain: 1.0,
+            position: 0,
+        };
+        limiter.set_release(sample_rate, 50.0);
+        limiter
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.0; INTERPOLATION_TAPS];
+        self.delay_line = [0.0; MAX_LOOKAHEAD];
+        self.required_gain.reset();
+        self.released_gains = [1.0; MAX_LOOKAHEAD];
+        self.released_sum = self.lookahead as f32;
+        self.released_gain = 1.0;
+        self.position = 0;
+    }
+
+    fn set_release(&mut self, sample_rate: usize, release_ms: f32) {
+        let release_samples = release_ms / 1000.0 * sample_rate as f32;
+        self.release_coefficient = match release_samples {
+            release_samples if release_samples < 1.0 => 1.0,
+            release_samples => 1.0 - (-1.0 / release_samples).exp(),
+        };
+    }
+
+    // The highest of the sample INTERPOLATION_DELAY samples ago and the points between it and the next sample
+    fn get_true_peak(&self) -> f32 {
+        let sample = self.history[INTERPOLATION_DELAY];
+
+        self.interpolation_taps.iter().fold(sample.abs(), |peak, taps| {
+            let interpolated: f32 = taps.iter().zip(self.history.iter()).map(|(tap, x)| tap * x).sum();
+            peak.max(interpolated.abs())
+        })
+    }
+
+    fn process_sample(&mut self, input: f32) -> f32 {
+        self.history.copy_within(0..INTERPOLATION_TAPS - 1, 1);
+        self.history[0] = input;
+
+        let sample = self.history[INTERPOLATION_DELAY];
+        let true_peak = self.get_true_peak();
+        let required_gain = match true_peak > self.ceiling {
+            true => self.ceiling / true_peak,
+            false => 1.0,
+        };
+
+        let lookahead = self.lookahead;
+        let position = self.position;
+
+        // every gain in the window is one the sample that comes out now or a sample after it needs,
+        // so its lowest gain holds from here until the loudest peak comes out
+        let lowest_gain = self.required_gain.push(required_gain);
+        self.released_gain = match lowest_gain < self.released_gain {
+            true => lowest_gain,
+            false => self.released_gain + (lowest_gain - self.released_gain) * self.release_coefficient,
+        };
+
+        // the average ramps the gain down over the lookahead instead of jumping, and still arrives in time.
+        // The running sum is added up again once per lookahead, so its rounding errors can't pile up.
+        self.released_sum += self.released_gain - self.released_gains[position];
+        self.released_gains[position] = self.released_gain;
+        if position == lookahead - 1 {
+            self.released_sum = self.released_gains[..lookahead].iter().sum();
+        }
+        let gain = self.released_sum / lookahead as f32;
+
+        let delayed = self.delay_line[position];
+        self.delay_line[position] = sample;
+        self.position = (position + 1) % lookahead;
+
+        delayed * gain
+    }
+}
+
+// The last part of the kick: the output gain and a true peak limiter
+pub(crate) struct OutputStage {
+    sample_rate: usize,
+    gain: TargetCurrentPair<f32>,
+    limiter_enabled: bool,
+    limiter: Limiter,
+}
+
+impl OutputStage {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            gain: TargetCurrentPair::new(1.0).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear),
+            limiter_enabled: false,
+            limiter: Limiter::new(sample_rate),
+        }
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        self.gain.update_to_target();
+        let output = input * self.gain.get();
+
+        match self.limiter_enabled {
+            true => self.limiter.process_sample(output),
+            false => output,
+        }
+    }
+
+    pub(crate) fn settle(&mut self) {
+        self.gain.settle();
+    }
+
+    pub(crate) fn set_gain(&mut self, gain: f32) {
+        self.gain.set_target(db_to_linear(gain));
+    }
+
+    // The latency changes with the limiter, the host is told by get_latency_samples
+    pub(crate) fn set_limiter_enabled(&mut self, enabled: bool) {
+        if enabled && !self.limiter_enabled {
+            self.limiter.reset();
+        }
+        self.limiter_enabled = enabled;
+    }
+
+    pub(crate) fn set_limiter_ceiling(&mut self, ceiling: f32) {
+        self.limiter.ceiling = db_to_linear(ceiling.min(0.0));
+    }
+
+    pub(crate) fn set_limiter_release(&mut self, release_ms: f32) {
+        self.limiter.set_release(self.sample_rate, release_ms);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::{FRAC_1_SQRT_2, PI};
+    use assert_approx_eq::assert_approx_eq;
+    use crate::output_stage::{get_limiter_latency, OutputStage, WindowMinimum, MAX_LOOKAHEAD};
+
+    #[test]
+    fn test_output_gain() {
+        let mut output_stage = OutputStage::new(48000);
+        output_stage.set_gain(6.0206);
+
+        assert_approx_eq!(output_stage.process_sample(0.25), 0.5, 1.0e-4);
+    }
+
+    #[test]
+    fn test_window_minimum() {
+        let window = MAX_LOOKAHEAD + 1;
+        let mut window_minimum = WindowMinimum::new(window);
+        let gains: Vec<f32> = (0..5000).map(|n| 0.5 + 0.5 * (n as f32 * 0.37).sin() * (n as f32 * 0.011).cos()).collect();
+
+        for (n, gain) in gains.iter().enumerate() {
+            let lowest = gains[n.saturating_sub(window - 1)..=n].iter().fold(1.0_f32, |lowest, gain| lowest.min(*gain));
+            assert_eq!(window_minimum.push(*gain), lowest);
+        }
+    }
+
+    #[test]
+    fn test_limiter_passes_quiet_signals_with_latency() {
+        let mut output_stage = OutputStage::new(48000);
+        output_stage.set_limiter_enabled(true);
+        let latency = get_limiter_latency(48000);
+        assert_eq!(latency, 8 + 72);
+
+        let input: Vec<f32> = (0..1000).map(|n| 0.5 * (2.0 * PI * 100.0 * n as f32 / 48000.0).sin()).collect();
+        let output: Vec<f32> = input.iter().map(|x| output_stage.process_sample(*x)).collect();
+
+        for n in latency..1000 {
+            assert_approx_eq!(output[n], input[n - latency], 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn test_limiter_catches_true_peaks() {
+        let mut output_stage = OutputStage::new(48000);
+        output_stage.set_limiter_enabled(true);
+        output_stage.set_limiter_ceiling(-1.0);
+        let ceiling = 0.8913;
+
+        // a quarter of the sample rate, sampled halfway between its peaks, so every sample is at 0.71 of its peak of 1
+        let input: Vec<f32> = (0..2000).map(|n| (PI / 2.0 * n as f32 + PI / 4.0).sin()).collect();
+        assert!(input.iter().all(|x| x.abs() < ceiling));
+
+        let output: Vec<f32> = input.iter().map(|x| output_stage.process_sample(*x)).collect();
+        let peak = output.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!(peak <= ceiling * FRAC_1_SQRT_2 + 1.0e-4);
+        assert!(peak > ceiling * FRAC_1_SQRT_2 * 0.95);
+    }
+
+    #[test]
+    fn test_limiter_lookahead() {
+        let mut output_stage = OutputStage::new(48000);
+        output_stage.set_limiter_enabled(true);
+        output_stage.set_limiter_ceiling(-6.0206);
+
+        // a sudden loud burst after silence never gets through
+        let input: Vec<f32> = (0..2000).map(|n| if n < 500 { 0.0 } else { 4.0 * (2.0 * PI * 60.0 * n as f32 / 48000.0).sin() }).collect();
+        let output: Vec<f32> = input.iter().map(|x| output_stage.process_sample(*x)).collect();
+
+        assert!(output.iter().all(|x| x.abs() <= 0.5 + 1.0e-4));
+    }
+}
@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::PI;
+
+// Every factor of 2 is one halfband stage, 8x is three of them
+const MAX_STAGES: usize = 3;
+const MAX_FACTOR: usize = 1 << MAX_STAGES;
+
+// The halfband filter has 2 * HALF_LENGTH + 1 taps, every other one is 0 except the one in the middle.
+// Its delay is HALF_LENGTH samples at the higher rate, for both the up and the down filter of a stage.
+const HALF_LENGTH: usize = 24;
+// the taps at an odd distance from the middle, the only ones that aren't 0
+const NUM_ODD_TAPS: usize = HALF_LENGTH;
+const EVEN_HISTORY_LENGTH: usize = HALF_LENGTH / 2 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OversamplingFactor {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl OversamplingFactor {
+    pub(crate) fn from_value(value: f32) -> Option<Self> {
+        match value as i32 {
+            0 => Some(OversamplingFactor::Off),
+            1 => Some(OversamplingFactor::X2),
+            2 => Some(OversamplingFactor::X4),
+            3 => Some(OversamplingFactor::X8),
+            _ => None,
+        }
+    }
+
+    fn get_num_stages(&self) -> usize {
+        match self {
+            OversamplingFactor::Off => 0,
+            OversamplingFactor::X2 => 1,
+            OversamplingFactor::X4 => 2,
+            OversamplingFactor::X8 => 3,
+        }
+    }
+
+    pub(crate) fn get_factor(&self) -> usize {
+        1 << self.get_num_stages()
+    }
+
+    // In samples at the base sample rate. Every stage delays by HALF_LENGTH samples at its lower rate,
+    // which halves with every stage, so the latency is always a whole number of samples.
+    pub(crate) fn get_latency(&self) -> usize {
+        (0..self.get_num_stages()).map(|stage| HALF_LENGTH >> stage).sum()
+    }
+}
+
+// The odd taps of a windowed sinc with its cutoff at a quarter of the higher sample rate, the middle tap is 0.5
+fn get_halfband_taps() -> [f32; NUM_ODD_TAPS] {
+    let num_taps = 2 * HALF_LENGTH + 1;
+    let mut taps = [0.0; NUM_ODD_TAPS];
+
+    for (i, tap) in taps.iter_mut().enumerate() {
+        let n = 2 * i + 1;
+        let offset = n as f32 - HALF_LENGTH as f32;
+        let sinc = (0.5 * PI * offset).sin() / (PI * offset);
+        // Blackman window
+        let window_phase = 2.0 * PI * n as f32 / (num_taps - 1) as f32;
+        let window = 0.42 - 0.5 * window_phase.cos() + 0.08 * (2.0 * window_phase).cos();
+
+        *tap = sinc * window;
+    }
+
+    // the odd taps add up to 0.5 like the middle tap, so a constant passes unchanged
+    let sum: f32 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap *= 0.5 / sum;
+    }
+
+    taps
+}
+
+// Doubles the sample rate on the way up and halves it on the way down, both as polyphase halfband filters
+// that only compute the taps that aren't 0
+#[derive(Clone, Copy)]
+struct HalfbandStage {
+    // the lower rate input of the up filter, newest first
+    up_history: [f32; NUM_ODD_TAPS],
+    // the higher rate input of the down filter, split in even and odd samples, newest first
+    down_even_history: [f32; EVEN_HISTORY_LENGTH],
+    down_odd_history: [f32; NUM_ODD_TAPS],
+}
+
+impl HalfbandStage {
+    fn new() -> Self {
+        Self {
+            up_history: [0.0; NUM_ODD_TAPS],
+            down_even_history: [0.0; EVEN_HISTORY_LENGTH],
+            down_odd_history: [0.0; NUM_ODD_TAPS],
+        }
+    }
+
+    fn upsample(&mut self, taps: &[f32; NUM_ODD_TAPS], input: f32) -> (f32, f32) {
+        push(&mut self.up_history, input);
+
+        // the zeros that are stuffed in between halve the level, which the factor 2 makes up for
+        let even = self.up_history[HALF_LENGTH / 2];
+        let odd = 2.0 * dot(taps, &self.up_history);
+
+        (even, odd)
+    }
+
+    fn downsample(&mut self, taps: &[f32; NUM_ODD_TAPS], even: f32, odd: f32) -> f32 {
+        push(&mut self.down_even_history, even);
+
+        let output = 0.5 * self.down_even_history[HALF_LENGTH / 2] + dot(taps, &self.down_odd_history);
+
+        push(&mut self.down_odd_history, odd);
+
+        output
+    }
+}
+
+fn push<const N: usize>(history: &mut [f32; N], input: f32) {
+    history.copy_within(0..N - 1, 1);
+    history[0] = input;
+}
+
+fn dot(taps: &[f32; NUM_ODD_TAPS], history: &[f32; NUM_ODD_TAPS]) -> f32 {
+    taps.iter().zip(history.iter()).map(|(tap, x)| tap * x).sum()
+}
+
+// Runs a nonlinear process at a multiple of the sample rate, so the harmonics it adds above
+// the original Nyquist frequency are filtered out instead of folding back as aliasing
+#[derive(Clone)]
+pub(crate) struct Oversampler {
+    factor: OversamplingFactor,
+    taps: [f32; NUM_ODD_TAPS],
+    stages: [HalfbandStage; MAX_STAGES],
+}
+
+impl Oversampler {
+    pub(crate) fn new() -> Self {
+        Self {
+            factor: OversamplingFactor::Off,
+            taps: get_halfband_taps(),
+            stages: [HalfbandStage::new(); MAX_STAGES],
+        }
+    }
+
+    pub(crate) fn set_factor(&mut self, factor: OversamplingFactor) {
+        if factor == self.factor {
+            return;
+        }
+
+        // the filters of the old factor hold samples at the wrong rates
+        self.factor = factor;
+        self.stages = [HalfbandStage::new(); MAX_STAGES];
+    }
+
+    pub(crate) fn process_sample<F: FnMut(f32) -> f32>(&mut self, input: f32, mut process: F) -> f32 {
+        let num_stages = self.factor.get_num_stages();
+        if num_stages == 0 {
+            return process(input);
+        }
+
+        let mut samples = [0.0; MAX_FACTOR];
+        let mut next_samples = [0.0; MAX_FACTOR];
+        samples[0] = input;
+
+        for stage in 0..num_stages {
+            for i in 0..1 << stage {
+                let (even, odd) = self.stages[stage].upsample(&self.taps, samples[i]);
+                next_samples[2 * i] = even;
+                next_samples[2 * i + 1] = odd;
+            }
+            samples = next_samples;
+        }
+
+        for sample in samples.iter_mut().take(1 << num_stages) {
+            *sample = process(*sample);
+        }
+
+        for stage in (0..num_stages).rev() {
+            for i in 0..1 << stage {
+                next_samples[i] = self.stages[stage].downsample(&self.taps, samples[2 * i], samples[2 * i + 1]);
+            }
+            samples = next_samples;
+        }
+
+        samples[0]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+    use assert_approx_eq::assert_approx_eq;
+    use crate::oversampling::{Oversampler, OversamplingFactor};
+
+    const FACTORS: [OversamplingFactor; 4] = [OversamplingFactor::Off, OversamplingFactor::X2, OversamplingFactor::X4, OversamplingFactor::X8];
+
+    fn get_sine(frequency: f32, sample_rate: f32, num_samples: usize) -> Vec<f32> {
+        (0..num_samples).map(|n| (2.0 * PI * frequency * n as f32 / sample_rate).sin()).collect()
+    }
+
+    // The magnitude of one frequency, the window has to hold a whole number of its periods
+    fn get_magnitude(signal: &[f32], frequency: f32, sample_rate: f32) -> f32 {
+        let (re, im) = signal.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, x)| {
+            let phase = 2.0 * PI * frequency * n as f32 / sample_rate;
+            (re + x * phase.cos(), im + x * phase.sin())
+        });
+
+        (re * re + im * im).sqrt() * 2.0 / signal.len() as f32
+    }
+
+    #[test]
+    fn test_latency() {
+        let latencies: Vec<usize> = FACTORS.iter().map(OversamplingFactor::get_latency).collect();
+        assert_eq!(latencies, vec![0, 24, 36, 42]);
+    }
+
+    #[test]
+    fn test_passes_low_frequencies_with_latency() {
+        let input = get_sine(100.0, 48000.0, 1000);
+
+        for factor in FACTORS {
+            let mut oversampler = Oversampler::new();
+            oversampler.set_factor(factor);
+            let latency = factor.get_latency();
+
+            let output: Vec<f32> = input.iter().map(|x| oversampler.process_sample(*x, |x| x)).collect();
+
+            for n in 200..1000 {
+                assert_approx_eq!(output[n], input[n - latency], 1.0e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_less_aliasing() {
+        let sample_rate = 48000.0;
+        let input = get_sine(5000.0, sample_rate, 1000);
+
+        let get_aliasing = |factor| {
+            let mut oversampler = Oversampler::new();
+            oversampler.set_factor(factor);
+            let output: Vec<f32> = input.iter().map(|x| oversampler.process_sample(*x * 10.0, |x| x.clamp(-1.0, 1.0))).collect();
+
+            // the 9th harmonic at 45 kHz folds back to 3 kHz, 480 samples are 50 periods of 5 kHz and 30 of 3 kHz
+            get_magnitude(&output[200..680], 3000.0, sample_rate)
+        };
+
+        let aliasing = get_aliasing(OversamplingFactor::Off);
+        assert!(aliasing > 0.05);
+        assert!(get_aliasing(OversamplingFactor::X8) < aliasing * 0.05);
+    }
+}
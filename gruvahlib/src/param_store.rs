@@ -0,0 +1,333 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::ffi::c_char;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::curve_envelope::{CurveStore, CurveTarget};
+use crate::params::{get_param_index, Param, PARAM_COUNT};
+use crate::scala::parse_tuning;
+use crate::tempo_sync::DEFAULT_TEMPO;
+use crate::tuning::MIDI_NOTE_COUNT;
+use crate::KickSynth;
+
+// One atomic value per parameter, which any thread can write to.
+// The audio thread picks up the changed values at the start of every block,
+// so the dsp itself is never touched from outside the audio thread.
+pub struct ParamStore {
+    values: [AtomicU32; PARAM_COUNT],
+    changed: [AtomicBool; PARAM_COUNT],
+    // parameters that were never set keep the default of the dsp, which isn't necessarily 0
+    assigned: [AtomicBool; PARAM_COUNT],
+    // the frequency of every midi note of a loaded Scala tuning, handed over like the parameters
+    note_frequencies: [AtomicU32; MIDI_NOTE_COUNT],
+    tuning_loaded: AtomicBool,
+    tuning_changed: AtomicBool,
+    amp_curve: CurveStore,
+    pitch_curve: CurveStore,
+    // the tempo of the host, for the synced times of the kick synth and the editor
+    tempo: AtomicU32,
+    tempo_changed: AtomicBool,
+}
+
+impl ParamStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            values: std::array::from_fn(|_| AtomicU32::new(0)),
+            changed: std::array::from_fn(|_| AtomicBool::new(false)),
+            assigned: std::array::from_fn(|_| AtomicBool::new(false)),
+            note_frequencies: std::array::from_fn(|_| AtomicU32::new(0)),
+            tuning_loaded: AtomicBool::new(false),
+            tuning_changed: AtomicBool::new(false),
+            amp_curve: CurveStore::new(),
+            pitch_curve: CurveStore::new(),
+            tempo: AtomicU32::new(DEFAULT_TEMPO.to_bits()),
+            tempo_changed: AtomicBool::new(false),
+        }
+    }
+
+    // Returns false and leaves the parameter alone when the id or the value is invalid
+    pub(crate) fn set(&self, parameter_id: &str, new_value: f32) -> bool {
+        match get_param_index(parameter_id) {
+            Some(index) => self.set_index(index, new_value),
+            None => false,
+        }
+    }
+
+    pub(crate) fn get(&self, parameter_id: &str) -> Option<f32> {
+        self.get_index(get_param_index(parameter_id)?)
+    }
+
+    // None when the parameter was never set
+    pub(crate) fn get_index(&self, index: usize) -> Option<f32> {
+        match self.assigned[index].load(Ordering::Acquire) {
+            true => Some(f32::from_bits(self.values[index].load(Ordering::Relaxed))),
+            false => None,
+        }
+    }
+
+    pub(crate) fn set_index(&self, index: usize, new_value: f32) -> bool {
+        // parse once here, so an invalid value never reaches the audio thread
+        if Param::from_index(index, new_value).is_none() {
+            return false;
+        }
+
+        self.values[index].store(new_value.to_bits(), Ordering::Relaxed);
+        self.assigned[index].store(true, Ordering::Relaxed);
+        self.changed[index].store(true, Ordering::Release);
+        true
+    }
+
+    // Sets every parameter that was set in the other store to its latest value there
+    pub(crate) fn copy_from(&self, other: &ParamStore) {
+        for index in 0..PARAM_COUNT {
+            if !other.assigned[index].load(Ordering::Relaxed) {
+                continue;
+            }
+
+            self.values[index].store(other.values[index].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.assigned[index].store(true, Ordering::Relaxed);
+            self.changed[index].store(true, Ordering::Release);
+        }
+
+        self.set_note_frequencies(other.get_note_frequencies());
+        self.amp_curve.set(&other.amp_curve.get());
+        self.pitch_curve.set(&other.pitch_curve.get());
+        self.set_tempo(other.get_tempo());
+    }
+
+    // Passes the latest value of every parameter that was ever set, without touching the changed flags
+    pub(crate) fn for_each_param(&self, mut apply: impl FnMut(Param)) {
+        for index in 0..PARAM_COUNT {
+            if !self.assigned[index].load(Ordering::Acquire) {
+                continue;
+            }
+
+            // every stored value was checked by set_index
+            let new_value = f32::from_bits(self.values[index].load(Ordering::Relaxed));
+            if let Some(param) = Param::from_index(index, new_value) {
+                apply(param);
+            }
+        }
+    }
+
+    // Only the audio thread should call this, the latest value of every changed parameter is passed on
+    pub(crate) fn apply_changes(&self, mut apply: impl FnMut(Param)) {
+        for index in 0..PARAM_COUNT {
+            if !self.changed[index].swap(false, Ordering::Acquire) {
+                continue;
+            }
+
+            // every stored value was checked by set_index
+            let new_value = f32::from_bits(self.values[index].load(Ordering::Relaxed));
+            if let Some(param) = Param::from_index(index, new_value) {
+                apply(param);
+            }
+        }
+    }
+
+    // Called by the audio thread every block, only a different tempo counts as a change
+    pub(crate) fn set_tempo(&self, tempo: f32) {
+        if tempo > 0.0 && self.tempo.swap(tempo.to_bits(), Ordering::Relaxed) != tempo.to_bits() {
+            self.tempo_changed.store(true, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn get_tempo(&self) -> f32 {
+        f32::from_bits(self.tempo.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn apply_tempo_changes(&self, apply: impl FnOnce(f32)) {
+        if self.tempo_changed.swap(false, Ordering::Acquire) {
+            apply(self.get_tempo());
+        }
+    }
+
+    pub(crate) fn get_curve_store(&self, target: CurveTarget) -> &CurveStore {
+        match target {
+            CurveTarget::Amp => &self.amp_curve,
+            CurveTarget::Pitch => &self.pitch_curve,
+        }
+    }
+
+    // None goes back to 12 tone equal temperament
+    pub(crate) fn set_note_frequencies(&self, note_frequencies: Option<[f32; MIDI_NOTE_COUNT]>) {
+        if let Some(note_frequencies) = note_frequencies {
+            for (value, frequency) in self.note_frequencies.iter().zip(note_frequencies) {
+                value.store(frequency.to_bits(), Ordering::Relaxed);
+            }
+        }
+
+        self.tuning_loaded.store(note_frequencies.is_some(), Ordering::Relaxed);
+        self.tuning_changed.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn get_note_frequencies(&self) -> Option<[f32; MIDI_NOTE_COUNT]> {
+        if !self.tuning_loaded.load(Ordering::Acquire) {
+            return None;
+        }
+
+        Some(std::array::from_fn(|note| f32::from_bits(self.note_frequencies[note].load(Ordering::Relaxed))))
+    }
+
+    // Only the audio thread should call this. A tuning loaded while it is read is picked up again on the next call,
+    // so a mix of two tunings never lasts longer than one block.
+    pub(crate) fn apply_tuning_changes(&self, apply: impl FnOnce(Option<[f32; MIDI_NOTE_COUNT]>)) {
+        if self.tuning_changed.swap(false, Ordering::Acquire) {
+            apply(self.get_note_frequencies());
+        }
+    }
+}
+
+/// Returns a handle to the parameters of the kick synth, which stays valid until `destroy_param_store` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+#[no_mangle]
+pub extern "C" fn create_param_store(kick_synth: &KickSynth) -> *const ParamStore {
+    Arc::into_raw(kick_synth.get_param_store())
+}
+
+/// # Safety
+/// `param_store` must be a handle returned by `create_param_store`, it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn destroy_param_store(param_store: *const ParamStore) {
+    assert!(!param_store.is_null());
+    drop(Arc::from_raw(param_store));
+}
+
+/// Returns the latest value set for the parameter, or `default_value` when it was never set.
+///
+/// # Safety
+/// `parameter_id` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn param_store_get(param_store: &ParamStore, parameter_id: *const c_char, default_value: f32) -> f32 {
+    match std::ffi::CStr::from_ptr(parameter_id).to_str() {
+        Ok(parameter_id) => param_store.get(parameter_id).unwrap_or(default_value),
+        Err(_) => default_value,
+    }
+}
+
+/// Can be called from any thread, the new value is used from the next processed block onwards.
+/// Returns false and ignores the new value when the parameter id or the value is invalid.
+///
+/// # Safety
+/// `parameter_id` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn param_store_update(param_store: &ParamStore, parameter_id: *const c_char, new_value: f32) -> bool {
+    match std::ffi::CStr::from_ptr(parameter_id).to_str() {
+        Ok(parameter_id) => param_store.set(parameter_id, new_value),
+        Err(_) => false,
+    }
+}
+
+/// Loads a Scala scale, and optionally a keyboard mapping, as the tuning of every note.
+/// Pass a null `kbm` to map the scale like Scala does without a .kbm file, with its root on middle C and A4 at 440 Hz.
+/// Returns false and keeps the current tuning when either file is invalid.
+///
+/// # Safety
+/// `scl` must be a valid nul terminated string, `kbm` must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn param_store_load_tuning(param_store: &ParamStore, scl: *const c_char, kbm: *const c_char) -> bool {
+    let scl = std::ffi::CStr::from_ptr(scl).to_string_lossy();
+    let kbm = match kbm.is_null() {
+        true => None,
+        false => Some(std::ffi::CStr::from_ptr(kbm).to_string_lossy()),
+    };
+
+    let Some(note_frequencies) = parse_tuning(&scl, kbm.as_deref()) else {
+        return false;
+    };
+
+    param_store.set_note_frequencies(Some(note_frequencies));
+    true
+}
+
+/// Goes back to 12 tone equal temperament, tuned by the a4_frequency parameter.
+#[no_mangle]
+pub extern "C" fn param_store_reset_tuning(param_store: &ParamStore) {
+    param_store.set_note_frequencies(None);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::param_store::ParamStore;
+    use crate::params::{Param, PitchParam};
+
+    #[test]
+    fn test_latest_value_is_applied_once() {
+        let param_store = ParamStore::new();
+
+        param_store.set("amp_release", 100.0);
+        param_store.set("amp_release", 200.0);
+
+        let mut releases = vec![];
+        param_store.apply_changes(|param| {
+            if let Param::AmpRelease(release) = param {
+                releases.push(release);
+            }
+        });
+        param_store.apply_changes(|_| panic!("Nothing changed"));
+
+        assert_eq!(releases, vec![200.0]);
+    }
+
+    #[test]
+    fn test_update_from_other_thread() {
+        let param_store = Arc::new(ParamStore::new());
+
+        let writer = param_store.clone();
+        std::thread::spawn(move || writer.set("timing_3", 16.5)).join().unwrap();
+
+        let mut applied = 0;
+        param_store.apply_changes(|param| {
+            let Param::Pitch(note_number, PitchParam::Timing(timing)) = param else {
+                panic!("Expected a pitch timing");
+            };
+            assert_eq!(note_number, 2);
+            assert_eq!(timing, 16.5);
+            applied += 1;
+        });
+
+        assert_eq!(applied, 1);
+    }
+
+    #[test]
+    fn test_tuning_is_applied_once() {
+        let param_store = ParamStore::new();
+
+        let mut note_frequencies = [0.0; 128];
+        note_frequencies[69] = 432.0;
+        param_store.set_note_frequencies(Some(note_frequencies));
+
+        let mut applied = vec![];
+        param_store.apply_tuning_changes(|tuning| applied.push(tuning.map(|tuning| tuning[69])));
+        param_store.apply_tuning_changes(|_| panic!("Nothing changed"));
+
+        param_store.set_note_frequencies(None);
+        param_store.apply_tuning_changes(|tuning| applied.push(tuning.map(|tuning| tuning[69])));
+
+        assert_eq!(applied, vec![Some(432.0), None]);
+    }
+
+    #[test]
+    fn test_invalid_value_is_rejected_by_writer() {
+        let param_store = ParamStore::new();
+        assert!(param_store.set("saturationType", 2.0));
+        assert!(!param_store.set("saturationType", 12.0));
+        assert!(!param_store.set("not_a_parameter", 1.0));
+        assert!(!param_store.set("octave_33", 1.0));
+
+        assert_eq!(param_store.get("saturationType"), Some(2.0));
+        assert_eq!(param_store.get("not_a_parameter"), None);
+    }
+}
@@ -10,87 +10,445 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::curve_envelope::EnvelopeSource;
+use crate::distortion_chain::{DistortionParam, MAX_DISTORTION_STAGES};
+use crate::multiband::{BandParam, MAX_BANDS};
+use crate::envelope::EnvelopeCurve;
+use crate::eq::{EqParam, NUM_EQ_BANDS};
+use crate::filter::{FilterMode, FilterParam, FilterPosition};
 use crate::oscillator::WaveType;
+use crate::oversampling::OversamplingFactor;
+use crate::pitch_envelope::{PitchCurve, PitchEndMode, MAX_PITCH_POINTS};
 use crate::saturation::SaturationType;
+use crate::tempo_sync::{SyncDivision, TimeParam};
 
 pub enum Param {
     Pitch(i32, PitchParam),
+    PitchPoints(usize),
+    PitchStartFrequency(f32),
+    PitchEndMode(PitchEndMode),
+    PitchReturnTime(f32),
+    FineTune(f32),
+    A4Frequency(f32),
 
     AmpAttack(f32),
     AmpDecay(f32),
     AmpSustain(f32),
     AmpRelease(f32),
     AmpExponentialFactorA(f32),
+    AmpHold(f32),
+    AmpSustainTime(f32),
+    AmpAttackCurve(EnvelopeCurve),
+    AmpAttackTension(f32),
+    AmpDecayCurve(EnvelopeCurve),
+    AmpDecayTension(f32),
+    AmpReleaseCurve(EnvelopeCurve),
+    AmpSource(EnvelopeSource),
+    PitchSource(EnvelopeSource),
+    Sync(TimeParam, SyncDivision),
+    Length(f32),
+    LengthKeepAttack(bool),
 
     Phase(f32),
     WaveType(WaveType),
 
     Drive(f32),
     SaturationType(SaturationType),
+    Oversampling(OversamplingFactor),
+    SaturationFolds(f32),
+    SaturationBias(f32),
+    BitcrushBits(f32),
+    BitcrushRate(f32),
+    SaturationAutoGain(bool),
+    SaturationOutput(f32),
+    Distortion(usize, DistortionParam),
+    DistortionStages(usize),
+    Band(usize, BandParam),
+    MultibandBands(usize),
+    MultibandLowCrossover(f32),
+    MultibandHighCrossover(f32),
+    MultibandLowClean(bool),
+    Filter(FilterParam),
+    Eq(usize, EqParam),
+    EqHighPass(bool),
+    EqHighPassFrequency(f32),
+    EqHighPassQ(f32),
+    DcBlocker(bool),
+    Subsonic(bool),
+    SubsonicFrequency(f32),
+    OutputGain(f32),
+    Limiter(bool),
+    LimiterCeiling(f32),
+    LimiterRelease(f32),
+    TransientAttack(f32),
+    TransientSustain(f32),
+    Punch(f32),
 }
 
 pub enum PitchParam {
     Octave(i32),
     Note(i32),
     Timing(f32),
+    Curve(PitchCurve),
+    Tension(f32),
+    Cents(f32),
+    Frequency(f32),
 }
 
+// Every parameter has a fixed index, which is used by the ParamStore to keep one value per parameter.
+// The pitch parameters come first, one of each per pitch envelope part, followed by the rest in this order.
+pub(crate) const PITCH_PARAM_IDS: [&str; 8] = ["octave", "note", "timing", "curve", "tension", "cents", "hz", "sync"];
+// what the pitch envelope uses for parameters that were never set
+pub(crate) const PITCH_PARAM_DEFAULTS: [f32; 8] = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+const PITCH_PARAM_COUNT: usize = PITCH_PARAM_IDS.len() * MAX_PITCH_POINTS;
+// The distortion parameters come next, one of each per stage of the distortion chain
+const DISTORTION_PARAM_IDS: [&str; 6] = [
+    "distortion_type",
+    "distortion_drive",
+    "distortion_emphasis",
+    "distortion_emphasis_frequency",
+    "distortion_output",
+    "distortion_mix",
+];
+const DISTORTION_PARAM_COUNT: usize = DISTORTION_PARAM_IDS.len() * MAX_DISTORTION_STAGES;
+// and then the ones of every band of the multiband saturation
+const BAND_PARAM_IDS: [&str; 3] = ["band_type", "band_drive", "band_level"];
+const BAND_PARAM_COUNT: usize = BAND_PARAM_IDS.len() * MAX_BANDS;
+// and the ones of every band of the EQ
+const EQ_PARAM_IDS: [&str; 3] = ["eq_frequency", "eq_gain", "eq_q"];
+const EQ_PARAM_COUNT: usize = EQ_PARAM_IDS.len() * NUM_EQ_BANDS;
+const INDEXED_PARAM_COUNT: usize = PITCH_PARAM_COUNT + DISTORTION_PARAM_COUNT + BAND_PARAM_COUNT + EQ_PARAM_COUNT;
+
+const PARAM_IDS: [&str; 68] = [
+    "pitch_points",
+    "pitch_start_frequency",
+    "pitch_end_mode",
+    "pitch_return_time",
+    "fine_tune",
+    "a4_frequency",
+    "amp_attack",
+    "amp_decay",
+    "amp_sustain",
+    "amp_release",
+    "amp_exponential_factor_a",
+    "amp_hold",
+    "amp_sustain_time",
+    "amp_attack_curve",
+    "amp_attack_tension",
+    "amp_decay_curve",
+    "amp_decay_tension",
+    "amp_release_curve",
+    "amp_source",
+    "pitch_source",
+    "pitch_return_time_sync",
+    "amp_attack_sync",
+    "amp_hold_sync",
+    "amp_decay_sync",
+    "amp_sustain_time_sync",
+    "amp_release_sync",
+    "length",
+    "length_keep_attack",
+    "phase",
+    "waveType",
+    "driveDb",
+    "saturationType",
+    "oversampling",
+    "saturation_folds",
+    "saturation_bias",
+    "bitcrush_bits",
+    "bitcrush_rate",
+    "saturation_auto_gain",
+    "saturation_output",
+    "distortion_stages",
+    "multiband_bands",
+    "multiband_low_crossover",
+    "multiband_high_crossover",
+    "multiband_low_clean",
+    "filter_mode",
+    "filter_position",
+    "filter_cutoff",
+    "filter_resonance",
+    "filter_key_tracking",
+    "filter_env_amount",
+    "filter_attack",
+    "filter_decay",
+    "filter_sustain",
+    "filter_release",
+    "filter_release_tension",
+    "eq_high_pass",
+    "eq_high_pass_frequency",
+    "eq_high_pass_q",
+    "dc_blocker",
+    "subsonic",
+    "subsonic_frequency",
+    "output_gain",
+    "limiter",
+    "limiter_ceiling",
+    "limiter_release",
+    "transient_attack",
+    "transient_sustain",
+    "punch",
+];
+
+pub(crate) const PARAM_COUNT: usize = INDEXED_PARAM_COUNT + PARAM_IDS.len();
+
 impl Param {
 
-    pub(crate) fn new(parameter_id: &str, new_value: f32) -> Self {
-        match parameter_id {
-            str if str.contains("octave") => {
-                let note_number = get_note_number(parameter_id);
-                Param::Pitch(note_number, PitchParam::Octave(new_value as i32))
-            }
+    // None when the value is invalid for the parameter
+    pub(crate) fn from_index(index: usize, new_value: f32) -> Option<Self> {
+        if index < PITCH_PARAM_COUNT {
+            let note_number = (index / PITCH_PARAM_IDS.len()) as i32;
+
+            let pitch_param = match PITCH_PARAM_IDS[index % PITCH_PARAM_IDS.len()] {
+                "octave" => PitchParam::Octave(new_value as i32),
+                "note" => PitchParam::Note(new_value as i32),
+                "timing" => PitchParam::Timing(new_value),
+                "curve" => match new_value as i32 {
+                    0 => PitchParam::Curve(PitchCurve::Linear),
+                    1 => PitchParam::Curve(PitchCurve::Exponential),
+                    2 => PitchParam::Curve(PitchCurve::Power),
+                    _ => return None,
+                },
+                "tension" => PitchParam::Tension(new_value),
+                "cents" => PitchParam::Cents(new_value),
+                "hz" => PitchParam::Frequency(new_value),
+                // the timing of the point in a musical division, which TempoSync turns into a timing
+                "sync" => return Some(Param::Sync(TimeParam::PitchTiming(note_number), SyncDivision::from_value(new_value)?)),
+                _ => return None,
+            };
+
+            return Some(Param::Pitch(note_number, pitch_param));
+        }
+
+        if index < PITCH_PARAM_COUNT + DISTORTION_PARAM_COUNT {
+            let index = index - PITCH_PARAM_COUNT;
+            let stage_number = index / DISTORTION_PARAM_IDS.len();
+
+            let distortion_param = match DISTORTION_PARAM_IDS[index % DISTORTION_PARAM_IDS.len()] {
+                "distortion_type" => DistortionParam::Type(get_saturation_type(new_value)?),
+                "distortion_drive" => DistortionParam::Drive(new_value),
+                "distortion_emphasis" => DistortionParam::Emphasis(new_value),
+                "distortion_emphasis_frequency" => DistortionParam::EmphasisFrequency(new_value),
+                "distortion_output" => DistortionParam::Output(new_value),
+                "distortion_mix" => DistortionParam::Mix(new_value),
+                _ => return None,
+            };
 
-            str if str.contains("note") => {
-                let note_number = get_note_number(parameter_id);
-                Param::Pitch(note_number, PitchParam::Note(new_value as i32))
-            }
+            return Some(Param::Distortion(stage_number, distortion_param));
+        }
+
+        if index < PITCH_PARAM_COUNT + DISTORTION_PARAM_COUNT + BAND_PARAM_COUNT {
+            let index = index - PITCH_PARAM_COUNT - DISTORTION_PARAM_COUNT;
+            let band_number = index / BAND_PARAM_IDS.len();
 
-            str if str.contains("timing") => {
-                let note_number = get_note_number(parameter_id);
-                Param::Pitch(note_number, PitchParam::Timing(new_value))
-            }
+            let band_param = match BAND_PARAM_IDS[index % BAND_PARAM_IDS.len()] {
+                "band_type" => BandParam::Type(get_saturation_type(new_value)?),
+                "band_drive" => BandParam::Drive(new_value),
+                "band_level" => BandParam::Level(new_value),
+                _ => return None,
+            };
+
+            return Some(Param::Band(band_number, band_param));
+        }
+
+        if index < INDEXED_PARAM_COUNT {
+            let index = index - PITCH_PARAM_COUNT - DISTORTION_PARAM_COUNT - BAND_PARAM_COUNT;
+            let band_number = index / EQ_PARAM_IDS.len();
+
+            let eq_param = match EQ_PARAM_IDS[index % EQ_PARAM_IDS.len()] {
+                "eq_frequency" => EqParam::Frequency(new_value),
+                "eq_gain" => EqParam::Gain(new_value),
+                "eq_q" => EqParam::Q(new_value),
+                _ => return None,
+            };
+
+            return Some(Param::Eq(band_number, eq_param));
+        }
+
+        let param = match PARAM_IDS[index - INDEXED_PARAM_COUNT] {
+            "pitch_points" => Param::PitchPoints(new_value as usize),
+            "pitch_start_frequency" => Param::PitchStartFrequency(new_value),
+            "pitch_end_mode" => match new_value as i32 {
+                0 => Param::PitchEndMode(PitchEndMode::Hold),
+                1 => Param::PitchEndMode(PitchEndMode::ReturnToRoot),
+                2 => Param::PitchEndMode(PitchEndMode::Glide),
+                _ => return None,
+            },
+            "pitch_return_time" => Param::PitchReturnTime(new_value),
+            "fine_tune" => Param::FineTune(new_value),
+            "a4_frequency" => Param::A4Frequency(new_value),
 
             "amp_attack" => Param::AmpAttack(new_value),
             "amp_decay" => Param::AmpDecay(new_value),
             "amp_sustain" => Param::AmpSustain(new_value),
             "amp_release" => Param::AmpRelease(new_value),
             "amp_exponential_factor_a" => Param::AmpExponentialFactorA(new_value),
+            "amp_hold" => Param::AmpHold(new_value),
+            "amp_sustain_time" => Param::AmpSustainTime(new_value),
+            "amp_attack_curve" => Param::AmpAttackCurve(get_envelope_curve(new_value)?),
+            "amp_attack_tension" => Param::AmpAttackTension(new_value),
+            "amp_decay_curve" => Param::AmpDecayCurve(get_envelope_curve(new_value)?),
+            "amp_decay_tension" => Param::AmpDecayTension(new_value),
+            "amp_release_curve" => Param::AmpReleaseCurve(get_envelope_curve(new_value)?),
+            "amp_source" => Param::AmpSource(get_envelope_source(new_value)?),
+            "pitch_source" => Param::PitchSource(get_envelope_source(new_value)?),
+            "pitch_return_time_sync" => Param::Sync(TimeParam::PitchReturnTime, SyncDivision::from_value(new_value)?),
+            "amp_attack_sync" => Param::Sync(TimeParam::AmpAttack, SyncDivision::from_value(new_value)?),
+            "amp_hold_sync" => Param::Sync(TimeParam::AmpHold, SyncDivision::from_value(new_value)?),
+            "amp_decay_sync" => Param::Sync(TimeParam::AmpDecay, SyncDivision::from_value(new_value)?),
+            "amp_sustain_time_sync" => Param::Sync(TimeParam::AmpSustainTime, SyncDivision::from_value(new_value)?),
+            "amp_release_sync" => Param::Sync(TimeParam::AmpRelease, SyncDivision::from_value(new_value)?),
+            // in percent of the times that were set
+            "length" => Param::Length(new_value / 100.0),
+            "length_keep_attack" => Param::LengthKeepAttack(new_value >= 0.5),
 
             "phase" => Param::Phase(new_value),
             "waveType" =>
                 match new_value as i32 {
                     0 => Param::WaveType(WaveType::Sine),
                     1 => Param::WaveType(WaveType::Wave909),
-                    _ => {
-                        panic!("Invalid wave type: {}", new_value);
-                    }
+                    _ => return None,
                 },
 
             "driveDb" => Param::Drive(new_value),
-            "saturationType" => {
-                match new_value as i32 {
-                    0 => Param::SaturationType(SaturationType::None),
-                    1 => Param::SaturationType(SaturationType::Soft),
-                    2 => Param::SaturationType(SaturationType::Clip),
-                    3 => Param::SaturationType(SaturationType::ExtremeClip),
-                    _ => {
-                        panic!("Invalid saturation type: {}", new_value);
-                    }
-                }
-            }
-            _ => {
-                panic!("Invalid parameter id: {}", parameter_id);
-            }
+            "saturationType" => Param::SaturationType(get_saturation_type(new_value)?),
+            "oversampling" => Param::Oversampling(OversamplingFactor::from_value(new_value)?),
+            "saturation_folds" => Param::SaturationFolds(new_value),
+            "saturation_bias" => Param::SaturationBias(new_value),
+            "bitcrush_bits" => Param::BitcrushBits(new_value),
+            "bitcrush_rate" => Param::BitcrushRate(new_value),
+            "saturation_auto_gain" => Param::SaturationAutoGain(new_value >= 0.5),
+            "saturation_output" => Param::SaturationOutput(new_value),
+            "distortion_stages" => match new_value as usize {
+                num_stages if num_stages <= MAX_DISTORTION_STAGES => Param::DistortionStages(num_stages),
+                _ => return None,
+            },
+            "multiband_bands" => match new_value as usize {
+                num_bands if (1..=MAX_BANDS).contains(&num_bands) => Param::MultibandBands(num_bands),
+                _ => return None,
+            },
+            "multiband_low_crossover" => Param::MultibandLowCrossover(new_value),
+            "multiband_high_crossover" => Param::MultibandHighCrossover(new_value),
+            "multiband_low_clean" => Param::MultibandLowClean(new_value >= 0.5),
+            "filter_mode" => Param::Filter(FilterParam::Mode(match new_value as i32 {
+                0 => FilterMode::Off,
+                1 => FilterMode::LowPass,
+                2 => FilterMode::HighPass,
+                3 => FilterMode::BandPass,
+                4 => FilterMode::Notch,
+                _ => return None,
+            })),
+            "filter_position" => Param::Filter(FilterParam::Position(match new_value as i32 {
+                0 => FilterPosition::PreSaturation,
+                1 => FilterPosition::PostSaturation,
+                _ => return None,
+            })),
+            "filter_cutoff" => Param::Filter(FilterParam::Cutoff(new_value)),
+            "filter_resonance" => Param::Filter(FilterParam::Resonance(new_value)),
+            // in percent, 100 moves the cutoff an octave with every octave of the note
+            "filter_key_tracking" => Param::Filter(FilterParam::KeyTracking(new_value / 100.0)),
+            // in octaves
+            "filter_env_amount" => Param::Filter(FilterParam::EnvelopeAmount(new_value)),
+            "filter_attack" => Param::Filter(FilterParam::Attack(new_value)),
+            "filter_decay" => Param::Filter(FilterParam::Decay(new_value)),
+            "filter_sustain" => Param::Filter(FilterParam::Sustain(new_value)),
+            "filter_release" => Param::Filter(FilterParam::Release(new_value)),
+            "filter_release_tension" => Param::Filter(FilterParam::ReleaseTension(new_value)),
+            "eq_high_pass" => Param::EqHighPass(new_value >= 0.5),
+            "eq_high_pass_frequency" => Param::EqHighPassFrequency(new_value),
+            "eq_high_pass_q" => Param::EqHighPassQ(new_value),
+            "dc_blocker" => Param::DcBlocker(new_value >= 0.5),
+            "subsonic" => Param::Subsonic(new_value >= 0.5),
+            "subsonic_frequency" => Param::SubsonicFrequency(new_value),
+            "output_gain" => Param::OutputGain(new_value),
+            "limiter" => Param::Limiter(new_value >= 0.5),
+            "limiter_ceiling" => Param::LimiterCeiling(new_value),
+            "limiter_release" => Param::LimiterRelease(new_value),
+            "transient_attack" => Param::TransientAttack(new_value),
+            "transient_sustain" => Param::TransientSustain(new_value),
+            // in percent
+            "punch" => Param::Punch(new_value / 100.0),
+            _ => return None,
+        };
+
+        Some(param)
+    }
+}
+
+fn get_saturation_type(new_value: f32) -> Option<SaturationType> {
+    match new_value as i32 {
+        0 => Some(SaturationType::None),
+        1 => Some(SaturationType::Soft),
+        2 => Some(SaturationType::Clip),
+        3 => Some(SaturationType::ExtremeClip),
+        4 => Some(SaturationType::Wavefolder),
+        5 => Some(SaturationType::Tube),
+        6 => Some(SaturationType::Diode),
+        7 => Some(SaturationType::SineShaper),
+        8 => Some(SaturationType::Bitcrush),
+        _ => None,
+    }
+}
+
+fn get_envelope_curve(new_value: f32) -> Option<EnvelopeCurve> {
+    match new_value as i32 {
+        0 => Some(EnvelopeCurve::Linear),
+        1 => Some(EnvelopeCurve::Exponential),
+        2 => Some(EnvelopeCurve::Power),
+        _ => None,
+    }
+}
+
+fn get_envelope_source(new_value: f32) -> Option<EnvelopeSource> {
+    match new_value as i32 {
+        0 => Some(EnvelopeSource::Envelope),
+        1 => Some(EnvelopeSource::Curve),
+        _ => None,
+    }
+}
+
+// None when there is no parameter with this id
+pub(crate) fn get_param_index(parameter_id: &str) -> Option<usize> {
+    if let Some(position) = PARAM_IDS.iter().position(|id| *id == parameter_id) {
+        return Some(INDEXED_PARAM_COUNT + position);
+    }
+
+    // pitch, distortion, band and EQ parameters look like octave_12 and distortion_drive_2, numbered from 1
+    let (param_id, number) = parameter_id.rsplit_once('_')?;
+
+    let number = match number.parse::<usize>() {
+        Ok(number) if number >= 1 => number - 1,
+        _ => return None,
+    };
+
+    if let Some(field) = PITCH_PARAM_IDS.iter().position(|id| *id == param_id) {
+        if number < MAX_PITCH_POINTS {
+            return Some(get_pitch_param_index(number, field));
+        }
+    }
+
+    if let Some(field) = DISTORTION_PARAM_IDS.iter().position(|id| *id == param_id) {
+        if number < MAX_DISTORTION_STAGES {
+            return Some(PITCH_PARAM_COUNT + number * DISTORTION_PARAM_IDS.len() + field);
         }
     }
+
+    if let Some(field) = BAND_PARAM_IDS.iter().position(|id| *id == param_id) {
+        if number < MAX_BANDS {
+            return Some(PITCH_PARAM_COUNT + DISTORTION_PARAM_COUNT + number * BAND_PARAM_IDS.len() + field);
+        }
+    }
+
+    if let Some(field) = EQ_PARAM_IDS.iter().position(|id| *id == param_id) {
+        if number < NUM_EQ_BANDS {
+            return Some(PITCH_PARAM_COUNT + DISTORTION_PARAM_COUNT + BAND_PARAM_COUNT + number * EQ_PARAM_IDS.len() + field);
+        }
+    }
+
+    None
 }
 
-fn get_note_number(parameter_id: &str) -> i32 {
-    let note_number = parameter_id.chars().last().unwrap().to_digit(10).unwrap() as i32;
-    let note_number = note_number - 1;
-    note_number
+// field is the position of the parameter in PITCH_PARAM_IDS
+pub(crate) fn get_pitch_param_index(note_number: usize, field: usize) -> usize {
+    note_number * PITCH_PARAM_IDS.len() + field
 }
@@ -10,8 +10,32 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::midi_message::{get_midi_frequency_from_pitch, get_midi_note_from_octave_and_note_number};
+use crate::midi_message::get_midi_note_from_octave_and_note_number;
 use crate::params::PitchParam;
+use crate::tuning::{Tuning, MIDI_NOTE_COUNT};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PitchCurve {
+    // straight line in Hz
+    Linear,
+    // straight line in semitones
+    Exponential,
+    // bent like the amp release, a tension above 1 moves most of the sweep to the start of the segment
+    Power,
+}
+
+// What the frequency does after the last pitch point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PitchEndMode {
+    Hold,
+    // glide to the frequency of the played midi note, in pitch_return_time
+    ReturnToRoot,
+    // keep going at the slope the last step ended with
+    Glide,
+}
+
+// the lowest frequency a glide can reach, below this the oscillator would practically stand still
+const MIN_GLIDE_FREQUENCY: f32 = 1.0;
 
 #[derive(Clone, Copy, Debug)]
 struct PitchEnvelopePart {
@@ -19,18 +43,44 @@ struct PitchEnvelopePart {
     octave: i32,
     note: i32,
     timing: usize,
+    // the timing before the length macro, timing is 0 until it is set
+    timing_ms: f32,
+    curve: PitchCurve,
+    tension: f32,
+    cents: f32,
+    // a fixed frequency in Hz instead of octave, note and cents, 0 when unused
+    hz: f32,
 }
 
 
 impl PitchEnvelopePart {
-    pub(crate) fn recalculate_frequency(&mut self) {
+    pub(crate) fn recalculate_frequency(&mut self, tuning: &Tuning) {
+        if self.hz > 0.0 {
+            self.frequency = self.hz;
+            return;
+        }
+
         let octave = self.octave;
         let note = self.note;
 
         // println!("octave: {}, note: {}", octave, note);
 
         let midi_note_number = get_midi_note_from_octave_and_note_number(octave, note);
-        self.frequency = get_midi_frequency_from_pitch(midi_note_number as u8);
+        self.frequency = tuning.get_frequency(midi_note_number as f32 + self.cents / 100.0);
+    }
+
+    // Frequency at timing_fraction of the way from the previous part to this part
+    fn interpolate_from(&self, previous_frequency: f32, timing_fraction: f32) -> f32 {
+        match self.curve {
+            PitchCurve::Exponential if previous_frequency > 0.0 && self.frequency > 0.0 => {
+                previous_frequency * (self.frequency / previous_frequency).powf(timing_fraction)
+            }
+            PitchCurve::Power => {
+                let remaining = (1.0 - timing_fraction).powf(self.tension);
+                self.frequency + (previous_frequency - self.frequency) * remaining
+            }
+            _ => previous_frequency + (self.frequency - previous_frequency) * timing_fraction,
+        }
     }
 }
 
@@ -41,29 +91,53 @@ impl Default for PitchEnvelopePart {
             octave: 0,
             note: 0,
             timing: 0,
+            timing_ms: 0.0,
+            curve: PitchCurve::Linear,
+            tension: 1.0,
+            cents: 0.0,
+            hz: 0.0,
         }
     }
 }
 
-const PITCH_ENVELOPE_SIZE: usize = 4;
+pub(crate) const MAX_PITCH_POINTS: usize = 32;
+// the number of points before pitch_points existed, so older presets sound the same
+pub(crate) const DEFAULT_PITCH_POINTS: usize = 4;
+// the frequency every note used to start from before pitch_start_frequency existed
+const DEFAULT_START_FREQUENCY: f32 = 20.0;
+// C2, the root when no note was played yet
+const DEFAULT_ROOT_NOTE: u8 = 36;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PitchEnvelope {
     sample_rate: usize,
     current_sample: usize,
-    pitches: [(i32, PitchEnvelopePart); PITCH_ENVELOPE_SIZE],
+    pitches: [(i32, PitchEnvelopePart); MAX_PITCH_POINTS],
+    num_points: usize,
     previous_pitch: PitchEnvelopePart,
     next_pitch: PitchEnvelopePart,
     previous_total_timing: usize,
     current_note_number: i32,
+    start_frequency: f32,
+    end_mode: PitchEndMode,
+    return_timing: usize,
+    return_time_ms: f32,
+    // scales every timing, see set_length
+    length: f32,
+    keep_attack: bool,
+    root_frequency: f32,
+    tuning: Tuning,
+    // frequency the last step started from, needed to keep gliding at its slope
+    last_step_start_frequency: f32,
 }
 
 impl PitchEnvelope {
     pub(crate) fn new(sample_rate: usize) -> Self {
-        let mut pitches = [(0, Default::default()); PITCH_ENVELOPE_SIZE];
+        let mut pitches = [(0, Default::default()); MAX_PITCH_POINTS];
+        let tuning = Tuning::new();
 
-        for i in 0..PITCH_ENVELOPE_SIZE {
-            pitches[i].0 = i as i32;
+        for (i, pitch) in pitches.iter_mut().enumerate() {
+            pitch.0 = i as i32;
         }
 
 
@@ -71,10 +145,20 @@ impl PitchEnvelope {
             sample_rate,
             current_sample: 0,
             pitches,
+            num_points: DEFAULT_PITCH_POINTS,
             previous_pitch: Default::default(),
             next_pitch: Default::default(),
             previous_total_timing: 0,
             current_note_number: 0, // note number is the index of which pitch envelope part we are currently in the pitches array
+            start_frequency: DEFAULT_START_FREQUENCY,
+            end_mode: PitchEndMode::Hold,
+            return_timing: 1,
+            return_time_ms: 0.0,
+            length: 1.0,
+            keep_attack: false,
+            root_frequency: tuning.get_frequency(DEFAULT_ROOT_NOTE as f32),
+            tuning,
+            last_step_start_frequency: DEFAULT_START_FREQUENCY,
         }
     }
 }
@@ -84,13 +168,104 @@ impl PitchEnvelope {
         self.current_sample = 0;
         self.current_note_number = 0;
         self.previous_total_timing = 0;
-        self.previous_pitch = Default::default();
+        self.previous_pitch = PitchEnvelopePart {
+            frequency: self.start_frequency,
+            ..Default::default()
+        };
+        self.last_step_start_frequency = self.start_frequency;
         self.next_pitch = self.pitches[0].1;
     }
 
     pub(crate) fn note_off(&self) {
         // do nothing when note off
     }
+
+    // index of the pitch envelope part we are currently moving towards, equal to the number of points once all parts are done
+    pub(crate) fn get_current_segment(&self) -> i32 {
+        self.current_note_number.min(self.num_points as i32)
+    }
+
+    pub(crate) fn set_num_points(&mut self, num_points: usize) {
+        self.num_points = num_points.clamp(1, MAX_PITCH_POINTS);
+    }
+
+    pub(crate) fn set_start_frequency(&mut self, start_frequency: f32) {
+        self.start_frequency = start_frequency.max(MIN_GLIDE_FREQUENCY);
+    }
+
+    pub(crate) fn set_end_mode(&mut self, end_mode: PitchEndMode) {
+        self.end_mode = end_mode;
+    }
+
+    pub(crate) fn set_return_time(&mut self, return_time_ms: f32) {
+        self.return_time_ms = return_time_ms;
+        self.return_timing = self.convert_ms_to_samples(return_time_ms * self.length);
+    }
+
+    // never returns 0, a step needs at least one sample
+    fn convert_ms_to_samples(&self, ms: f32) -> usize {
+        ((ms / 1000.0 * self.sample_rate as f32) as usize).max(1)
+    }
+
+    // The first step is the attack of the kick, which keep_attack leaves at its own timing
+    fn get_scaled_timing(&self, note_number: usize, timing_ms: f32) -> usize {
+        match note_number == 0 && self.keep_attack {
+            true => self.convert_ms_to_samples(timing_ms),
+            false => self.convert_ms_to_samples(timing_ms * self.length),
+        }
+    }
+
+    // Scales every timing by length, without touching the timings that were set
+    pub(crate) fn set_length(&mut self, length: f32) {
+        self.length = length.max(0.01);
+        self.update_timings();
+    }
+
+    pub(crate) fn set_keep_attack(&mut self, keep_attack: bool) {
+        self.keep_attack = keep_attack;
+        self.update_timings();
+    }
+
+    fn update_timings(&mut self) {
+        for note_number in 0..MAX_PITCH_POINTS {
+            let pitch_envelope_part = self.pitches[note_number].1;
+            if pitch_envelope_part.timing > 0 {
+                self.pitches[note_number].1.timing = self.get_scaled_timing(note_number, pitch_envelope_part.timing_ms);
+            }
+        }
+
+        self.set_return_time(self.return_time_ms);
+    }
+
+    pub(crate) fn get_tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    // the played note, used by PitchEndMode::ReturnToRoot
+    pub(crate) fn set_root_note(&mut self, root_note: u8) {
+        self.root_frequency = self.tuning.get_frequency(root_note as f32);
+    }
+
+    pub(crate) fn set_a4_frequency(&mut self, a4_frequency: f32) {
+        self.tuning.set_a4_frequency(a4_frequency);
+        self.recalculate_frequencies();
+    }
+
+    pub(crate) fn set_fine_tune(&mut self, fine_tune: f32) {
+        self.tuning.set_fine_tune(fine_tune);
+        self.recalculate_frequencies();
+    }
+
+    pub(crate) fn set_note_frequencies(&mut self, note_frequencies: Option<[f32; MIDI_NOTE_COUNT]>) {
+        self.tuning.set_note_frequencies(note_frequencies);
+        self.recalculate_frequencies();
+    }
+
+    fn recalculate_frequencies(&mut self) {
+        for pitch in self.pitches.iter_mut() {
+            pitch.1.recalculate_frequency(&self.tuning);
+        }
+    }
 }
 
 impl PitchEnvelope {
@@ -106,23 +281,31 @@ impl PitchEnvelope {
 
             },
             PitchParam::Timing(timing) => {
-                let mut timing = (timing / 1000.0 * self.sample_rate as f32) as usize;
-                if timing == 0 {
-                    timing = 1;
-                }
-
-
-                pitch_envelope_part.timing = timing;
+                pitch_envelope_part.timing_ms = timing;
+                pitch_envelope_part.timing = self.get_scaled_timing(note_number as usize, timing);
+            },
+            PitchParam::Curve(curve) => {
+                pitch_envelope_part.curve = curve;
+            },
+            PitchParam::Tension(tension) => {
+                pitch_envelope_part.tension = tension.max(0.01);
+            },
+            PitchParam::Cents(cents) => {
+                pitch_envelope_part.cents = cents;
+            },
+            PitchParam::Frequency(hz) => {
+                pitch_envelope_part.hz = hz.max(0.0);
             },
         }
 
-        pitch_envelope_part.recalculate_frequency();
+        pitch_envelope_part.recalculate_frequency(&self.tuning);
 
         // println!("{:?}", self);
 
         self.pitches[note_number as usize].1 = pitch_envelope_part;
     }
 
+    #[cfg(test)]
     pub(crate) fn set_frequency(&mut self, note_number: i32, frequency: f32) {
         let mut pitch_envelope_part = self.pitches[note_number as usize].1;
 
@@ -133,20 +316,42 @@ impl PitchEnvelope {
 
 
 
+    // Renders the frequency curve of the next note, using the same get_frequency as the audio thread.
+    // Output points are samples_per_point samples apart.
+    pub(crate) fn render_curve(&self, output: &mut [f32], samples_per_point: usize) {
+        let mut pitch_envelope = self.clone();
+        pitch_envelope.note_on();
+
+        for x in output.iter_mut() {
+            *x = pitch_envelope.get_frequency();
+
+            for _ in 1..samples_per_point {
+                pitch_envelope.get_frequency();
+            }
+        }
+    }
+
     pub(crate) fn get_frequency(&mut self) -> f32 {
+        if self.current_note_number >= self.num_points as i32 {
+            let frequency = self.get_end_frequency();
+            self.current_sample += 1;
+            return frequency;
+        }
+
         let current_sample_delta = self.current_sample - self.previous_total_timing;
         let timing_fraction = match self.next_pitch.timing {
             0 => 1.0,
-            _ => current_sample_delta as f32 / self.next_pitch.timing as f32,
+            _ => (current_sample_delta as f32 / self.next_pitch.timing as f32).min(1.0),
         };
 
-        let frequency = self.previous_pitch.frequency + (self.next_pitch.frequency - self.previous_pitch.frequency) * timing_fraction;
+        let frequency = self.next_pitch.interpolate_from(self.previous_pitch.frequency, timing_fraction);
 
 
         if self.current_sample == self.next_pitch.timing + self.previous_total_timing {
+            self.last_step_start_frequency = self.previous_pitch.frequency;
             self.previous_pitch = self.next_pitch;
 
-            if self.current_note_number + 1 < self.pitches.len() as i32 {
+            if self.current_note_number + 1 < self.num_points as i32 {
                 self.next_pitch = self.pitches[self.current_note_number as usize + 1].1;
             }
 
@@ -162,13 +367,37 @@ impl PitchEnvelope {
 
     }
 
+    fn get_end_frequency(&self) -> f32 {
+        let last_pitch = self.previous_pitch;
+        let samples_since_end = self.current_sample - self.previous_total_timing;
+
+        match self.end_mode {
+            PitchEndMode::Hold => last_pitch.frequency,
+            PitchEndMode::ReturnToRoot => {
+                let return_fraction = (samples_since_end as f32 / self.return_timing as f32).min(1.0);
+                last_pitch.frequency * (self.root_frequency / last_pitch.frequency).powf(return_fraction)
+            }
+            PitchEndMode::Glide => {
+                // the power curve always flattens out at the end of a step, so there is no slope to keep
+                if last_pitch.curve == PitchCurve::Power || last_pitch.timing == 0 {
+                    return last_pitch.frequency;
+                }
+
+                let timing_fraction = 1.0 + samples_since_end as f32 / last_pitch.timing as f32;
+                let frequency = last_pitch.interpolate_from(self.last_step_start_frequency, timing_fraction);
+                frequency.clamp(MIN_GLIDE_FREQUENCY, self.sample_rate as f32 / 2.0)
+            }
+        }
+    }
+
 }
 
 
 #[cfg(test)]
 mod test {
+    use assert_approx_eq::assert_approx_eq;
     use crate::params::PitchParam;
-    use crate::pitch_envelope::PitchEnvelope;
+    use crate::pitch_envelope::{PitchCurve, PitchEndMode, PitchEnvelope};
 
     #[test]
     fn test_get_frequency_0() {
@@ -225,4 +454,183 @@ mod test {
         }
 
     }
+
+    fn get_frequency_at(pitch_envelope: &mut PitchEnvelope, sample: usize) -> f32 {
+        pitch_envelope.note_on();
+
+        for _ in 0..sample {
+            pitch_envelope.get_frequency();
+        }
+
+        pitch_envelope.get_frequency()
+    }
+
+    #[test]
+    fn test_exponential_curve() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 800.0);
+
+        pitch_envelope.set_pitch(1, PitchParam::Timing(10.0));
+        pitch_envelope.set_pitch(1, PitchParam::Curve(PitchCurve::Exponential));
+        pitch_envelope.set_frequency(1, 50.0);
+
+        // 800 Hz to 50 Hz is four octaves, so halfway through the segment is two octaves down
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 15), 200.0, 0.01);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 20), 50.0, 0.01);
+    }
+
+    #[test]
+    fn test_power_curve() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 500.0);
+
+        pitch_envelope.set_pitch(1, PitchParam::Timing(10.0));
+        pitch_envelope.set_pitch(1, PitchParam::Curve(PitchCurve::Power));
+        pitch_envelope.set_pitch(1, PitchParam::Tension(2.0));
+        pitch_envelope.set_frequency(1, 100.0);
+
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 15), 100.0 + 400.0 * 0.25, 0.01);
+
+        pitch_envelope.set_pitch(1, PitchParam::Tension(1.0));
+        pitch_envelope.set_frequency(1, 100.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 15), 300.0, 0.01);
+    }
+
+    #[test]
+    fn test_more_than_four_points() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(6);
+
+        for note_number in 0..6 {
+            pitch_envelope.set_pitch(note_number, PitchParam::Timing(10.0));
+            pitch_envelope.set_frequency(note_number, 100.0 * (note_number + 1) as f32);
+        }
+
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 45), 450.0, 0.01);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 100), 600.0, 0.01);
+
+        pitch_envelope.set_num_points(2);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 100), 200.0, 0.01);
+    }
+
+    #[test]
+    fn test_start_frequency() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_start_frequency(1000.0);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 100.0);
+
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 0), 1000.0, 0.01);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 550.0, 0.01);
+    }
+
+    #[test]
+    fn test_end_modes() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(2);
+        // A2 at 50 Hz
+        pitch_envelope.set_a4_frequency(200.0);
+        pitch_envelope.set_root_note(45);
+        pitch_envelope.set_return_time(10.0);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 400.0);
+        pitch_envelope.set_pitch(1, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(1, 200.0);
+
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 25), 200.0, 0.01);
+
+        // an octave down to 100 Hz is halfway in semitones to the 50 Hz root
+        pitch_envelope.set_end_mode(PitchEndMode::ReturnToRoot);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 25), 100.0, 0.01);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 100), 50.0, 0.01);
+
+        // the last step drops 20 Hz per sample
+        pitch_envelope.set_end_mode(PitchEndMode::Glide);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 25), 100.0, 0.01);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 100), 1.0, 0.01);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(2);
+        pitch_envelope.set_start_frequency(500.0);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 500.0);
+        pitch_envelope.set_pitch(1, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(1, 100.0);
+
+        pitch_envelope.set_length(2.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 30), 300.0, 0.01);
+
+        pitch_envelope.set_keep_attack(true);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 20), 300.0, 0.01);
+
+        // timings set after the length are scaled as well
+        pitch_envelope.set_pitch(1, PitchParam::Timing(20.0));
+        pitch_envelope.set_frequency(1, 100.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 30), 300.0, 0.01);
+
+        pitch_envelope.set_length(1.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 20), 300.0, 0.01);
+    }
+
+    #[test]
+    fn test_power_curve_after_last_point() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(1);
+
+        pitch_envelope.set_pitch(0, PitchParam::Curve(PitchCurve::Power));
+        pitch_envelope.set_pitch(0, PitchParam::Tension(2.5));
+        pitch_envelope.set_pitch(0, PitchParam::Timing(10.0));
+        pitch_envelope.set_frequency(0, 100.0);
+
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 50), 100.0, 0.01);
+
+        pitch_envelope.set_end_mode(PitchEndMode::Glide);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 50), 100.0, 0.01);
+    }
+
+    #[test]
+    fn test_cents_and_fine_tune() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(1);
+
+        // A4
+        pitch_envelope.set_pitch(0, PitchParam::Octave(4));
+        pitch_envelope.set_pitch(0, PitchParam::Note(9));
+        pitch_envelope.set_pitch(0, PitchParam::Timing(1.0));
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 440.0, 0.01);
+
+        pitch_envelope.set_pitch(0, PitchParam::Cents(-50.0));
+        pitch_envelope.set_fine_tune(150.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 466.16, 0.01);
+
+        pitch_envelope.set_a4_frequency(432.0);
+        pitch_envelope.set_fine_tune(0.0);
+        pitch_envelope.set_pitch(0, PitchParam::Cents(0.0));
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 432.0, 0.01);
+    }
+
+    #[test]
+    fn test_frequency_point_ignores_tuning() {
+        let mut pitch_envelope = PitchEnvelope::new(1000);
+        pitch_envelope.set_num_points(1);
+
+        pitch_envelope.set_pitch(0, PitchParam::Timing(1.0));
+        pitch_envelope.set_pitch(0, PitchParam::Frequency(47.5));
+        pitch_envelope.set_fine_tune(100.0);
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 47.5, 0.001);
+
+        // back to octave and note, C0 a semitone up
+        pitch_envelope.set_pitch(0, PitchParam::Frequency(0.0));
+        assert_approx_eq!(get_frequency_at(&mut pitch_envelope, 5), 17.32, 0.01);
+    }
 }
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::ffi::{c_char, c_void, CString};
+
+use crate::param_store::ParamStore;
+use crate::params::{get_pitch_param_index, Param, PITCH_PARAM_DEFAULTS, PITCH_PARAM_IDS};
+use crate::pitch_envelope::{DEFAULT_PITCH_POINTS, MAX_PITCH_POINTS};
+
+// Editing the breakpoints of the pitch envelope as a whole, on top of the octave_N, note_N, ... parameters.
+// A point list is serialized as "octave,note,timing,curve,tension,cents,hz,sync", with the points separated by ';'.
+// The points are read from the store, but the edits are passed back as parameter changes, so the plugin sets them
+// on its parameters like any other change and they end up in the store, the preset and the undo history from there.
+
+type PitchPoint = [f32; PITCH_PARAM_IDS.len()];
+
+fn get_num_points(param_store: &ParamStore) -> usize {
+    let num_points = param_store.get("pitch_points").map_or(DEFAULT_PITCH_POINTS, |num_points| num_points as usize);
+    num_points.clamp(1, MAX_PITCH_POINTS)
+}
+
+fn get_point(param_store: &ParamStore, note_number: usize) -> PitchPoint {
+    std::array::from_fn(|field| {
+        param_store.get_index(get_pitch_param_index(note_number, field)).unwrap_or(PITCH_PARAM_DEFAULTS[field])
+    })
+}
+
+fn get_points(param_store: &ParamStore) -> Vec<PitchPoint> {
+    (0..get_num_points(param_store)).map(|note_number| get_point(param_store, note_number)).collect()
+}
+
+// Passes every field of every point and the number of points, the points after them are left alone
+fn set_points(points: &[PitchPoint], mut set_param: impl FnMut(&str, f32)) {
+    for (note_number, point) in points.iter().enumerate() {
+        for (param_id, value) in PITCH_PARAM_IDS.iter().zip(point) {
+            set_param(&format!("{}_{}", param_id, note_number + 1), *value);
+        }
+    }
+    set_param("pitch_points", points.len() as f32);
+}
+
+// Missing fields at the end get their default, so "4,7,12.5" is a valid point
+fn parse_point(serialized_point: &str) -> Option<PitchPoint> {
+    let mut point = PITCH_PARAM_DEFAULTS;
+    let mut fields = serialized_point.split(',');
+
+    for value in point.iter_mut() {
+        match fields.next().map(str::trim) {
+            Some(field) if !field.is_empty() => *value = field.parse().ok()?,
+            _ => break,
+        }
+    }
+
+    // every field has to be a valid value of its parameter, curves and sync divisions are a choice and have to be whole
+    let is_valid = |(field, value): (usize, &f32)| {
+        let is_choice = matches!(PITCH_PARAM_IDS[field], "curve" | "sync");
+        (!is_choice || value.fract() == 0.0) && Param::from_index(get_pitch_param_index(0, field), *value).is_some()
+    };
+    if fields.next().is_some() || !point.iter().enumerate().all(is_valid) {
+        return None;
+    }
+
+    Some(point)
+}
+
+pub(crate) fn serialize_points(param_store: &ParamStore) -> String {
+    get_points(param_store).iter()
+        .map(|point| {
+            point.iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// Replaces every point, passes nothing when any point is invalid
+pub(crate) fn deserialize_points(serialized_points: &str, set_param: impl FnMut(&str, f32)) -> bool {
+    let points: Option<Vec<PitchPoint>> = serialized_points.split(';')
+        .filter(|serialized_point| !serialized_point.trim().is_empty())
+        .map(parse_point)
+        .collect();
+
+    let Some(points) = points else {
+        return false;
+    };
+
+    if points.is_empty() || points.len() > MAX_PITCH_POINTS {
+        return false;
+    }
+
+    set_points(&points, set_param);
+    true
+}
+
+// The point ends up at note_number, the points after it move one place up
+pub(crate) fn insert_point(param_store: &ParamStore, note_number: usize, serialized_point: &str, set_param: impl FnMut(&str, f32)) -> bool {
+    let mut points = get_points(param_store);
+
+    let Some(point) = parse_point(serialized_point) else {
+        return false;
+    };
+
+    if points.len() == MAX_PITCH_POINTS || note_number > points.len() {
+        return false;
+    }
+
+    points.insert(note_number, point);
+    set_points(&points, set_param);
+    true
+}
+
+// The points after note_number move one place down, the last point can't be removed
+pub(crate) fn remove_point(param_store: &ParamStore, note_number: usize, set_param: impl FnMut(&str, f32)) -> bool {
+    let mut points = get_points(param_store);
+
+    if points.len() == 1 || note_number >= points.len() {
+        return false;
+    }
+
+    points.remove(note_number);
+    set_points(&points, set_param);
+    true
+}
+
+// Calls the callback of the plugin for every parameter change, with the parameter id as a nul terminated string
+fn with_callback(
+    set_param: extern "C" fn(parameter_id: *const c_char, new_value: f32, user_data: *mut c_void),
+    user_data: *mut c_void,
+) -> impl FnMut(&str, f32) {
+    move |parameter_id, new_value| {
+        if let Ok(parameter_id) = CString::new(parameter_id) {
+            set_param(parameter_id.as_ptr(), new_value, user_data);
+        }
+    }
+}
+
+/// Writes the nul terminated point list into `output` when it fits, and returns its length without the nul.
+/// Call it with an `output_size` of 0 first to find out how much space is needed.
+///
+/// # Safety
+/// `output` must point to at least `output_size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pitch_points_serialize(param_store: &ParamStore, output: *mut c_char, output_size: usize) -> usize {
+    let serialized_points = serialize_points(param_store);
+
+    if serialized_points.len() < output_size {
+        let output = std::slice::from_raw_parts_mut(output as *mut u8, output_size);
+        output[..serialized_points.len()].copy_from_slice(serialized_points.as_bytes());
+        output[serialized_points.len()] = 0;
+    }
+
+    serialized_points.len()
+}
+
+/// Replaces the whole point list by calling `set_param` for every parameter that has to change.
+/// Nothing is written to the store, set the changes on the plugin parameters, which pass them on to the store.
+/// Returns false and calls nothing when the list is invalid.
+///
+/// # Safety
+/// `serialized_points` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pitch_points_deserialize(
+    serialized_points: *const c_char,
+    set_param: extern "C" fn(parameter_id: *const c_char, new_value: f32, user_data: *mut c_void),
+    user_data: *mut c_void,
+) -> bool {
+    let serialized_points = std::ffi::CStr::from_ptr(serialized_points).to_string_lossy();
+    deserialize_points(&serialized_points, with_callback(set_param, user_data))
+}
+
+/// Inserts a point like "octave,note,timing,curve,tension,cents,hz,sync" at `index`, counted from 0,
+/// by calling `set_param` for every parameter that has to change, like `pitch_points_deserialize`.
+/// Returns false when the point is invalid, the index is out of range or the envelope is full.
+///
+/// # Safety
+/// `serialized_point` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pitch_points_insert(
+    param_store: &ParamStore,
+    index: usize,
+    serialized_point: *const c_char,
+    set_param: extern "C" fn(parameter_id: *const c_char, new_value: f32, user_data: *mut c_void),
+    user_data: *mut c_void,
+) -> bool {
+    let serialized_point = std::ffi::CStr::from_ptr(serialized_point).to_string_lossy();
+    insert_point(param_store, index, &serialized_point, with_callback(set_param, user_data))
+}
+
+/// Removes the point at `index`, counted from 0, by calling `set_param` for every parameter that has to change,
+/// like `pitch_points_deserialize`. Returns false when the index is out of range or it is the only point.
+#[no_mangle]
+pub extern "C" fn pitch_points_remove(
+    param_store: &ParamStore,
+    index: usize,
+    set_param: extern "C" fn(parameter_id: *const c_char, new_value: f32, user_data: *mut c_void),
+    user_data: *mut c_void,
+) -> bool {
+    remove_point(param_store, index, with_callback(set_param, user_data))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::param_store::ParamStore;
+    use crate::pitch_points::{deserialize_points, insert_point, remove_point, serialize_points};
+
+    // Stands in for the plugin, which sets the changes on its parameters and from there on the store
+    fn set_on(param_store: &ParamStore) -> impl FnMut(&str, f32) + '_ {
+        move |parameter_id, new_value| assert!(param_store.set(parameter_id, new_value))
+    }
+
+    #[test]
+    fn test_serialize_defaults() {
+        let param_store = ParamStore::new();
+        param_store.set("octave_1", 8.0);
+        param_store.set("timing_2", 2.5);
+
+        assert_eq!(serialize_points(&param_store), "8,0,0,0,1,0,0,0;0,0,2.5,0,1,0,0,0;0,0,0,0,1,0,0,0;0,0,0,0,1,0,0,0");
+    }
+
+    #[test]
+    fn test_deserialize_round_trip() {
+        let param_store = ParamStore::new();
+
+        assert!(deserialize_points("8,0,0;4,7,2.12,1;3,5,16.55,2,3.5;1,9,69.09,0,1,-12;1,9,100,0,1,0,45", set_on(&param_store)));
+        assert_eq!(param_store.get("pitch_points"), Some(5.0));
+        assert_eq!(param_store.get("tension_3"), Some(3.5));
+        assert_eq!(param_store.get("hz_5"), Some(45.0));
+        assert_eq!(
+            serialize_points(&param_store),
+            "8,0,0,0,1,0,0,0;4,7,2.12,1,1,0,0,0;3,5,16.55,2,3.5,0,0,0;1,9,69.09,0,1,-12,0,0;1,9,100,0,1,0,45,0"
+        );
+    }
+
+    #[test]
+    fn test_edits_are_passed_back_instead_of_stored() {
+        let param_store = ParamStore::new();
+        let mut changes = Vec::new();
+
+        assert!(remove_point(&param_store, 3, |parameter_id, new_value| changes.push((parameter_id.to_string(), new_value))));
+        assert_eq!(changes.len(), 3 * 8 + 1);
+        assert_eq!(changes.last(), Some(&("pitch_points".to_string(), 3.0)));
+        assert_eq!(param_store.get("pitch_points"), None);
+    }
+
+    #[test]
+    fn test_deserialize_invalid_changes_nothing() {
+        let param_store = ParamStore::new();
+        param_store.set("octave_1", 8.0);
+
+        assert!(!deserialize_points("4,7,2.12;4,seven,2.12", set_on(&param_store)));
+        assert!(!deserialize_points("4,7,2.12,5", set_on(&param_store)));
+        assert!(!deserialize_points("4,7,2.12,0,1,0,0,14", set_on(&param_store)));
+        assert!(!deserialize_points("4,7,2.12,1.5", set_on(&param_store)));
+        assert!(!deserialize_points("4,7,2.12,0,1,12,0,3,1", set_on(&param_store)));
+        assert!(!deserialize_points("", set_on(&param_store)));
+        assert_eq!(param_store.get("octave_1"), Some(8.0));
+        assert_eq!(param_store.get("octave_2"), None);
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let param_store = ParamStore::new();
+        assert!(deserialize_points("8,0,0;4,7,2;1,9,70", set_on(&param_store)));
+
+        assert!(insert_point(&param_store, 1, "6,0,1,1", set_on(&param_store)));
+        assert_eq!(serialize_points(&param_store), "8,0,0,0,1,0,0,0;6,0,1,1,1,0,0,0;4,7,2,0,1,0,0,0;1,9,70,0,1,0,0,0");
+
+        assert!(insert_point(&param_store, 4, "0,0,200", set_on(&param_store)));
+        assert!(!insert_point(&param_store, 6, "0,0,200", set_on(&param_store)));
+
+        assert!(remove_point(&param_store, 0, set_on(&param_store)));
+        assert_eq!(serialize_points(&param_store), "6,0,1,1,1,0,0,0;4,7,2,0,1,0,0,0;1,9,70,0,1,0,0,0;0,0,200,0,1,0,0,0");
+        assert!(!remove_point(&param_store, 4, set_on(&param_store)));
+    }
+
+    #[test]
+    fn test_cannot_remove_last_point() {
+        let param_store = ParamStore::new();
+        assert!(deserialize_points("8,0,0", set_on(&param_store)));
+        assert!(!remove_point(&param_store, 0, set_on(&param_store)));
+    }
+}
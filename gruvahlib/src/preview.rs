@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use num_complex::Complex;
+
+use crate::midi_message::MidiMessage;
+use crate::param_store::ParamStore;
+use crate::utils::linear_to_db;
+use crate::KickSynth;
+
+// Offline rendering and analysis of a single hit, used by the editor and the gruvah_preview cli.
+// None of this is real-time safe, never call it from the audio thread.
+
+const PREVIEW_BLOCK_SIZE: usize = 512;
+const PREVIEW_NOTE_PITCH: u8 = 36;
+// frames quieter than this don't get a fundamental frequency
+const FUNDAMENTAL_THRESHOLD: f32 = 1.0e-4;
+
+pub(crate) fn render_preview_from_store(param_store: &ParamStore, sample_rate: usize, output: &mut [f32]) {
+    let mut kick_synth = KickSynth::new(sample_rate);
+    kick_synth.get_param_store().copy_from(param_store);
+    kick_synth.settle_params();
+
+    output.fill(0.0);
+    kick_synth.queue_midi_message(&MidiMessage::new(0, &0x90, PREVIEW_NOTE_PITCH, 127));
+
+    for block in output.chunks_mut(PREVIEW_BLOCK_SIZE) {
+        kick_synth.process_block(block);
+    }
+}
+
+// Renders one hit with the given parameters on top of the defaults of the kick synth, panics on an invalid parameter
+pub fn render_preview(params: &[(&str, f32)], sample_rate: usize, output: &mut [f32]) {
+    let param_store = ParamStore::new();
+
+    for (parameter_id, new_value) in params {
+        assert!(param_store.set(parameter_id, *new_value), "Invalid parameter: {} = {}", parameter_id, new_value);
+    }
+
+    render_preview_from_store(&param_store, sample_rate, output);
+}
+
+pub fn spectrogram_num_bins(fft_size: usize) -> usize {
+    fft_size / 2 + 1
+}
+
+// Frames start every hop_size samples, the last frames are zero padded
+pub fn spectrogram_num_frames(num_samples: usize, hop_size: usize) -> usize {
+    num_samples.div_ceil(hop_size)
+}
+
+// Hann windowed stft, calls on_frame with the magnitude of every bin of every frame
+fn for_each_stft_frame(signal: &[f32], fft_size: usize, hop_size: usize, mut on_frame: impl FnMut(usize, &[f32])) {
+    let mut planner = rustfft::FftPlanner::new();
+    let fft = planner.plan_fft_forward(fft_size);
+
+    let window: Vec<f32> = (0..fft_size)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / fft_size as f32).cos())
+        .collect();
+    // scale so a full scale sine ends up with a magnitude of 1
+    let window_gain = 2.0 / window.iter().sum::<f32>();
+
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+    let mut magnitudes = vec![0.0_f32; spectrogram_num_bins(fft_size)];
+
+    for frame in 0..spectrogram_num_frames(signal.len(), hop_size) {
+        let start = frame * hop_size;
+
+        for (i, x) in buffer.iter_mut().enumerate() {
+            let sample = signal.get(start + i).copied().unwrap_or(0.0);
+            *x = Complex::new(sample * window[i], 0.0);
+        }
+
+        fft.process(&mut buffer);
+
+        for (magnitude, bin) in magnitudes.iter_mut().zip(buffer.iter()) {
+            *magnitude = bin.norm() * window_gain;
+        }
+
+        on_frame(frame, &magnitudes);
+    }
+}
+
+// Writes the magnitude in dB of every bin, frame after frame, and returns the number of frames written.
+// Stops early when the output can't hold another frame.
+pub fn compute_spectrogram(signal: &[f32], fft_size: usize, hop_size: usize, output: &mut [f32]) -> usize {
+    let num_bins = spectrogram_num_bins(fft_size);
+    let max_frames = output.len() / num_bins;
+    let mut frames_written = 0;
+
+    for_each_stft_frame(signal, fft_size, hop_size, |frame, magnitudes| {
+        if frame >= max_frames {
+            return;
+        }
+
+        let frame_output = &mut output[frame * num_bins..(frame + 1) * num_bins];
+        for (x, magnitude) in frame_output.iter_mut().zip(magnitudes) {
+            *x = linear_to_db(*magnitude);
+        }
+
+        frames_written += 1;
+    });
+
+    frames_written
+}
+
+// Writes the frequency in Hz of the loudest bin of every frame, refined with parabolic interpolation,
+// or 0 for silent frames. Returns the number of frames written.
+pub fn compute_fundamental_trace(signal: &[f32], sample_rate: usize, fft_size: usize, hop_size: usize, output: &mut [f32]) -> usize {
+    let mut frames_written = 0;
+
+    for_each_stft_frame(signal, fft_size, hop_size, |frame, magnitudes| {
+        let Some(x) = output.get_mut(frame) else {
+            return;
+        };
+
+        let (peak_bin, peak_magnitude) = magnitudes.iter()
+            .enumerate()
+            .skip(1)
+            .fold((0, 0.0_f32), |peak, (bin, magnitude)| if *magnitude > peak.1 { (bin, *magnitude) } else { peak });
+
+        frames_written += 1;
+
+        if peak_magnitude < FUNDAMENTAL_THRESHOLD {
+            *x = 0.0;
+            return;
+        }
+
+        let mut peak_position = peak_bin as f32;
+        if peak_bin + 1 < magnitudes.len() {
+            let left = linear_to_db(magnitudes[peak_bin - 1]);
+            let center = linear_to_db(peak_magnitude);
+            let right = linear_to_db(magnitudes[peak_bin + 1]);
+
+            let denominator = left - 2.0 * center + right;
+            if denominator != 0.0 {
+                peak_position += 0.5 * (left - right) / denominator;
+            }
+        }
+
+        *x = peak_position * sample_rate as f32 / fft_size as f32;
+    });
+
+    frames_written
+}
+
+/// Renders one hit with the current parameters into `output`, on a separate kick synth.
+/// Never call this from the audio thread.
+///
+/// # Safety
+/// `output` must point to at least `num_samples` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn render_kick_preview(param_store: &ParamStore, sample_rate: usize, output: *mut f32, num_samples: usize) {
+    let output = std::slice::from_raw_parts_mut(output, num_samples);
+    render_preview_from_store(param_store, sample_rate, output);
+}
+
+/// Writes `fft_size / 2 + 1` magnitudes in dB per frame into `output`, with a frame every `hop_size` samples.
+/// Returns the number of frames written.
+///
+/// # Safety
+/// `signal` must point to `num_samples` floats and `output` to at least `output_size` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn preview_spectrogram(
+    signal: *const f32,
+    num_samples: usize,
+    fft_size: usize,
+    hop_size: usize,
+    output: *mut f32,
+    output_size: usize,
+) -> usize {
+    let signal = std::slice::from_raw_parts(signal, num_samples);
+    let output = std::slice::from_raw_parts_mut(output, output_size);
+    compute_spectrogram(signal, fft_size, hop_size, output)
+}
+
+/// Writes the fundamental frequency in Hz of every frame into `output`, 0 for silent frames.
+/// Returns the number of frames written.
+///
+/// # Safety
+/// `signal` must point to `num_samples` floats and `output` to at least `output_size` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn preview_fundamental_trace(
+    signal: *const f32,
+    num_samples: usize,
+    sample_rate: usize,
+    fft_size: usize,
+    hop_size: usize,
+    output: *mut f32,
+    output_size: usize,
+) -> usize {
+    let signal = std::slice::from_raw_parts(signal, num_samples);
+    let output = std::slice::from_raw_parts_mut(output, output_size);
+    compute_fundamental_trace(signal, sample_rate, fft_size, hop_size, output)
+}
+
+#[no_mangle]
+pub extern "C" fn preview_num_frames(num_samples: usize, hop_size: usize) -> usize {
+    spectrogram_num_frames(num_samples, hop_size)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::preview::{compute_fundamental_trace, compute_spectrogram, render_preview, spectrogram_num_bins, spectrogram_num_frames};
+
+    fn sine(frequency: f32, sample_rate: usize, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_fundamental_of_sine() {
+        let signal = sine(1000.0, 48000, 4096);
+
+        let mut trace = vec![0.0; spectrogram_num_frames(signal.len(), 1024)];
+        let frames = compute_fundamental_trace(&signal, 48000, 1024, 1024, &mut trace);
+
+        assert_eq!(frames, 4);
+        assert_approx_eq!(trace[0], 1000.0, 5.0);
+        assert_approx_eq!(trace[2], 1000.0, 5.0);
+    }
+
+    #[test]
+    fn test_spectrogram_peak_magnitude() {
+        let signal = sine(1500.0, 48000, 1024);
+
+        let num_bins = spectrogram_num_bins(1024);
+        let mut spectrogram = vec![0.0; num_bins];
+        let frames = compute_spectrogram(&signal, 1024, 1024, &mut spectrogram);
+
+        // 1500 Hz lies exactly on bin 32, a full scale sine should end up around 0 dB
+        assert_eq!(frames, 1);
+        assert_approx_eq!(spectrogram[32], 0.0, 0.1);
+        assert!(spectrogram[100] < -40.0);
+    }
+
+    #[test]
+    fn test_preview_follows_pitch_envelope() {
+        let params = [
+            ("octave_1", 3.0), ("timing_1", 1.0),
+            ("octave_2", 3.0), ("timing_2", 1.0),
+            ("octave_3", 3.0), ("timing_3", 1.0),
+            ("octave_4", 3.0), ("timing_4", 1.0),
+            ("amp_attack", 1.0), ("amp_decay", 1.0), ("amp_sustain", 100.0), ("amp_release", 500.0),
+            ("amp_exponential_factor_a", 1.0),
+        ];
+
+        let mut output = vec![0.0; 24000];
+        render_preview(&params, 48000, &mut output);
+
+        let mut trace = vec![0.0; spectrogram_num_frames(output.len(), 4096)];
+        compute_fundamental_trace(&output, 48000, 4096, 4096, &mut trace);
+
+        // C3
+        assert_approx_eq!(trace[2], 130.81, 3.0);
+        assert!(output.iter().any(|x| x.abs() > 0.1));
+    }
+}
@@ -10,62 +10,259 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::target_pair::TargetCurrentPair;
-use crate::utils::db_to_linear;
+use std::f32::consts::FRAC_PI_2;
 
+use crate::distortion_chain::DistortionChain;
+use crate::multiband::Multiband;
+use crate::oversampling::{Oversampler, OversamplingFactor};
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::utils::{db_to_linear, linear_to_db};
+
+#[derive(Clone, Copy)]
 pub enum SaturationType {
     None,
     Soft,
     Clip,
     ExtremeClip,
+    Wavefolder,
+    Tube,
+    Diode,
+    SineShaper,
+    Bitcrush,
 }
 
-pub(crate) struct Saturation {
+// The curve the driven signal goes through, together with the settings and the state of the curves that need them
+#[derive(Clone, Copy)]
+pub(crate) struct Waveshaper {
     saturation_type: SaturationType,
+    // how many times the wavefolder folds a full scale signal back
+    folds: f32,
+    // shifts the tube curve, so the positive half saturates before the negative half
+    bias: f32,
+    bits: f32,
+    // the part of a held sample of the bitcrusher that passes per sample
+    crush_step: f32,
+    crush_phase: f32,
+    crush_held: f32,
+}
+
+impl Waveshaper {
+    pub(crate) fn new(saturation_type: SaturationType) -> Self {
+        Self {
+            saturation_type,
+            folds: 1.0,
+            bias: 0.2,
+            bits: 8.0,
+            crush_step: 1.0,
+            crush_phase: 0.0,
+            crush_held: 0.0,
+        }
+    }
+
+    pub(crate) fn set_saturation_type(&mut self, saturation_type: SaturationType) {
+        self.saturation_type = saturation_type;
+    }
+
+    pub(crate) fn get_drive_gain(&self, drive: f32) -> f32 {
+        match self.saturation_type {
+            SaturationType::None => 1.0,
+            // TO THE MEGA, YEEAAAHHHHH, HARDCORE TO THE MEGA
+            SaturationType::ExtremeClip => drive * drive,
+            _ => drive,
+        }
+    }
+
+    pub(crate) fn saturate(&mut self, driven: f32) -> f32 {
+        match self.saturation_type {
+            SaturationType::None => driven,
+            SaturationType::Soft => driven.tanh(),
+            SaturationType::Clip | SaturationType::ExtremeClip => driven.clamp(-1.0, 1.0),
+            SaturationType::Wavefolder => fold(driven * (1.0 + self.folds)),
+            // moves to the same place at 0 as without the bias, the asymmetry adds even harmonics
+            SaturationType::Tube => (driven + self.bias).tanh() - self.bias.tanh(),
+            // a pair of diodes, which have a harder knee than tanh
+            SaturationType::Diode => driven.signum() * (1.0 - (-driven.abs()).exp()),
+            SaturationType::SineShaper => (driven * FRAC_PI_2).sin(),
+            SaturationType::Bitcrush => self.crush(driven),
+        }
+    }
+
+    fn crush(&mut self, driven: f32) -> f32 {
+        self.crush_phase += self.crush_step;
+
+        if self.crush_phase >= 1.0 {
+            self.crush_phase -= self.crush_phase.floor();
+
+            let levels = 2.0_f32.powf(self.bits - 1.0);
+            self.crush_held = (driven.clamp(-1.0, 1.0) * levels).round() / levels;
+        }
+
+        self.crush_held
+    }
+}
+
+// How long the auto gain averages the level over, short enough to follow the body of a kick
+const AUTO_GAIN_RMS_MS: f32 = 50.0;
+// below this level the auto gain keeps its last gain, so the tail and the silence after it don't pump
+const AUTO_GAIN_THRESHOLD: f32 = 1.0e-4;
+// the most the auto gain turns the level up or down, in dB
+const AUTO_GAIN_RANGE_DB: f32 = 36.0;
+
+// Follows the RMS level before and after the saturation and turns the output by the difference,
+// so changing the drive or the type mostly changes the sound and not how loud it is
+struct AutoGain {
+    coefficient: f32,
+    input_mean_square: f32,
+    output_mean_square: f32,
+    gain: f32,
+    // fades the compensation in and out, so switching the auto gain doesn't click
+    amount: TargetCurrentPair<f32>,
+}
+
+impl AutoGain {
+    fn new(sample_rate: usize) -> Self {
+        Self {
+            coefficient: 1.0 - (-1000.0 / (AUTO_GAIN_RMS_MS * sample_rate as f32)).exp(),
+            input_mean_square: 0.0,
+            output_mean_square: 0.0,
+            gain: 1.0,
+            amount: TargetCurrentPair::new(0.0).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear),
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.amount.set_target(if enabled { 1.0 } else { 0.0 });
+    }
+
+    fn process_sample(&mut self, input: f32, output: f32) -> f32 {
+        self.amount.update_to_target();
+        self.input_mean_square += (input * input - self.input_mean_square) * self.coefficient;
+        self.output_mean_square += (output * output - self.output_mean_square) * self.coefficient;
+
+        if self.input_mean_square.sqrt() > AUTO_GAIN_THRESHOLD && self.output_mean_square > 0.0 {
+            let max_gain = db_to_linear(AUTO_GAIN_RANGE_DB);
+            self.gain = (self.input_mean_square / self.output_mean_square).sqrt().clamp(1.0 / max_gain, max_gain);
+        }
+
+        output * (1.0 + (self.gain - 1.0) * self.amount.get())
+    }
+
+    fn settle(&mut self) {
+        self.amount.settle();
+    }
+}
+
+// Mirrors everything above 1 and below -1 back, as often as it takes to end up in between
+fn fold(x: f32) -> f32 {
+    1.0 - 4.0 * (((x + 1.0) / 4.0).rem_euclid(1.0) - 0.5).abs()
+}
+
+pub(crate) struct Saturation {
+    sample_rate: usize,
+    waveshaper: Waveshaper,
     drive: TargetCurrentPair<f32>,
+    oversampler: Oversampler,
+    oversampling_factor: OversamplingFactor,
+    multiband: Multiband,
+    distortion_chain: DistortionChain,
+    auto_gain: AutoGain,
+    output_gain: TargetCurrentPair<f32>,
+    // the sample rate of the bitcrusher in Hz
+    crush_rate: f32,
+    driven_peak: f32,
+    output_peak: f32,
 }
 
 impl Saturation {
-    pub fn new() -> Self {
-        let mut drive = TargetCurrentPair::default();
-        drive.set_target(1.0);
-
+    pub fn new(sample_rate: usize) -> Self {
         Self {
-            saturation_type: SaturationType::Soft,
-            drive,
+            sample_rate,
+            waveshaper: Waveshaper::new(SaturationType::Soft),
+            drive: TargetCurrentPair::new(1.0).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear),
+            oversampler: Oversampler::new(),
+            oversampling_factor: OversamplingFactor::Off,
+            multiband: Multiband::new(sample_rate),
+            distortion_chain: DistortionChain::new(sample_rate),
+            auto_gain: AutoGain::new(sample_rate),
+            output_gain: TargetCurrentPair::new(1.0).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear),
+            crush_rate: sample_rate as f32,
+            driven_peak: 0.0,
+            output_peak: 0.0,
         }
     }
 
     pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
         self.drive.update_to_target();
+        self.multiband.update_smoothing();
+        self.distortion_chain.update_smoothing();
+        self.output_gain.update_to_target();
 
-        match self.saturation_type {
-            SaturationType::None => {
-                input
-            },
-            SaturationType::Soft => {
-                let output = input * self.drive.get();
-                let output = output.tanh();
-                output
-            },
-            SaturationType::Clip => {
-                let mut output = input * self.drive.get();
-                output = output.min(1.0);
-                output = output.max(-1.0);
-                output
-            }
-            SaturationType::ExtremeClip => {
-                // TO THE MEGA, YEEAAAHHHHH, HARDCORE TO THE MEGA
-                let mut output = input * self.drive.get() * self.drive.get();
-                output = output.min(1.0);
-                output = output.max(-1.0);
-                output
-            }
-        }
+        // the multiband bands saturate the signal before the drive, then it goes through the saturation and the chain.
+        // A clean low band goes around both.
+        let drive_gain = self.waveshaper.get_drive_gain(self.drive.get());
+        let waveshaper = &mut self.waveshaper;
+        let multiband = &mut self.multiband;
+        let distortion_chain = &mut self.distortion_chain;
+        let output = self.oversampler.process_sample(input, |x| {
+            let (banded, clean) = multiband.process_sample(x);
+            distortion_chain.process_sample(waveshaper.saturate(banded * drive_gain)) + clean
+        });
+
+        self.driven_peak = self.driven_peak.max((input * drive_gain).abs());
+        self.output_peak = self.output_peak.max(output.abs());
+
+        // the gain reduction is measured before the auto gain, which would hide it
+        self.auto_gain.process_sample(input, output) * self.output_gain.get()
+    }
+
+    // How much the saturation squashed the driven signal since the last call, in dB (0 or negative)
+    pub(crate) fn take_gain_reduction_db(&mut self) -> f32 {
+        let gain_reduction = match self.driven_peak {
+            driven_peak if driven_peak > 0.0 => linear_to_db(self.output_peak / driven_peak).min(0.0),
+            _ => 0.0,
+        };
+
+        self.driven_peak = 0.0;
+        self.output_peak = 0.0;
+
+        gain_reduction
+    }
+
+    pub(crate) fn settle(&mut self) {
+        self.drive.settle();
+        self.multiband.settle();
+        self.distortion_chain.settle();
+        self.auto_gain.settle();
+        self.output_gain.settle();
+    }
+
+    pub(crate) fn get_multiband_mut(&mut self) -> &mut Multiband {
+        &mut self.multiband
+    }
+
+    pub(crate) fn get_distortion_chain_mut(&mut self) -> &mut DistortionChain {
+        &mut self.distortion_chain
+    }
+
+    // The shape settings are shared by the saturation, the multiband bands and every stage of the distortion chain
+    fn get_waveshapers_mut(&mut self) -> impl Iterator<Item = &mut Waveshaper> {
+        std::iter::once(&mut self.waveshaper)
+            .chain(self.multiband.get_waveshapers_mut())
+            .chain(self.distortion_chain.get_waveshapers_mut())
     }
 
     pub fn set_saturation_type(&mut self, saturation_type: SaturationType) {
-        self.saturation_type = saturation_type;
+        self.waveshaper.set_saturation_type(saturation_type);
+    }
+
+    // Without saturation the signal is still delayed by the oversampling, so the latency never depends on the type
+    pub fn set_oversampling(&mut self, factor: OversamplingFactor) {
+        self.oversampling_factor = factor;
+        self.oversampler.set_factor(factor);
+        let oversampled_rate = (self.sample_rate * factor.get_factor()) as f32;
+        self.multiband.set_sample_rate(oversampled_rate);
+        self.distortion_chain.set_sample_rate(oversampled_rate);
+        self.update_crush_step();
     }
 
     pub fn set_drive(&mut self, drive: f32) {
@@ -73,4 +270,135 @@ impl Saturation {
         // println!("drive: {}", drive);
         self.drive.set_target(drive);
     }
-}
\ No newline at end of file
+
+    pub fn set_auto_gain(&mut self, auto_gain: bool) {
+        self.auto_gain.set_enabled(auto_gain);
+    }
+
+    pub fn set_output_gain(&mut self, output_gain: f32) {
+        self.output_gain.set_target(db_to_linear(output_gain));
+    }
+
+    pub fn set_folds(&mut self, folds: f32) {
+        self.get_waveshapers_mut().for_each(|waveshaper| waveshaper.folds = folds.max(0.0));
+    }
+
+    pub fn set_bias(&mut self, bias: f32) {
+        self.get_waveshapers_mut().for_each(|waveshaper| waveshaper.bias = bias);
+    }
+
+    pub fn set_bits(&mut self, bits: f32) {
+        self.get_waveshapers_mut().for_each(|waveshaper| waveshaper.bits = bits.max(1.0));
+    }
+
+    pub fn set_crush_rate(&mut self, crush_rate: f32) {
+        self.crush_rate = crush_rate;
+        self.update_crush_step();
+    }
+
+    // The bitcrusher runs inside the oversampling, at a multiple of the sample rate
+    fn update_crush_step(&mut self) {
+        let oversampled_rate = (self.sample_rate * self.oversampling_factor.get_factor()) as f32;
+        let crush_step = (self.crush_rate / oversampled_rate).clamp(0.0, 1.0);
+        self.get_waveshapers_mut().for_each(|waveshaper| waveshaper.crush_step = crush_step);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::{FRAC_1_SQRT_2, PI};
+    use assert_approx_eq::assert_approx_eq;
+    use crate::distortion_chain::DistortionParam;
+    use crate::oversampling::OversamplingFactor;
+    use crate::saturation::{fold, Saturation, SaturationType};
+    use crate::test_utils::get_sine_gain;
+
+    #[test]
+    fn test_fold() {
+        assert_approx_eq!(fold(0.5), 0.5);
+        assert_approx_eq!(fold(1.5), 0.5);
+        assert_approx_eq!(fold(-2.5), 0.5);
+        assert_approx_eq!(fold(3.0), -1.0);
+    }
+
+    #[test]
+    fn test_tube_is_asymmetric() {
+        let mut saturation = Saturation::new(1000);
+        saturation.set_saturation_type(SaturationType::Tube);
+        saturation.set_bias(0.5);
+
+        assert_eq!(saturation.process_sample(0.0), 0.0);
+        let positive = saturation.process_sample(2.0);
+        let negative = saturation.process_sample(-2.0);
+        assert!(positive < -negative);
+    }
+
+    #[test]
+    fn test_bitcrush() {
+        let mut saturation = Saturation::new(1000);
+        saturation.set_saturation_type(SaturationType::Bitcrush);
+        saturation.set_drive(0.0);
+        saturation.set_bits(2.0);
+        saturation.set_crush_rate(500.0);
+
+        let output: Vec<f32> = [0.3, 0.9, 0.6, -0.2].iter().map(|x| saturation.process_sample(*x)).collect();
+        // every other sample is held, on the levels -1, -0.5, 0, 0.5 and 1
+        assert_eq!(output, vec![0.0, 1.0, 1.0, 0.0]);
+
+        // the rate stays the same with oversampling
+        saturation.set_oversampling(OversamplingFactor::X2);
+        saturation.set_crush_rate(250.0);
+        assert_approx_eq!(saturation.waveshaper.crush_step, 0.125);
+    }
+
+    // The RMS of a sine with a peak of 0.5
+    fn get_level(saturation: &mut Saturation) -> f32 {
+        get_sine_gain(|x| saturation.process_sample(0.5 * x), 50.0, 1000.0) * FRAC_1_SQRT_2
+    }
+
+    #[test]
+    fn test_auto_gain() {
+        let input_level = 0.5 * FRAC_1_SQRT_2;
+
+        for saturation_type in [SaturationType::Soft, SaturationType::ExtremeClip, SaturationType::Wavefolder] {
+            let mut saturation = Saturation::new(1000);
+            saturation.set_saturation_type(saturation_type);
+            saturation.set_drive(24.0);
+            assert!((get_level(&mut saturation) - input_level).abs() > 0.1);
+
+            saturation.set_auto_gain(true);
+            saturation.settle();
+            assert_approx_eq!(get_level(&mut saturation), input_level, 0.02);
+        }
+    }
+
+    #[test]
+    fn test_clean_low_band_skips_the_saturation() {
+        let mut saturation = Saturation::new(48000);
+        saturation.set_saturation_type(SaturationType::Clip);
+        saturation.set_drive(12.0);
+        saturation.get_distortion_chain_mut().set_num_stages(1);
+        saturation.get_distortion_chain_mut().set_stage(0, DistortionParam::Type(SaturationType::Clip));
+        saturation.get_distortion_chain_mut().set_stage(0, DistortionParam::Drive(12.0));
+        saturation.get_multiband_mut().set_num_bands(2);
+        saturation.get_multiband_mut().set_low_clean(true);
+
+        // a 30 Hz sine, two octaves below the crossover at 120 Hz, so only a little of it ends up in the high band
+        let output: Vec<f32> = (0..48000).map(|n| saturation.process_sample(0.5 * (2.0 * PI * 30.0 * n as f32 / 48000.0).sin())).collect();
+        let peak = output[24000..].iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        let level = (output[24000..].iter().map(|x| x * x).sum::<f32>() / 24000.0).sqrt();
+
+        // clipped it would be close to a square wave with a peak of 1
+        assert_approx_eq!(peak, 0.5, 0.05);
+        assert_approx_eq!(level, 0.5 * FRAC_1_SQRT_2, 0.03);
+    }
+
+    #[test]
+    fn test_output_gain() {
+        let mut saturation = Saturation::new(1000);
+        saturation.set_saturation_type(SaturationType::None);
+        saturation.set_output_gain(-6.0206);
+
+        assert_approx_eq!(saturation.process_sample(0.8), 0.4);
+    }
+}
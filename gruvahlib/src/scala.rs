@@ -0,0 +1,295 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+use crate::tuning::MIDI_NOTE_COUNT;
+
+// Parser for Scala scale (.scl) and keyboard mapping (.kbm) files, see https://www.huygens-fokker.org/scala/scl_format.html
+// Both are turned into one frequency per midi note, which is all the kick synth needs.
+
+pub(crate) struct Scale {
+    // in cents, from the first degree above the root up to the period, which is usually 1200
+    degrees: Vec<f64>,
+}
+
+pub(crate) struct KeyboardMapping {
+    middle_note: i32,
+    reference_note: i32,
+    reference_frequency: f64,
+    // scale degree that repeats the mapping, 0 means the period of the scale
+    octave_degree: i32,
+    // None for keys that are not mapped, empty to map every key to the next scale degree
+    mapping: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    // what Scala uses without a .kbm file, the root on middle C and A4 at 440 Hz
+    fn default() -> Self {
+        Self {
+            middle_note: 60,
+            reference_note: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            mapping: vec![],
+        }
+    }
+}
+
+// Lines starting with ! are comments, everything after the first value on a line is ignored
+fn get_lines(file: &str) -> impl Iterator<Item = &str> {
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('!'))
+}
+
+fn get_first_word(line: &str) -> Option<&str> {
+    line.split_whitespace().next()
+}
+
+// Values with a period are cents, anything else is a ratio like 3/2 or a whole number like 2
+fn parse_pitch(line: &str) -> Option<f64> {
+    let pitch = get_first_word(line)?;
+
+    if pitch.contains('.') {
+        return pitch.parse().ok();
+    }
+
+    let (numerator, denominator) = pitch.split_once('/').unwrap_or((pitch, "1"));
+    let numerator: f64 = numerator.parse::<u64>().ok()? as f64;
+    let denominator: f64 = denominator.parse::<u64>().ok()? as f64;
+
+    if numerator == 0.0 || denominator == 0.0 {
+        return None;
+    }
+
+    Some(1200.0 * (numerator / denominator).log2())
+}
+
+pub(crate) fn parse_scl(file: &str) -> Option<Scale> {
+    // the first line is the description, which can be empty
+    let mut lines = get_lines(file).skip(1);
+
+    let num_degrees: usize = get_first_word(lines.next()?)?.parse().ok()?;
+    let degrees: Vec<f64> = lines.take(num_degrees).map(parse_pitch).collect::<Option<_>>()?;
+
+    // a scale of 0 notes is valid Scala, but there is nothing to tune to
+    if num_degrees == 0 || degrees.len() != num_degrees || degrees[num_degrees - 1] <= 0.0 {
+        return None;
+    }
+
+    Some(Scale { degrees })
+}
+
+pub(crate) fn parse_kbm(file: &str) -> Option<KeyboardMapping> {
+    let values: Vec<&str> = get_lines(file).filter_map(get_first_word).collect();
+    let (header, mapping) = (values.get(..7)?, &values[7..]);
+
+    // header[1] and header[2] are the first and last note to retune, every midi note is retuned here
+    let map_size: usize = header[0].parse().ok()?;
+    let middle_note: i32 = header[3].parse().ok()?;
+    let reference_note: i32 = header[4].parse().ok()?;
+    let reference_frequency: f64 = header[5].parse().ok()?;
+    let octave_degree: i32 = header[6].parse().ok()?;
+
+    let mut mapping: Vec<Option<i32>> = mapping.iter()
+        .take(map_size)
+        .map(|degree| match *degree {
+            "x" => Some(None),
+            degree => degree.parse().ok().map(Some),
+        })
+        .collect::<Option<_>>()?;
+
+    // missing entries at the end are unmapped
+    mapping.resize(map_size, None);
+
+    if reference_frequency <= 0.0 || octave_degree < 0 {
+        return None;
+    }
+
+    Some(KeyboardMapping {
+        middle_note,
+        reference_note,
+        reference_frequency,
+        octave_degree,
+        mapping,
+    })
+}
+
+impl Scale {
+    // cents above the root, the degree can be negative or above the period
+    fn get_cents(&self, degree: i32) -> f64 {
+        let num_degrees = self.degrees.len() as i32;
+        let period = self.degrees[self.degrees.len() - 1];
+
+        let periods = degree.div_euclid(num_degrees);
+        let degree_in_period = degree.rem_euclid(num_degrees);
+
+        let cents = match degree_in_period {
+            0 => 0.0,
+            _ => self.degrees[degree_in_period as usize - 1],
+        };
+
+        periods as f64 * period + cents
+    }
+}
+
+impl KeyboardMapping {
+    fn get_degree(&self, scale: &Scale, note: i32) -> Option<i32> {
+        let keys_from_middle = note - self.middle_note;
+
+        if self.mapping.is_empty() {
+            return Some(keys_from_middle);
+        }
+
+        let map_size = self.mapping.len() as i32;
+        let octave_degree = match self.octave_degree {
+            0 => scale.degrees.len() as i32,
+            octave_degree => octave_degree,
+        };
+
+        let degree = self.mapping[keys_from_middle.rem_euclid(map_size) as usize]?;
+        Some(keys_from_middle.div_euclid(map_size) * octave_degree + degree)
+    }
+}
+
+// Unmapped keys get the frequency of the closest mapped key below them, or above for the lowest keys
+pub(crate) fn get_note_frequencies(scale: &Scale, keyboard_mapping: &KeyboardMapping) -> Option<[f32; MIDI_NOTE_COUNT]> {
+    let reference_degree = keyboard_mapping.get_degree(scale, keyboard_mapping.reference_note)?;
+    let root_frequency = keyboard_mapping.reference_frequency / 2.0_f64.powf(scale.get_cents(reference_degree) / 1200.0);
+
+    let frequencies: [Option<f32>; MIDI_NOTE_COUNT] = std::array::from_fn(|note| {
+        let degree = keyboard_mapping.get_degree(scale, note as i32)?;
+        Some((root_frequency * 2.0_f64.powf(scale.get_cents(degree) / 1200.0)) as f32)
+    });
+
+    let first_mapped = frequencies.iter().flatten().next().copied()?;
+    let mut previous = first_mapped;
+
+    Some(frequencies.map(|frequency| {
+        previous = frequency.unwrap_or(previous);
+        previous
+    }))
+}
+
+pub(crate) fn parse_tuning(scl: &str, kbm: Option<&str>) -> Option<[f32; MIDI_NOTE_COUNT]> {
+    let scale = parse_scl(scl)?;
+    let keyboard_mapping = match kbm {
+        Some(kbm) => parse_kbm(kbm)?,
+        None => KeyboardMapping::default(),
+    };
+
+    get_note_frequencies(&scale, &keyboard_mapping)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::scala::{parse_scl, parse_tuning};
+
+    const TWELVE_TET: &str = "! 12tet.scl
+!
+12 tone equal temperament
+ 12
+!
+ 100.0
+ 200.
+ 300.0
+ 400.0
+ 500.0
+ 600.0
+ 700.0
+ 800.0
+ 900.0
+ 1000.0
+ 1100.0
+ 2/1
+";
+
+    const PENTATONIC: &str = "Just pentatonic
+5
+9/8
+5/4
+3/2
+5/3
+2
+";
+
+    #[test]
+    fn test_twelve_tet_matches_midi() {
+        let frequencies = parse_tuning(TWELVE_TET, None).unwrap();
+
+        assert_approx_eq!(frequencies[69], 440.0, 0.001);
+        assert_approx_eq!(frequencies[60], 261.63, 0.01);
+        assert_approx_eq!(frequencies[24], 32.70, 0.01);
+    }
+
+    #[test]
+    fn test_ratios_and_linear_mapping() {
+        let kbm = "! root on C4 at 256 Hz
+0
+0
+127
+60
+60
+256.0
+5
+";
+        let frequencies = parse_tuning(PENTATONIC, Some(kbm)).unwrap();
+
+        assert_approx_eq!(frequencies[60], 256.0, 0.001);
+        assert_approx_eq!(frequencies[61], 288.0, 0.001);
+        assert_approx_eq!(frequencies[64], 426.67, 0.01);
+        assert_approx_eq!(frequencies[65], 512.0, 0.001);
+        assert_approx_eq!(frequencies[59], 256.0 * 5.0 / 6.0, 0.001);
+    }
+
+    #[test]
+    fn test_unmapped_keys() {
+        // C, D, E, G and A, the other keys hold the pitch of the key below them
+        let kbm = "12
+0
+127
+60
+69
+440.0
+5
+0
+x
+1
+x
+2
+x
+x
+3
+x
+4
+";
+        let frequencies = parse_tuning(PENTATONIC, Some(kbm)).unwrap();
+
+        assert_approx_eq!(frequencies[69], 440.0, 0.001);
+        assert_approx_eq!(frequencies[60], 264.0, 0.001);
+        assert_approx_eq!(frequencies[62], 297.0, 0.001);
+        assert_eq!(frequencies[65], frequencies[64]);
+        assert_eq!(frequencies[71], frequencies[69]);
+        assert_approx_eq!(frequencies[72], 528.0, 0.001);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(parse_scl("Broken\n3\n100.0\n200.0\n").is_none());
+        assert!(parse_scl("Broken\n1\nthree halves\n").is_none());
+        assert!(parse_scl("Broken\n1\n0/2\n").is_none());
+        assert!(parse_tuning(PENTATONIC, Some("1\n0\n127\n60\n69\n")).is_none());
+        // the reference note is on an unmapped key
+        assert!(parse_tuning(PENTATONIC, Some("1\n0\n127\n60\n69\n440.0\n5\nx\n")).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::f32::consts::PI;
+
+use crate::biquad::Biquad;
+
+// Low enough to leave the fundamental of any kick alone
+const DC_BLOCKER_FREQUENCY: f32 = 5.0;
+// The Qs of the biquads of an 8th order Butterworth filter, 48 dB per octave
+const SUBSONIC_QS: [f32; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+
+// Removes the DC offset that the phase, short attacks and asymmetric saturation leave in the kick,
+// and optionally everything below a frequency with a steep high pass
+pub(crate) struct Subsonic {
+    sample_rate: f32,
+    dc_blocker_enabled: bool,
+    // a one pole high pass, y[n] = x[n] - x[n - 1] + r * y[n - 1]
+    dc_coefficient: f32,
+    dc_input: f32,
+    dc_output: f32,
+    subsonic_enabled: bool,
+    subsonic_frequency: f32,
+    subsonic_biquads: [Biquad; SUBSONIC_QS.len()],
+}
+
+impl Subsonic {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let mut subsonic = Self {
+            sample_rate: sample_rate as f32,
+            dc_blocker_enabled: false,
+            dc_coefficient: (-2.0 * PI * DC_BLOCKER_FREQUENCY / sample_rate as f32).exp(),
+            dc_input: 0.0,
+            dc_output: 0.0,
+            subsonic_enabled: false,
+            subsonic_frequency: 20.0,
+            subsonic_biquads: [Biquad::new(); SUBSONIC_QS.len()],
+        };
+        subsonic.update_subsonic();
+        subsonic
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        let mut output = input;
+
+        if self.dc_blocker_enabled {
+            self.dc_output = output - self.dc_input + self.dc_coefficient * self.dc_output;
+            self.dc_input = output;
+            output = self.dc_output;
+        }
+
+        if self.subsonic_enabled {
+            output = self.subsonic_biquads.iter_mut().fold(output, |x, biquad| biquad.process_sample(x));
+        }
+
+        output
+    }
+
+    fn update_subsonic(&mut self) {
+        let (sample_rate, frequency) = (self.sample_rate, self.subsonic_frequency);
+        for (biquad, q) in self.subsonic_biquads.iter_mut().zip(SUBSONIC_QS) {
+            biquad.set_highpass(sample_rate, frequency, q);
+        }
+    }
+
+    // Switching on starts from silence, the state is from the last time it was on
+    pub(crate) fn set_dc_blocker_enabled(&mut self, enabled: bool) {
+        if enabled && !self.dc_blocker_enabled {
+            self.dc_input = 0.0;
+            self.dc_output = 0.0;
+        }
+        self.dc_blocker_enabled = enabled;
+    }
+
+    pub(crate) fn set_subsonic_enabled(&mut self, enabled: bool) {
+        if enabled && !self.subsonic_enabled {
+            self.subsonic_biquads.iter_mut().for_each(Biquad::reset);
+        }
+        self.subsonic_enabled = enabled;
+    }
+
+    pub(crate) fn set_subsonic_frequency(&mut self, frequency: f32) {
+        self.subsonic_frequency = frequency;
+        self.update_subsonic();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_1_SQRT_2;
+    use assert_approx_eq::assert_approx_eq;
+    use crate::subsonic::Subsonic;
+    use crate::test_utils::get_sine_gain;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn get_gain(subsonic: &mut Subsonic, frequency: f32) -> f32 {
+        get_sine_gain(|x| subsonic.process_sample(x), frequency, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn test_dc_blocker() {
+        let mut subsonic = Subsonic::new(SAMPLE_RATE as usize);
+        // off by default, so older sessions sound the same
+        assert_eq!(subsonic.process_sample(0.5), 0.5);
+        subsonic.set_dc_blocker_enabled(true);
+
+        let mut output = 0.0;
+        for _ in 0..SAMPLE_RATE as usize {
+            output = subsonic.process_sample(0.5);
+        }
+        assert!(output.abs() < 1.0e-3);
+
+        // a kick at 40 Hz passes
+        assert_approx_eq!(get_gain(&mut subsonic, 40.0), 1.0, 1.0e-2);
+
+        subsonic.set_dc_blocker_enabled(false);
+        assert_eq!(subsonic.process_sample(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_subsonic_is_steep() {
+        let mut subsonic = Subsonic::new(SAMPLE_RATE as usize);
+        subsonic.set_subsonic_enabled(true);
+        subsonic.set_subsonic_frequency(30.0);
+
+        assert_approx_eq!(get_gain(&mut subsonic, 30.0), FRAC_1_SQRT_2, 2.0e-2);
+        // an octave down is 48 dB lower
+        assert!(get_gain(&mut subsonic, 15.0) < 0.005);
+        assert_approx_eq!(get_gain(&mut subsonic, 120.0), 1.0, 1.0e-2);
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::envelope::EnvelopeStage;
+use crate::KickSynth;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SynthStateSnapshot {
+    pub frequency: f32,
+    pub amp_envelope_value: f32,
+    pub amp_envelope_stage: EnvelopeStage,
+    pub pitch_envelope_segment: i32,
+    pub voice_count: u32,
+    pub peak_output: f32,
+    pub saturation_gain_reduction_db: f32,
+    pub tempo: f32,
+    pub ppq_position: f64,
+}
+
+impl Default for SynthStateSnapshot {
+    fn default() -> Self {
+        Self {
+            frequency: 0.0,
+            amp_envelope_value: 0.0,
+            amp_envelope_stage: EnvelopeStage::Off,
+            pitch_envelope_segment: 0,
+            voice_count: 0,
+            peak_output: 0.0,
+            saturation_gain_reduction_db: 0.0,
+            tempo: 0.0,
+            ppq_position: 0.0,
+        }
+    }
+}
+
+// Sequence lock, the audio thread publishes a snapshot after every block and never waits on a reader.
+// A reader retries when the audio thread published while it was reading, so it always gets one consistent block.
+pub struct SynthState {
+    sequence: AtomicU32,
+    frequency: AtomicU32,
+    amp_envelope_value: AtomicU32,
+    amp_envelope_stage: AtomicU32,
+    pitch_envelope_segment: AtomicU32,
+    voice_count: AtomicU32,
+    peak_output: AtomicU32,
+    saturation_gain_reduction_db: AtomicU32,
+    tempo: AtomicU32,
+    ppq_position: AtomicU64,
+}
+
+impl SynthState {
+    pub(crate) fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+            frequency: AtomicU32::new(0),
+            amp_envelope_value: AtomicU32::new(0),
+            amp_envelope_stage: AtomicU32::new(EnvelopeStage::Off as u32),
+            pitch_envelope_segment: AtomicU32::new(0),
+            voice_count: AtomicU32::new(0),
+            peak_output: AtomicU32::new(0),
+            saturation_gain_reduction_db: AtomicU32::new(0),
+            tempo: AtomicU32::new(0),
+            ppq_position: AtomicU64::new(0),
+        }
+    }
+
+    // Only the audio thread publishes
+    pub(crate) fn publish(&self, snapshot: &SynthStateSnapshot) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence.wrapping_add(1), Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::Release);
+
+        self.frequency.store(snapshot.frequency.to_bits(), Ordering::Relaxed);
+        self.amp_envelope_value.store(snapshot.amp_envelope_value.to_bits(), Ordering::Relaxed);
+        self.amp_envelope_stage.store(snapshot.amp_envelope_stage as u32, Ordering::Relaxed);
+        self.pitch_envelope_segment.store(snapshot.pitch_envelope_segment as u32, Ordering::Relaxed);
+        self.voice_count.store(snapshot.voice_count, Ordering::Relaxed);
+        self.peak_output.store(snapshot.peak_output.to_bits(), Ordering::Relaxed);
+        self.saturation_gain_reduction_db.store(snapshot.saturation_gain_reduction_db.to_bits(), Ordering::Relaxed);
+        self.tempo.store(snapshot.tempo.to_bits(), Ordering::Relaxed);
+        self.ppq_position.store(snapshot.ppq_position.to_bits(), Ordering::Relaxed);
+
+        self.sequence.store(sequence.wrapping_add(2), Ordering::Release);
+    }
+
+    pub(crate) fn read(&self) -> SynthStateSnapshot {
+        loop {
+            let sequence_before = self.sequence.load(Ordering::Acquire);
+            if sequence_before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let snapshot = SynthStateSnapshot {
+                frequency: f32::from_bits(self.frequency.load(Ordering::Relaxed)),
+                amp_envelope_value: f32::from_bits(self.amp_envelope_value.load(Ordering::Relaxed)),
+                amp_envelope_stage: match self.amp_envelope_stage.load(Ordering::Relaxed) {
+                    1 => EnvelopeStage::Attack,
+                    2 => EnvelopeStage::Decay,
+                    3 => EnvelopeStage::Release,
+                    4 => EnvelopeStage::Hold,
+                    5 => EnvelopeStage::Sustain,
+                    6 => EnvelopeStage::Curve,
+                    _ => EnvelopeStage::Off,
+                },
+                pitch_envelope_segment: self.pitch_envelope_segment.load(Ordering::Relaxed) as i32,
+                voice_count: self.voice_count.load(Ordering::Relaxed),
+                peak_output: f32::from_bits(self.peak_output.load(Ordering::Relaxed)),
+                saturation_gain_reduction_db: f32::from_bits(self.saturation_gain_reduction_db.load(Ordering::Relaxed)),
+                tempo: f32::from_bits(self.tempo.load(Ordering::Relaxed)),
+                ppq_position: f64::from_bits(self.ppq_position.load(Ordering::Relaxed)),
+            };
+
+            std::sync::atomic::fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == sequence_before {
+                return snapshot;
+            }
+        }
+    }
+}
+
+/// Returns a handle to the published state of the kick synth, which stays valid until `destroy_synth_state` is called.
+/// Get the handle before starting the audio thread, after that it can be used from any thread.
+#[no_mangle]
+pub extern "C" fn create_synth_state(kick_synth: &KickSynth) -> *const SynthState {
+    Arc::into_raw(kick_synth.get_synth_state())
+}
+
+/// # Safety
+/// `synth_state` must be a handle returned by `create_synth_state`, it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn destroy_synth_state(synth_state: *const SynthState) {
+    assert!(!synth_state.is_null());
+    drop(Arc::from_raw(synth_state));
+}
+
+/// Copies the state as it was after the last processed block, can be called from any thread.
+#[no_mangle]
+pub extern "C" fn read_synth_state(synth_state: &SynthState, snapshot: &mut SynthStateSnapshot) {
+    *snapshot = synth_state.read();
+}
+
+#[cfg(test)]
+mod test {
+    use crate::envelope::EnvelopeStage;
+    use crate::midi_message::MidiMessage;
+    use crate::synth_state::{SynthState, SynthStateSnapshot};
+    use crate::KickSynth;
+
+    #[test]
+    fn test_publish_and_read() {
+        let synth_state = SynthState::new();
+        assert_eq!(synth_state.read(), SynthStateSnapshot::default());
+
+        let snapshot = SynthStateSnapshot {
+            frequency: 55.0,
+            amp_envelope_value: 0.5,
+            amp_envelope_stage: EnvelopeStage::Release,
+            pitch_envelope_segment: 3,
+            voice_count: 1,
+            peak_output: 0.8,
+            saturation_gain_reduction_db: -3.0,
+            tempo: 128.0,
+            ppq_position: 1024.25,
+        };
+        synth_state.publish(&snapshot);
+
+        assert_eq!(synth_state.read(), snapshot);
+    }
+
+    #[test]
+    fn test_kick_synth_publishes_after_block() {
+        let mut kick_synth = KickSynth::new(1000);
+        kick_synth.set_param("waveType", 0.0);
+        kick_synth.set_param("timing_1", 10.0);
+
+        let synth_state = kick_synth.get_synth_state();
+        let mut block = [0.0_f32; 4];
+
+        kick_synth.process_block(&mut block);
+        assert_eq!(synth_state.read().voice_count, 0);
+        assert_eq!(synth_state.read().amp_envelope_stage, EnvelopeStage::Off);
+
+        kick_synth.queue_midi_message(&MidiMessage::new(0, &0x90, 36, 100));
+        kick_synth.process_block(&mut block);
+
+        let snapshot = synth_state.read();
+        // still on the way from the 20 Hz start to C0 (16.35 Hz)
+        assert_eq!(snapshot.pitch_envelope_segment, 0);
+        assert!(snapshot.frequency < 20.0 && snapshot.frequency > 16.35);
+    }
+
+    #[test]
+    fn test_transport_is_published() {
+        let mut kick_synth = KickSynth::new(1000);
+        let synth_state = kick_synth.get_synth_state();
+        let mut block = [0.0_f32; 4];
+
+        kick_synth.set_transport(174.0, 96.5);
+        kick_synth.process_block(&mut block);
+
+        assert_eq!(synth_state.read().tempo, 174.0);
+        assert_eq!(synth_state.read().ppq_position, 96.5);
+    }
+}
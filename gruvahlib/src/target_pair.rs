@@ -10,78 +10,242 @@
  * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
  */
 
-pub static TARGET_DIVISOR: f32 = 1000.0;
+pub(crate) const DEFAULT_SMOOTHING_MS: f32 = 20.0;
 
-// Slowly update a current value to a target value in order to avoid clicks
+// A one pole ramp never quite arrives, it jumps to the target once it is this close, relative to the target
+const ONE_POLE_SETTLE_THRESHOLD: f32 = 1.0e-5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmoothingMode {
+    // A straight line that arrives at the target after the smoothing time, for gains and times
+    Linear,
+    // Covers most of the distance at the start, the smoothing time is its time constant
+    OnePole,
+}
+
+pub(crate) trait Smoothable: Copy + PartialEq {
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Smoothable for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl Smoothable for i32 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as i32
+    }
+}
+
+// Slowly update a current value to a target value in order to avoid clicks.
+// The smoothing time is in ms, so it sounds the same at every sample rate.
+#[derive(Clone, Copy)]
 pub struct TargetCurrentPair<T>
-    where T: Copy {
+    where T: Smoothable {
     target: T,
-    current: T,
+    current: f32,
+    mode: SmoothingMode,
+    smoothing_samples: f32,
+    // linear ramps take a fixed step for the samples that are left
+    step: f32,
+    remaining_samples: usize,
+    // the part of the distance a one pole ramp covers per sample
+    coefficient: f32,
+    // the first target is where the value starts, there is nothing to avoid a click with yet
+    has_target: bool,
 }
 
 
-impl<T: Copy> TargetCurrentPair<T> {
+impl<T: Smoothable> TargetCurrentPair<T> {
+    // Without with_smoothing the value jumps to every target
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            target: value,
+            current: value.to_f32(),
+            mode: SmoothingMode::Linear,
+            smoothing_samples: 0.0,
+            step: 0.0,
+            remaining_samples: 0,
+            coefficient: 1.0,
+            has_target: false,
+        }
+    }
+
+    pub(crate) fn with_smoothing(mut self, sample_rate: usize, smoothing_ms: f32, mode: SmoothingMode) -> Self {
+        self.mode = mode;
+        self.smoothing_samples = (smoothing_ms / 1000.0 * sample_rate as f32).max(0.0);
+        self.coefficient = match self.smoothing_samples {
+            smoothing_samples if smoothing_samples < 1.0 => 1.0,
+            smoothing_samples => 1.0 - (-1.0 / smoothing_samples).exp(),
+        };
+        self.settle();
+        self
+    }
+
     pub(crate) fn get(&self) -> T {
-        self.current
+        T::from_f32(self.current)
     }
 
     pub(crate) fn set_target(&mut self, target: T) {
         self.target = target;
-    }
-
-}
 
-impl TargetCurrentPair<f32> {
-    pub(crate) fn update_to_target(&mut self) {
-        if self.current == self.target {
+        if !self.has_target || self.smoothing_samples < 1.0 {
+            self.has_target = true;
+            self.settle();
             return;
         }
 
-        // println!("current: {}, target: {}", self.current, self.target);
+        self.remaining_samples = self.smoothing_samples as usize;
+        self.step = (target.to_f32() - self.current) / self.remaining_samples as f32;
+    }
 
-        let difference = self.target - self.current;
-        let delta = difference / TARGET_DIVISOR;
+    pub(crate) fn is_smoothing(&self) -> bool {
+        self.current != self.target.to_f32()
+    }
 
-        if delta.abs() < 1.0e-6 {
-            self.current = self.target;
-        } else {
-            self.current += delta;
-        }
+    // Jump straight to the target, skipping the smoothing
+    pub(crate) fn settle(&mut self) {
+        self.current = self.target.to_f32();
+        self.remaining_samples = 0;
     }
-}
 
-impl TargetCurrentPair<i32> {
     pub(crate) fn update_to_target(&mut self) {
-        if self.current == self.target {
+        self.update_to_target_by(1);
+    }
+
+    // Moves num_samples samples along the ramp at once, for values that only change once per block
+    pub(crate) fn update_to_target_by(&mut self, num_samples: usize) {
+        if !self.is_smoothing() {
             return;
         }
 
+        let target = self.target.to_f32();
+
+        match self.mode {
+            SmoothingMode::Linear => {
+                let num_samples = num_samples.min(self.remaining_samples);
+                self.current += self.step * num_samples as f32;
+                self.remaining_samples -= num_samples;
+
+                if self.remaining_samples == 0 {
+                    self.current = target;
+                }
+            }
+            SmoothingMode::OnePole => {
+                let coefficient = match num_samples {
+                    1 => self.coefficient,
+                    _ => 1.0 - (1.0 - self.coefficient).powi(num_samples as i32),
+                };
+                self.current += (target - self.current) * coefficient;
+
+                if (target - self.current).abs() <= ONE_POLE_SETTLE_THRESHOLD * target.abs().max(1.0) {
+                    self.current = target;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Smoothable + Default> Default for TargetCurrentPair<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
 
-        let difference = self.target- self.current;
-        let delta = difference as f32 / TARGET_DIVISOR;
-        let delta = delta.signum() * delta.abs().ceil();
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::target_pair::{SmoothingMode, TargetCurrentPair};
 
-        self.current += delta as i32;
+    fn get_smoothed_pair(sample_rate: usize, mode: SmoothingMode) -> TargetCurrentPair<f32> {
+        let mut pair = TargetCurrentPair::new(0.0).with_smoothing(sample_rate, 10.0, mode);
+        pair.set_target(0.0);
+        pair.set_target(1.0);
+        pair
+    }
 
-        // println!("current: {}, target: {}, delta: {}", self.current, self.target, delta);
+    #[test]
+    fn test_first_target_jumps() {
+        let mut pair = TargetCurrentPair::new(0.0).with_smoothing(1000, 10.0, SmoothingMode::Linear);
+        pair.set_target(0.5);
+        assert_eq!(pair.get(), 0.5);
+        assert!(!pair.is_smoothing());
 
+        pair.set_target(1.0);
+        assert_eq!(pair.get(), 0.5);
+        assert!(pair.is_smoothing());
     }
-}
 
-impl Default for TargetCurrentPair<f32> {
-    fn default() -> Self {
-        Self {
-            target: 0.0,
-            current: 0.0,
+    #[test]
+    fn test_linear_time_is_independent_of_sample_rate() {
+        for sample_rate in [1000, 48000] {
+            let mut pair = get_smoothed_pair(sample_rate, SmoothingMode::Linear);
+            let smoothing_samples = sample_rate / 100;
+
+            for _ in 0..smoothing_samples / 2 {
+                pair.update_to_target();
+            }
+            assert_approx_eq!(pair.get(), 0.5, 1.0e-3);
+
+            for _ in smoothing_samples / 2..smoothing_samples {
+                pair.update_to_target();
+            }
+            assert_eq!(pair.get(), 1.0);
+            assert!(!pair.is_smoothing());
         }
     }
-}
 
-impl Default for TargetCurrentPair<i32> {
-    fn default() -> Self {
-        Self {
-            target: 0,
-            current: 0,
+    #[test]
+    fn test_one_pole_time_constant() {
+        for sample_rate in [1000, 48000] {
+            let mut pair = get_smoothed_pair(sample_rate, SmoothingMode::OnePole);
+
+            for _ in 0..sample_rate / 100 {
+                pair.update_to_target();
+            }
+            assert_approx_eq!(pair.get(), 1.0 - (-1.0_f32).exp(), 1.0e-3);
         }
     }
+
+    #[test]
+    fn test_block_wise_smoothing() {
+        for mode in [SmoothingMode::Linear, SmoothingMode::OnePole] {
+            let mut per_sample = get_smoothed_pair(1000, mode);
+            let mut per_block = get_smoothed_pair(1000, mode);
+
+            for _ in 0..4 {
+                per_sample.update_to_target();
+            }
+            per_block.update_to_target_by(4);
+
+            assert_approx_eq!(per_sample.get(), per_block.get(), 1.0e-5);
+        }
+
+        let mut pair = get_smoothed_pair(1000, SmoothingMode::Linear);
+        pair.update_to_target_by(64);
+        assert_eq!(pair.get(), 1.0);
+    }
+
+    #[test]
+    fn test_integer_values() {
+        let mut pair = TargetCurrentPair::new(0).with_smoothing(1000, 10.0, SmoothingMode::Linear);
+        pair.set_target(100);
+        pair.set_target(200);
+
+        pair.update_to_target_by(5);
+        assert_eq!(pair.get(), 150);
+        pair.update_to_target_by(5);
+        assert_eq!(pair.get(), 200);
+    }
 }
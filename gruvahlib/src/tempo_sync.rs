@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+use crate::params::{Param, PitchParam};
+use crate::pitch_envelope::MAX_PITCH_POINTS;
+
+// Times that can follow the tempo of the host instead of their value in ms.
+// Every time parameter passes through TempoSync, which hands on the time in ms of its division while it is synced.
+
+pub(crate) const DEFAULT_TEMPO: f32 = 120.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncDivision {
+    Off,
+    SixtyFourth,
+    ThirtySecond,
+    SixteenthTriplet,
+    Sixteenth,
+    SixteenthDotted,
+    EighthTriplet,
+    Eighth,
+    EighthDotted,
+    QuarterTriplet,
+    Quarter,
+    QuarterDotted,
+    Half,
+    Whole,
+}
+
+impl SyncDivision {
+    pub(crate) fn from_value(new_value: f32) -> Option<Self> {
+        match new_value as i32 {
+            0 => Some(SyncDivision::Off),
+            1 => Some(SyncDivision::SixtyFourth),
+            2 => Some(SyncDivision::ThirtySecond),
+            3 => Some(SyncDivision::SixteenthTriplet),
+            4 => Some(SyncDivision::Sixteenth),
+            5 => Some(SyncDivision::SixteenthDotted),
+            6 => Some(SyncDivision::EighthTriplet),
+            7 => Some(SyncDivision::Eighth),
+            8 => Some(SyncDivision::EighthDotted),
+            9 => Some(SyncDivision::QuarterTriplet),
+            10 => Some(SyncDivision::Quarter),
+            11 => Some(SyncDivision::QuarterDotted),
+            12 => Some(SyncDivision::Half),
+            13 => Some(SyncDivision::Whole),
+            _ => None,
+        }
+    }
+
+    // length in quarter notes, None when not synced
+    fn get_beats(&self) -> Option<f32> {
+        match self {
+            SyncDivision::Off => None,
+            SyncDivision::SixtyFourth => Some(1.0 / 16.0),
+            SyncDivision::ThirtySecond => Some(1.0 / 8.0),
+            SyncDivision::SixteenthTriplet => Some(1.0 / 6.0),
+            SyncDivision::Sixteenth => Some(1.0 / 4.0),
+            SyncDivision::SixteenthDotted => Some(3.0 / 8.0),
+            SyncDivision::EighthTriplet => Some(1.0 / 3.0),
+            SyncDivision::Eighth => Some(1.0 / 2.0),
+            SyncDivision::EighthDotted => Some(3.0 / 4.0),
+            SyncDivision::QuarterTriplet => Some(2.0 / 3.0),
+            SyncDivision::Quarter => Some(1.0),
+            SyncDivision::QuarterDotted => Some(1.5),
+            SyncDivision::Half => Some(2.0),
+            SyncDivision::Whole => Some(4.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeParam {
+    PitchTiming(i32),
+    PitchReturnTime,
+    AmpAttack,
+    AmpHold,
+    AmpDecay,
+    AmpSustainTime,
+    AmpRelease,
+}
+
+pub(crate) const TIME_PARAM_COUNT: usize = MAX_PITCH_POINTS + 6;
+
+impl TimeParam {
+    fn get_index(&self) -> usize {
+        match self {
+            TimeParam::PitchTiming(note_number) => *note_number as usize,
+            TimeParam::PitchReturnTime => MAX_PITCH_POINTS,
+            TimeParam::AmpAttack => MAX_PITCH_POINTS + 1,
+            TimeParam::AmpHold => MAX_PITCH_POINTS + 2,
+            TimeParam::AmpDecay => MAX_PITCH_POINTS + 3,
+            TimeParam::AmpSustainTime => MAX_PITCH_POINTS + 4,
+            TimeParam::AmpRelease => MAX_PITCH_POINTS + 5,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            index if index < MAX_PITCH_POINTS => TimeParam::PitchTiming(index as i32),
+            index if index == MAX_PITCH_POINTS => TimeParam::PitchReturnTime,
+            index if index == MAX_PITCH_POINTS + 1 => TimeParam::AmpAttack,
+            index if index == MAX_PITCH_POINTS + 2 => TimeParam::AmpHold,
+            index if index == MAX_PITCH_POINTS + 3 => TimeParam::AmpDecay,
+            index if index == MAX_PITCH_POINTS + 4 => TimeParam::AmpSustainTime,
+            _ => TimeParam::AmpRelease,
+        }
+    }
+
+    fn to_param(self, time_ms: f32) -> Param {
+        match self {
+            TimeParam::PitchTiming(note_number) => Param::Pitch(note_number, PitchParam::Timing(time_ms)),
+            TimeParam::PitchReturnTime => Param::PitchReturnTime(time_ms),
+            TimeParam::AmpAttack => Param::AmpAttack(time_ms),
+            TimeParam::AmpHold => Param::AmpHold(time_ms),
+            TimeParam::AmpDecay => Param::AmpDecay(time_ms),
+            TimeParam::AmpSustainTime => Param::AmpSustainTime(time_ms),
+            TimeParam::AmpRelease => Param::AmpRelease(time_ms),
+        }
+    }
+
+    // The time param and its value in ms, for parameters that are a time
+    fn from_param(param: &Param) -> Option<(Self, f32)> {
+        match *param {
+            Param::Pitch(note_number, PitchParam::Timing(time_ms)) => Some((TimeParam::PitchTiming(note_number), time_ms)),
+            Param::PitchReturnTime(time_ms) => Some((TimeParam::PitchReturnTime, time_ms)),
+            Param::AmpAttack(time_ms) => Some((TimeParam::AmpAttack, time_ms)),
+            Param::AmpHold(time_ms) => Some((TimeParam::AmpHold, time_ms)),
+            Param::AmpDecay(time_ms) => Some((TimeParam::AmpDecay, time_ms)),
+            Param::AmpSustainTime(time_ms) => Some((TimeParam::AmpSustainTime, time_ms)),
+            Param::AmpRelease(time_ms) => Some((TimeParam::AmpRelease, time_ms)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TempoSync {
+    tempo: f32,
+    // None until the parameter was set, so a division alone doesn't overwrite the default of the dsp
+    times_ms: [Option<f32>; TIME_PARAM_COUNT],
+    divisions: [SyncDivision; TIME_PARAM_COUNT],
+}
+
+impl TempoSync {
+    pub(crate) fn new() -> Self {
+        Self {
+            tempo: DEFAULT_TEMPO,
+            times_ms: [None; TIME_PARAM_COUNT],
+            divisions: [SyncDivision::Off; TIME_PARAM_COUNT],
+        }
+    }
+
+    fn get_time_ms(&self, index: usize) -> Option<f32> {
+        match self.divisions[index].get_beats() {
+            Some(beats) => Some(beats * 60000.0 / self.tempo),
+            None => self.times_ms[index],
+        }
+    }
+
+    // The time of the parameter at index, as a parameter, None while it has neither a time nor a division
+    fn get_time_param(&self, index: usize) -> Option<Param> {
+        self.get_time_ms(index).map(|time_ms| TimeParam::from_index(index).to_param(time_ms))
+    }
+
+    // Returns the parameter to apply, time parameters and sync divisions as the time they should get now.
+    // The caller applies it, so tempo_sync and the dsp it feeds can be borrowed separately.
+    pub(crate) fn resolve(&mut self, param: Param) -> Option<Param> {
+        if let Param::Sync(time_param, division) = param {
+            let index = time_param.get_index();
+            self.divisions[index] = division;
+            return self.get_time_param(index);
+        }
+
+        let Some((time_param, time_ms)) = TimeParam::from_param(&param) else {
+            return Some(param);
+        };
+
+        let index = time_param.get_index();
+        self.times_ms[index] = Some(time_ms);
+        self.get_time_param(index)
+    }
+
+    // True when the tempo changed, get_synced_param then has the new time of every synced parameter
+    pub(crate) fn set_tempo(&mut self, tempo: f32) -> bool {
+        if tempo <= 0.0 || tempo == self.tempo {
+            return false;
+        }
+
+        self.tempo = tempo;
+        true
+    }
+
+    // None for indexes that aren't synced, every index below TIME_PARAM_COUNT can be asked for
+    pub(crate) fn get_synced_param(&self, index: usize) -> Option<Param> {
+        match self.divisions[index] {
+            SyncDivision::Off => None,
+            _ => self.get_time_param(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::params::{Param, PitchParam};
+    use crate::tempo_sync::{SyncDivision, TempoSync, TimeParam, TIME_PARAM_COUNT};
+
+    fn get_release(tempo_sync: &mut TempoSync, param: Param) -> Option<f32> {
+        match tempo_sync.resolve(param) {
+            Some(Param::AmpRelease(time_ms)) => Some(time_ms),
+            _ => None,
+        }
+    }
+
+    fn get_synced_params(tempo_sync: &TempoSync) -> Vec<Param> {
+        (0..TIME_PARAM_COUNT).filter_map(|index| tempo_sync.get_synced_param(index)).collect()
+    }
+
+    #[test]
+    fn test_synced_release() {
+        let mut tempo_sync = TempoSync::new();
+
+        assert_eq!(get_release(&mut tempo_sync, Param::AmpRelease(300.0)), Some(300.0));
+        // an eighth at 120 bpm
+        assert_eq!(get_release(&mut tempo_sync, Param::Sync(TimeParam::AmpRelease, SyncDivision::Eighth)), Some(250.0));
+        assert_eq!(get_release(&mut tempo_sync, Param::AmpRelease(400.0)), Some(250.0));
+
+        assert!(tempo_sync.set_tempo(150.0));
+        assert!(!tempo_sync.set_tempo(150.0));
+        assert!(matches!(get_synced_params(&tempo_sync)[..], [Param::AmpRelease(time_ms)] if time_ms == 200.0));
+
+        assert_eq!(get_release(&mut tempo_sync, Param::Sync(TimeParam::AmpRelease, SyncDivision::Off)), Some(400.0));
+    }
+
+    #[test]
+    fn test_tempo_only_changes_synced_times() {
+        let mut tempo_sync = TempoSync::new();
+        tempo_sync.resolve(Param::Pitch(2, PitchParam::Timing(20.0)));
+        tempo_sync.resolve(Param::Sync(TimeParam::PitchTiming(3), SyncDivision::SixteenthTriplet));
+
+        assert!(tempo_sync.set_tempo(100.0));
+        assert!(matches!(get_synced_params(&tempo_sync)[..], [Param::Pitch(3, PitchParam::Timing(time_ms))] if time_ms == 100.0));
+    }
+
+    #[test]
+    fn test_other_params_pass_through() {
+        let mut tempo_sync = TempoSync::new();
+
+        assert!(matches!(tempo_sync.resolve(Param::AmpSustain(50.0)), Some(Param::AmpSustain(sustain)) if sustain == 50.0));
+    }
+}
@@ -0,0 +1,30 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+use std::f32::consts::PI;
+
+// The level of a sine with a peak of 1 once process has settled, from the RMS of the second half of a second.
+// A process that passes the sine unchanged has a level of 1.
+pub(crate) fn get_sine_gain(mut process: impl FnMut(f32) -> f32, frequency: f32, sample_rate: f32) -> f32 {
+    let num_samples = sample_rate as usize;
+    let mut sum_of_squares = 0.0;
+
+    for n in 0..num_samples {
+        let output = process((2.0 * PI * frequency * n as f32 / sample_rate).sin());
+        if n >= num_samples / 2 {
+            sum_of_squares += output * output;
+        }
+    }
+
+    (2.0 * sum_of_squares / (num_samples / 2) as f32).sqrt()
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::target_pair::{DEFAULT_SMOOTHING_MS, SmoothingMode, TargetCurrentPair};
+use crate::utils::{db_to_linear, linear_to_db};
+
+// How far apart the fast and the slow follower have to be, in dB, before any and for the full attack or sustain gain.
+// Below the threshold the followers are only apart because they ripple with the waveform of the kick.
+const DETECTION_THRESHOLD_DB: f32 = 2.0;
+const DETECTION_RANGE_DB: f32 = 6.0;
+// What full punch adds to the attack and the sustain gain, in dB
+const PUNCH_ATTACK_DB: f32 = 12.0;
+const PUNCH_SUSTAIN_DB: f32 = -6.0;
+
+// From 0 at the threshold to 1 at the end of the range
+fn get_detection(difference: f32) -> f32 {
+    ((difference - DETECTION_THRESHOLD_DB) / DETECTION_RANGE_DB).clamp(0.0, 1.0)
+}
+
+// Follows the peak level, rising with the attack time and falling with the release time
+#[derive(Clone, Copy)]
+struct EnvelopeFollower {
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    fn new(sample_rate: usize, attack_ms: f32, release_ms: f32) -> Self {
+        let get_coefficient = |ms: f32| 1.0 - (-1000.0 / (ms * sample_rate as f32)).exp();
+
+        Self {
+            attack_coefficient: get_coefficient(attack_ms),
+            release_coefficient: get_coefficient(release_ms),
+            level: 0.0,
+        }
+    }
+
+    fn process_sample(&mut self, input: f32) -> f32 {
+        let input = input.abs();
+        let coefficient = match input > self.level {
+            true => self.attack_coefficient,
+            false => self.release_coefficient,
+        };
+        self.level += (input - self.level) * coefficient;
+
+        self.level
+    }
+}
+
+// Turns the attack of the kick up or down and the body after it, independently of the amp envelope.
+// A follower with a fast attack gets ahead of a slower one that follows it at the start of the kick, and a follower
+// with a slow release stays above one with a fast release while it decays. How far apart they are decides
+// how much of the attack and the sustain gain is applied.
+pub(crate) struct TransientShaper {
+    fast_attack: EnvelopeFollower,
+    slow_attack: EnvelopeFollower,
+    fast_release: EnvelopeFollower,
+    slow_release: EnvelopeFollower,
+    // in dB
+    attack: TargetCurrentPair<f32>,
+    sustain: TargetCurrentPair<f32>,
+    attack_gain: f32,
+    sustain_gain: f32,
+    // from 0 to 1, raises the attack and lowers the sustain together
+    punch: f32,
+}
+
+impl TransientShaper {
+    pub(crate) fn new(sample_rate: usize) -> Self {
+        let smoothed = || TargetCurrentPair::new(0.0).with_smoothing(sample_rate, DEFAULT_SMOOTHING_MS, SmoothingMode::Linear);
+
+        Self {
+            fast_attack: EnvelopeFollower::new(sample_rate, 0.5, 100.0),
+            slow_attack: EnvelopeFollower::new(sample_rate, 15.0, 100.0),
+            fast_release: EnvelopeFollower::new(sample_rate, 0.5, 40.0),
+            slow_release: EnvelopeFollower::new(sample_rate, 0.5, 300.0),
+            attack: smoothed(),
+            sustain: smoothed(),
+            attack_gain: 0.0,
+            sustain_gain: 0.0,
+            punch: 0.0,
+        }
+    }
+
+    pub(crate) fn process_sample(&mut self, input: f32) -> f32 {
+        self.attack.update_to_target();
+        self.sustain.update_to_target();
+
+        let fast_attack = self.fast_attack.process_sample(input);
+        let slow_attack = self.slow_attack.process_sample(fast_attack);
+        let attack_difference = linear_to_db(fast_attack) - linear_to_db(slow_attack);
+        let sustain_difference = linear_to_db(self.slow_release.process_sample(input)) - linear_to_db(self.fast_release.process_sample(input));
+
+        let gain = self.attack.get() * get_detection(attack_difference) + self.sustain.get() * get_detection(sustain_difference);
+
+        input * db_to_linear(gain)
+    }
+
+    pub(crate) fn settle(&mut self) {
+        self.attack.settle();
+        self.sustain.settle();
+    }
+
+    fn update_gains(&mut self) {
+        self.attack.set_target(self.attack_gain + PUNCH_ATTACK_DB * self.punch);
+        self.sustain.set_target(self.sustain_gain + PUNCH_SUSTAIN_DB * self.punch);
+    }
+
+    pub(crate) fn set_attack(&mut self, attack_gain: f32) {
+        self.attack_gain = attack_gain;
+        self.update_gains();
+    }
+
+    pub(crate) fn set_sustain(&mut self, sustain_gain: f32) {
+        self.sustain_gain = sustain_gain;
+        self.update_gains();
+    }
+
+    pub(crate) fn set_punch(&mut self, punch: f32) {
+        self.punch = punch.clamp(0.0, 1.0);
+        self.update_gains();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+    use crate::transient_shaper::TransientShaper;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // The peaks of the first 10 ms and of 150 to 200 ms of a kick like sine that starts at once and decays
+    fn get_attack_and_sustain(transient_shaper: &mut TransientShaper) -> (f32, f32) {
+        let output: Vec<f32> = (0..SAMPLE_RATE / 5)
+            .map(|n| {
+                let time = n as f32 / SAMPLE_RATE as f32;
+                transient_shaper.process_sample((2.0 * PI * 60.0 * time).sin() * (-time / 0.15).exp())
+            })
+            .collect();
+        let peak = |samples: &[f32]| samples.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+
+        (peak(&output[..SAMPLE_RATE / 100]), peak(&output[SAMPLE_RATE * 3 / 20..]))
+    }
+
+    #[test]
+    fn test_unchanged_by_default() {
+        let mut transient_shaper = TransientShaper::new(SAMPLE_RATE);
+
+        for n in 0..1000 {
+            let x = (n as f32 * 0.01).sin();
+            assert_eq!(transient_shaper.process_sample(x), x);
+        }
+    }
+
+    #[test]
+    fn test_attack_and_sustain() {
+        let (attack, sustain) = get_attack_and_sustain(&mut TransientShaper::new(SAMPLE_RATE));
+
+        let mut louder_attack = TransientShaper::new(SAMPLE_RATE);
+        louder_attack.set_attack(12.0);
+        let (shaped_attack, shaped_sustain) = get_attack_and_sustain(&mut louder_attack);
+        assert!(shaped_attack > attack * 1.5);
+        assert!((shaped_sustain - sustain).abs() < sustain * 0.1);
+
+        let mut quieter_sustain = TransientShaper::new(SAMPLE_RATE);
+        quieter_sustain.set_sustain(-12.0);
+        let (shaped_attack, shaped_sustain) = get_attack_and_sustain(&mut quieter_sustain);
+        assert!((shaped_attack - attack).abs() < attack * 0.1);
+        assert!(shaped_sustain < sustain * 0.6);
+    }
+
+    #[test]
+    fn test_punch() {
+        let (attack, sustain) = get_attack_and_sustain(&mut TransientShaper::new(SAMPLE_RATE));
+
+        let mut punchy = TransientShaper::new(SAMPLE_RATE);
+        punchy.set_punch(1.0);
+        let (punchy_attack, punchy_sustain) = get_attack_and_sustain(&mut punchy);
+        assert!(punchy_attack / punchy_sustain > attack / sustain * 2.0);
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) 2023 Joshua Azimullah
+ *
+ * This file is part of Gruvah.
+ *
+ * Gruvah is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * Gruvah is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with Gruvah. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+// Turns midi note numbers into frequencies, shared by every part of the pitch envelope
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Tuning {
+    a4_frequency: f32,
+    fine_tune: f32,
+    // a loaded Scala tuning, which brings its own reference frequency instead of a4_frequency
+    note_frequencies: Option<[f32; MIDI_NOTE_COUNT]>,
+}
+
+pub(crate) const DEFAULT_A4_FREQUENCY: f32 = 440.0;
+pub(crate) const MIDI_NOTE_COUNT: usize = 128;
+const A4_MIDI_NOTE: f32 = 69.0;
+
+impl Tuning {
+    pub(crate) fn new() -> Self {
+        Self {
+            a4_frequency: DEFAULT_A4_FREQUENCY,
+            fine_tune: 0.0,
+            note_frequencies: None,
+        }
+    }
+
+    pub(crate) fn set_a4_frequency(&mut self, a4_frequency: f32) {
+        self.a4_frequency = a4_frequency.max(1.0);
+    }
+
+    // in cents, applied to every note
+    pub(crate) fn set_fine_tune(&mut self, fine_tune: f32) {
+        self.fine_tune = fine_tune;
+    }
+
+    // None goes back to 12 tone equal temperament
+    pub(crate) fn set_note_frequencies(&mut self, note_frequencies: Option<[f32; MIDI_NOTE_COUNT]>) {
+        self.note_frequencies = note_frequencies;
+    }
+
+    // midi_note can be fractional, 60.5 is a quarter tone above C4
+    pub(crate) fn get_frequency(&self, midi_note: f32) -> f32 {
+        let Some(note_frequencies) = &self.note_frequencies else {
+            let semitones_from_a4 = midi_note + self.fine_tune / 100.0 - A4_MIDI_NOTE;
+            return self.a4_frequency * 2.0_f32.powf(semitones_from_a4 / 12.0);
+        };
+
+        // in semitones between the two closest keys, notes outside the midi range continue the step of the outer keys
+        let lower_note = (midi_note.floor() as i32).clamp(0, MIDI_NOTE_COUNT as i32 - 2) as usize;
+        let lower_frequency = note_frequencies[lower_note];
+        let step = note_frequencies[lower_note + 1] / lower_frequency;
+
+        lower_frequency * step.powf(midi_note - lower_note as f32) * 2.0_f32.powf(self.fine_tune / 1200.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_approx_eq::assert_approx_eq;
+    use crate::scala::parse_tuning;
+    use crate::tuning::Tuning;
+
+    #[test]
+    fn test_default_tuning_matches_midi() {
+        let tuning = Tuning::new();
+        assert_approx_eq!(tuning.get_frequency(69.0), 440.0, 0.001);
+        assert_approx_eq!(tuning.get_frequency(60.0), 261.63, 0.01);
+    }
+
+    #[test]
+    fn test_reference_and_fine_tune() {
+        let mut tuning = Tuning::new();
+        tuning.set_a4_frequency(432.0);
+        assert_approx_eq!(tuning.get_frequency(69.0), 432.0, 0.001);
+
+        tuning.set_fine_tune(-1200.0);
+        assert_approx_eq!(tuning.get_frequency(69.0), 216.0, 0.001);
+
+        tuning.set_fine_tune(50.0);
+        assert_approx_eq!(tuning.get_frequency(68.5), 432.0, 0.001);
+    }
+
+    #[test]
+    fn test_scala_tuning() {
+        let mut tuning = Tuning::new();
+        tuning.set_note_frequencies(parse_tuning("Whole tones\n6\n200.0\n400.0\n600.0\n800.0\n1000.0\n2/1\n", None));
+        tuning.set_a4_frequency(432.0);
+
+        assert_approx_eq!(tuning.get_frequency(69.0), 440.0, 0.001);
+        assert_approx_eq!(tuning.get_frequency(70.0), 493.88, 0.01);
+        assert_approx_eq!(tuning.get_frequency(69.5), 466.16, 0.01);
+        assert_approx_eq!(tuning.get_frequency(130.0), tuning.get_frequency(127.0) * 2.0_f32.powf(3.0 / 6.0), 0.1);
+
+        tuning.set_fine_tune(-200.0);
+        assert_approx_eq!(tuning.get_frequency(70.0), 440.0, 0.001);
+
+        tuning.set_note_frequencies(None);
+        assert_approx_eq!(tuning.get_frequency(71.0), 432.0, 0.001);
+    }
+}
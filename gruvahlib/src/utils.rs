@@ -12,13 +12,17 @@
 
 
 pub fn db_to_linear(db: f32) -> f32 {
-    10.0_f32.powf(db / 10.0)
+    10.0_f32.powf(db / 20.0)
+}
+
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * linear.max(1.0e-9).log10()
 }
 
 #[cfg(test)]
 mod test {
     use assert_approx_eq::assert_approx_eq;
-    use crate::utils::db_to_linear;
+    use crate::utils::{db_to_linear, linear_to_db};
 
     #[test]
     fn test_0_db() {
@@ -29,4 +33,10 @@ mod test {
     fn test_6_db() {
         assert_approx_eq!(db_to_linear(6.0), 2.0, 0.005);
     }
+
+    #[test]
+    fn test_linear_to_db() {
+        assert_approx_eq!(linear_to_db(1.0), 0.0, 0.005);
+        assert_approx_eq!(linear_to_db(0.5), -6.02, 0.005);
+    }
 }
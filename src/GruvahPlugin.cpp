@@ -14,6 +14,11 @@
 
 static const juce::String midiNoteNames[] = { "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B" };
 
+// Has to match MAX_PITCH_POINTS in gruvahlib
+static constexpr int maxPitchPoints = 32;
+static constexpr int maxDistortionStages = 4;
+static constexpr int maxBands = 3;
+
 namespace
 {
     const String octave_1 = "octave_1";
@@ -32,17 +37,40 @@ namespace
     const String note_4 = "note_4";
     const String timing_4 = "timing_4";
 
+    const String pitchPointsTag = "pitch_points";
+    const String pitchStartFrequencyTag = "pitch_start_frequency";
+    const String pitchEndModeTag = "pitch_end_mode";
+    const String pitchReturnTimeTag = "pitch_return_time";
+    const String fineTuneTag = "fine_tune";
+    const String a4FrequencyTag = "a4_frequency";
+
     const String amp_attack = "amp_attack";
     const String amp_decay = "amp_decay";
     const String amp_sustain = "amp_sustain";
     const String amp_release = "amp_release";
     const String amp_exponential_factor_a = "amp_exponential_factor_a";
+    const String ampHoldTag = "amp_hold";
+    const String ampSustainTimeTag = "amp_sustain_time";
+    const String ampAttackCurveTag = "amp_attack_curve";
+    const String ampAttackTensionTag = "amp_attack_tension";
+    const String ampDecayCurveTag = "amp_decay_curve";
+    const String ampDecayTensionTag = "amp_decay_tension";
+    const String ampReleaseCurveTag = "amp_release_curve";
+    const String ampSourceTag = "amp_source";
+    const String pitchSourceTag = "pitch_source";
+    const String lengthTag = "length";
+    const String lengthKeepAttackTag = "length_keep_attack";
+
+    const StringArray saturationTypes ("None", "Soft", "Clip", "ExtremeClip", "Wavefolder", "Tube", "Diode", "Sine Shaper", "Bitcrush");
+    const StringArray syncDivisions ("Off", "1/64", "1/32", "1/16T", "1/16", "1/16D", "1/8T", "1/8", "1/8D", "1/4T", "1/4", "1/4D", "1/2", "1/1");
 
     const String phaseTag = "phase";
     const String waveTypeTag = "waveType";
 
     const String driveTag = "driveDb";
     const String saturationTypeTag = "saturationType";
+    const String oversamplingTag = "oversampling";
+    const String limiterTag = "limiter";
     AudioProcessorValueTreeState::Listener* paramListener;
 
 }
@@ -74,23 +102,171 @@ void Gruvah::addParameters (Parameters& params)
     params.push_back (std::make_unique<AudioParameterInt> (ParameterID { note_4, 1}, "Note 4", 0, 11, 9));
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { timing_4, 1}, "Timing 4 (ms)", 0.0f, 300.0f, 69.09f));
 
+    // Only the first pitch_points points are used, the rest can be switched on when a kick needs more detail
+    params.push_back (std::make_unique<AudioParameterInt> (ParameterID { pitchPointsTag, 2}, "Pitch Points", 1, maxPitchPoints, 4));
+
+    for (int block = 5; block <= maxPitchPoints; ++block)
+    {
+        params.push_back (std::make_unique<AudioParameterInt> (ParameterID { "octave_" + String (block), 2}, "Octave " + String (block), 0, 10, 1));
+        params.push_back (std::make_unique<AudioParameterInt> (ParameterID { "note_" + String (block), 2}, "Note " + String (block), 0, 11, 9));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "timing_" + String (block), 2}, "Timing " + String (block) + " (ms)", 0.0f, 300.0f, 10.0f));
+    }
+
+    // The curve of the sweep towards each pitch, tension only applies to the power curve
+    for (int block = 1; block <= maxPitchPoints; ++block)
+    {
+        params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "curve_" + String (block), 2}, "Curve " + String (block), StringArray ("Linear", "Exponential", "Power"), 0));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "tension_" + String (block), 2}, "Tension " + String (block), NormalisableRange<float> (0.1f, 10.0f, 0.0f, 0.5f), 1.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "cents_" + String (block), 2}, "Cents " + String (block), -100.0f, 100.0f, 0.0f));
+        // 0 Hz uses octave, note and cents instead
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "hz_" + String (block), 2}, "Frequency " + String (block) + " (Hz)", NormalisableRange<float> (0.0f, 20000.0f, 0.0f, 0.25f), 0.0f));
+        params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "sync_" + String (block), 2}, "Timing " + String (block) + " Sync", syncDivisions, 0));
+    }
+
+    // Where every note starts from, and what happens once the last pitch point is reached
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { pitchStartFrequencyTag, 2}, "Pitch Start (Hz)", NormalisableRange<float> (1.0f, 20000.0f, 0.0f, 0.25f), 20.0f));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { pitchEndModeTag, 2}, "Pitch End Mode", StringArray ("Hold", "Return to root", "Glide"), 0));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { pitchReturnTimeTag, 2}, "Pitch Return Time (ms)", 1.0f, 1000.0f, 100.0f));
+
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { fineTuneTag, 2}, "Fine Tune (cents)", -100.0f, 100.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { a4FrequencyTag, 2}, "A4 (Hz)", 400.0f, 480.0f, 440.0f));
+
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { amp_attack, 1}, "Amp Attack (ms)", 0.0f, 10.0f, 0.65f));
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { amp_decay, 1}, "Amp Decay (ms)", 0.0f, 50.0f, 10.0f));
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { amp_sustain, 1}, "Amp Sustain %", 0.0f, 100.0f, 100.0f));
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { amp_release, 1}, "Amp Release (ms)", 0.0f, 1000.0f, 419.43f));
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { amp_exponential_factor_a, 1}, "Amp Exponential Factor A", 1.0f, 10.0f, 4.31f));
 
+    // The defaults give the envelope from before it had a hold, a sustain time and a curve per segment
+    const StringArray envelopeCurves ("Linear", "Exponential", "Power");
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { ampHoldTag, 2}, "Amp Hold (ms)", 0.0f, 100.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { ampSustainTimeTag, 2}, "Amp Sustain Time (ms)", 0.0f, 1000.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampAttackCurveTag, 2}, "Amp Attack Curve", envelopeCurves, 0));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { ampAttackTensionTag, 2}, "Amp Attack Tension", NormalisableRange<float> (0.1f, 10.0f, 0.0f, 0.5f), 1.0f));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampDecayCurveTag, 2}, "Amp Decay Curve", envelopeCurves, 0));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { ampDecayTensionTag, 2}, "Amp Decay Tension", NormalisableRange<float> (0.1f, 10.0f, 0.0f, 0.5f), 1.0f));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampReleaseCurveTag, 2}, "Amp Release Curve", envelopeCurves, 2));
+
+    // Switch to the drawn curves, see setCurveEnvelope
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampSourceTag, 2}, "Amp Source", StringArray ("Envelope", "Curve"), 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { pitchSourceTag, 2}, "Pitch Source", StringArray ("Points", "Curve"), 0));
+
+    // Every time can follow the tempo of the host instead, "Off" uses the time in ms
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { pitchReturnTimeTag + "_sync", 2}, "Pitch Return Time Sync", syncDivisions, 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { amp_attack + "_sync", 2}, "Amp Attack Sync", syncDivisions, 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampHoldTag + "_sync", 2}, "Amp Hold Sync", syncDivisions, 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { amp_decay + "_sync", 2}, "Amp Decay Sync", syncDivisions, 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { ampSustainTimeTag + "_sync", 2}, "Amp Sustain Time Sync", syncDivisions, 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { amp_release + "_sync", 2}, "Amp Release Sync", syncDivisions, 0));
+
+    // Scales the times of the amp and pitch envelopes together
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { lengthTag, 2}, "Length %", NormalisableRange<float> (25.0f, 400.0f, 0.0f, 0.5f), 100.0f));
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { lengthKeepAttackTag, 2}, "Length Keeps Attack", false));
+
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { phaseTag, 1}, "Phase", 0.0f, 1.0f, 0.0f));
     params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { waveTypeTag, 1}, "Wave Type", StringArray ("Sine", "909"), 0));
 
+    // Transient shaper on the kick before the saturation, punch turns the attack up and the sustain down together
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "transient_attack", 2}, "Transient Attack (dB)", -24.0f, 24.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "transient_sustain", 2}, "Transient Sustain (dB)", -24.0f, 24.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "punch", 2}, "Punch %", 0.0f, 100.0f, 0.0f));
+
     params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { driveTag, 1}, "Drive", 0.0f, 24.0f, 0.0f));
-    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { saturationTypeTag, 1}, "Saturation Type", StringArray ("None", "Soft", "Clip", "ExtremeClip"), 0));
+    // The types after ExtremeClip came with version 2, the version only orders AU parameters and doesn't migrate anything.
+    // Saved states keep the choice index, but the normalised value of every choice moved, so host automation recorded
+    // with the 4 old choices plays back the wrong types
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { saturationTypeTag, 2}, "Saturation Type", saturationTypes, 0));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "saturation_folds", 2}, "Folds", 0.0f, 8.0f, 1.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "saturation_bias", 2}, "Tube Bias", 0.0f, 1.0f, 0.2f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "bitcrush_bits", 2}, "Bitcrush Bits", 1.0f, 16.0f, 8.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "bitcrush_rate", 2}, "Bitcrush Rate (Hz)", NormalisableRange<float> (100.0f, 48000.0f, 0.0f, 0.3f), 48000.0f));
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { "saturation_auto_gain", 2}, "Auto Gain", false));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "saturation_output", 2}, "Saturation Output (dB)", -24.0f, 24.0f, 0.0f));
+
+    // Filter with its own envelope, before or after the saturation
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "filter_mode", 2}, "Filter Mode", StringArray ("Off", "Low Pass", "High Pass", "Band Pass", "Notch"), 0));
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "filter_position", 2}, "Filter Position", StringArray ("Before Saturation", "After Saturation"), 1));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_cutoff", 2}, "Filter Cutoff (Hz)", NormalisableRange<float> (20.0f, 20000.0f, 0.0f, 0.25f), 20000.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_resonance", 2}, "Filter Resonance", 0.0f, 1.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_key_tracking", 2}, "Filter Key Tracking %", 0.0f, 100.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_env_amount", 2}, "Filter Envelope (oct)", -8.0f, 8.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_attack", 2}, "Filter Attack (ms)", NormalisableRange<float> (0.0f, 1000.0f, 0.0f, 0.4f), 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_decay", 2}, "Filter Decay (ms)", NormalisableRange<float> (0.0f, 2000.0f, 0.0f, 0.4f), 200.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_sustain", 2}, "Filter Sustain %", 0.0f, 100.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_release", 2}, "Filter Release (ms)", NormalisableRange<float> (0.0f, 2000.0f, 0.0f, 0.4f), 0.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "filter_release_tension", 2}, "Filter Release Tension", 0.01f, 10.0f, 1.0f));
+
+    // Splits the kick in bands that are saturated on their own, before the saturation
+    params.push_back (std::make_unique<AudioParameterInt> (ParameterID { "multiband_bands", 2}, "Bands", 1, maxBands, 1));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "multiband_low_crossover", 2}, "Low Crossover (Hz)", NormalisableRange<float> (20.0f, 1000.0f, 0.0f, 0.4f), 120.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "multiband_high_crossover", 2}, "High Crossover (Hz)", NormalisableRange<float> (200.0f, 16000.0f, 0.0f, 0.4f), 2000.0f));
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { "multiband_low_clean", 2}, "Clean Low Band", false));
+    for (int band = 1; band <= maxBands; ++band)
+    {
+        String name = "Band " + String (band);
+        params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "band_type_" + String (band), 2}, name + " Type", saturationTypes, 1));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "band_drive_" + String (band), 2}, name + " Drive", 0.0f, 36.0f, 0.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "band_level_" + String (band), 2}, name + " Level (dB)", -36.0f, 12.0f, 0.0f));
+    }
+
+    // Serial distortion stages after the saturation, "distortion_stages" sets how many are used
+    params.push_back (std::make_unique<AudioParameterInt> (ParameterID { "distortion_stages", 2}, "Distortion Stages", 0, maxDistortionStages, 0));
+    for (int stage = 1; stage <= maxDistortionStages; ++stage)
+    {
+        String name = "Distortion " + String (stage);
+        params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { "distortion_type_" + String (stage), 2}, name + " Type", saturationTypes, 1));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "distortion_drive_" + String (stage), 2}, name + " Drive", 0.0f, 36.0f, 0.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "distortion_emphasis_" + String (stage), 2}, name + " Emphasis (dB)", 0.0f, 24.0f, 0.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "distortion_emphasis_frequency_" + String (stage), 2}, name + " Emphasis Frequency (Hz)", NormalisableRange<float> (20.0f, 20000.0f, 0.0f, 0.25f), 1000.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "distortion_output_" + String (stage), 2}, name + " Output (dB)", -36.0f, 12.0f, 0.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "distortion_mix_" + String (stage), 2}, name + " Mix %", 0.0f, 100.0f, 100.0f));
+    }
+    params.push_back (std::make_unique<AudioParameterChoice> (ParameterID { oversamplingTag, 2}, "Oversampling", StringArray ("Off", "2x", "4x", "8x"), 0));
+
+    // EQ at the end of the kick: a low shelf, two peaks and a high shelf, and a high pass
+    const StringArray eqBandNames ("Low Shelf", "Peak 1", "Peak 2", "High Shelf");
+    const float eqBandFrequencies[] = { 80.0f, 250.0f, 2500.0f, 8000.0f };
+    for (int band = 1; band <= eqBandNames.size(); ++band)
+    {
+        String name = "EQ " + eqBandNames[band - 1];
+        float q = (band == 1 || band == eqBandNames.size()) ? 0.707f : 1.0f;
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "eq_frequency_" + String (band), 2}, name + " Frequency (Hz)", NormalisableRange<float> (20.0f, 20000.0f, 0.0f, 0.25f), eqBandFrequencies[band - 1]));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "eq_gain_" + String (band), 2}, name + " Gain (dB)", -18.0f, 18.0f, 0.0f));
+        params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "eq_q_" + String (band), 2}, name + " Q", NormalisableRange<float> (0.1f, 18.0f, 0.0f, 0.3f), q));
+    }
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { "eq_high_pass", 2}, "EQ High Pass", false));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "eq_high_pass_frequency", 2}, "EQ High Pass Frequency (Hz)", NormalisableRange<float> (10.0f, 500.0f, 0.0f, 0.4f), 30.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "eq_high_pass_q", 2}, "EQ High Pass Q", NormalisableRange<float> (0.1f, 18.0f, 0.0f, 0.3f), 0.707f));
+
+    // Removes the DC offset and optionally the subsonic rumble, after the EQ
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { "dc_blocker", 2}, "DC Blocker", false));
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { "subsonic", 2}, "Subsonic Filter", false));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "subsonic_frequency", 2}, "Subsonic Frequency (Hz)", NormalisableRange<float> (10.0f, 60.0f, 0.0f, 0.5f), 20.0f));
+
+    // Output gain and a true peak limiter at the very end, the limiter adds latency while it is on
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "output_gain", 2}, "Output Gain (dB)", -36.0f, 12.0f, 0.0f));
+    params.push_back (std::make_unique<AudioParameterBool> (ParameterID { limiterTag, 2}, "Limiter", false));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "limiter_ceiling", 2}, "Limiter Ceiling (dBTP)", -12.0f, 0.0f, -1.0f));
+    params.push_back (std::make_unique<AudioParameterFloat> (ParameterID { "limiter_release", 2}, "Limiter Release (ms)", NormalisableRange<float> (1.0f, 500.0f, 0.0f, 0.4f), 50.0f));
 }
 
 void Gruvah::prepareToPlay (double sampleRate, int samplesPerBlock)
 {
+    stopTimer();
+    diagnostics.reset();
+    paramStore.reset();
+    synthState.reset();
     distProc.reset (kick_synth::create (sampleRate));
 
+    // Parameter listeners can fire from any thread, they only write into the param store
+    // and the audio thread picks the changes up at the start of the next block
+    paramStore.reset (kick_synth::create_param_store (distProc.get()));
+
+    // The audio thread only logs into a lock free ring buffer, the timer drains it on the message thread
+    diagnostics.reset (kick_synth::create_diagnostics (distProc.get()));
+    synthState.reset (kick_synth::create_synth_state (distProc.get()));
+    startTimerHz (30);
+
 
     paramListener = new ParamListener(this);
 
@@ -108,12 +284,54 @@ void Gruvah::prepareToPlay (double sampleRate, int samplesPerBlock)
         vts.addParameterListener(paramWithID->getParameterID(), paramListener);
     }
 
+    loadCurveEnvelopes();
+    loadTuning();
+
+    // Hosts read the latency right after prepareToPlay, so it can't wait for the message thread
+    setLatencySamples ((int) kick_synth::get_latency_samples (paramStore.get(), (size_t) sampleRate));
 }
 
 void Gruvah::releaseResources()
 {
 }
 
+void Gruvah::timerCallback()
+{
+    if (diagnostics == nullptr || synthState == nullptr)
+        return;
+
+    // Published after every block by the audio thread, used by the editor for the playhead overlays
+    kick_synth::SynthStateSnapshot snapshot;
+    kick_synth::read_synth_state (synthState.get(), &snapshot);
+
+    magicState.getPropertyAsValue ("kick:frequency").setValue (snapshot.frequency);
+    magicState.getPropertyAsValue ("kick:amp_envelope_value").setValue (snapshot.amp_envelope_value);
+    magicState.getPropertyAsValue ("kick:amp_envelope_stage").setValue ((int) snapshot.amp_envelope_stage);
+    magicState.getPropertyAsValue ("kick:pitch_envelope_segment").setValue (snapshot.pitch_envelope_segment);
+    magicState.getPropertyAsValue ("kick:voice_count").setValue ((int) snapshot.voice_count);
+    magicState.getPropertyAsValue ("kick:peak_output").setValue (snapshot.peak_output);
+    magicState.getPropertyAsValue ("kick:saturation_gain_reduction_db").setValue (snapshot.saturation_gain_reduction_db);
+
+    kick_synth::drain_diagnostics (diagnostics.get(), [] (const kick_synth::DiagnosticEvent* event, void*)
+    {
+        switch (event->kind)
+        {
+            case kick_synth::DiagnosticEventKind::MidiMessageQueued:
+                DBG ("Queued midi message: note " << event->value << " at " << event->timestamp);
+                break;
+            case kick_synth::DiagnosticEventKind::MidiMessageDropped:
+                DBG ("Dropped midi message: note " << event->value << " at " << event->timestamp);
+                break;
+            case kick_synth::DiagnosticEventKind::Clip:
+                DBG ("Clipping! with value " << event->value << " at sample " << event->timestamp);
+                break;
+            case kick_synth::DiagnosticEventKind::Allocation:
+                DBG ("process_block allocated " << event->value << " times");
+                break;
+        }
+    }, nullptr);
+}
+
 void Gruvah::processMidi(MidiBuffer& midiBuffer, kick_synth::KickSynth *distProc)
 {
 
@@ -126,9 +344,8 @@ void Gruvah::processMidi(MidiBuffer& midiBuffer, kick_synth::KickSynth *distProc
         if (!message.isNoteOnOrOff())
             continue;
 
-        const kick_synth::MidiMessage *midiMessage = kick_synth::create_midi_message(message.getTimeStamp(), rawData, message.getNoteNumber(), message.getVelocity());
-        kick_synth::process_midi_message (distProc, midiMessage);
-        kick_synth::destroy_midi_message (midiMessage);
+        // process_raw_midi_message doesn't allocate, unlike create_midi_message
+        kick_synth::process_raw_midi_message (distProc, message.getTimeStamp(), rawData, message.getNoteNumber(), message.getVelocity());
     }
 
 }
@@ -148,6 +365,122 @@ void Gruvah::afterSetStateInformation() {
     setMidiNoteLabel(2);
     setMidiNoteLabel(3);
     setMidiNoteLabel(4);
+
+    loadCurveEnvelopes();
+    loadTuning();
+}
+
+static String getCurvePropertyId (kick_synth::CurveTarget target)
+{
+    return target == kick_synth::CurveTarget::Amp ? "curve:amp" : "curve:pitch";
+}
+
+bool Gruvah::setCurveEnvelope (kick_synth::CurveTarget target, const String& points)
+{
+    if (paramStore == nullptr || ! kick_synth::curve_envelope_deserialize (paramStore.get(), target, points.toRawUTF8()))
+        return false;
+
+    magicState.getPropertyAsValue (getCurvePropertyId (target)).setValue (points);
+    return true;
+}
+
+String Gruvah::getPitchPoints() const
+{
+    if (paramStore == nullptr)
+        return {};
+
+    auto length = kick_synth::pitch_points_serialize (paramStore.get(), nullptr, 0);
+    std::vector<char> points (length + 1);
+    kick_synth::pitch_points_serialize (paramStore.get(), points.data(), points.size());
+    return String::fromUTF8 (points.data(), (int) length);
+}
+
+void Gruvah::setParameterFromPitchPoints (const char* parameterID, float newValue, void* processor)
+{
+    // The parameter listener passes the new value on to the param store
+    if (auto* param = static_cast<Gruvah*> (processor)->vts.getParameter (parameterID))
+        param->setValueNotifyingHost (param->convertTo0to1 (newValue));
+}
+
+bool Gruvah::setPitchPoints (const String& points)
+{
+    return kick_synth::pitch_points_deserialize (points.toRawUTF8(), &setParameterFromPitchPoints, this);
+}
+
+bool Gruvah::insertPitchPoint (int index, const String& point)
+{
+    if (paramStore == nullptr || index < 0)
+        return false;
+
+    return kick_synth::pitch_points_insert (paramStore.get(), (size_t) index, point.toRawUTF8(), &setParameterFromPitchPoints, this);
+}
+
+bool Gruvah::removePitchPoint (int index)
+{
+    if (paramStore == nullptr || index < 0)
+        return false;
+
+    return kick_synth::pitch_points_remove (paramStore.get(), (size_t) index, &setParameterFromPitchPoints, this);
+}
+
+// The drawn curves aren't parameters, they live as properties in the state of the preset
+void Gruvah::loadCurveEnvelopes()
+{
+    if (paramStore == nullptr)
+        return;
+
+    for (auto target : { kick_synth::CurveTarget::Amp, kick_synth::CurveTarget::Pitch })
+    {
+        auto points = magicState.getPropertyAsValue (getCurvePropertyId (target)).toString();
+        kick_synth::curve_envelope_deserialize (paramStore.get(), target, points.toRawUTF8());
+    }
+}
+
+static const String tuningSclPropertyId = "tuning:scl";
+static const String tuningKbmPropertyId = "tuning:kbm";
+
+static bool loadTuningIntoParamStore (const kick_synth::ParamStore* paramStore, const String& scl, const String& kbm)
+{
+    if (scl.isEmpty())
+    {
+        kick_synth::param_store_reset_tuning (paramStore);
+        return true;
+    }
+
+    return kick_synth::param_store_load_tuning (paramStore, scl.toRawUTF8(), kbm.isEmpty() ? nullptr : kbm.toRawUTF8());
+}
+
+bool Gruvah::setTuning (const String& scl, const String& kbm)
+{
+    if (paramStore == nullptr || scl.isEmpty() || ! loadTuningIntoParamStore (paramStore.get(), scl, kbm))
+        return false;
+
+    magicState.getPropertyAsValue (tuningSclPropertyId).setValue (scl);
+    magicState.getPropertyAsValue (tuningKbmPropertyId).setValue (kbm);
+    return true;
+}
+
+void Gruvah::resetTuning()
+{
+    magicState.getPropertyAsValue (tuningSclPropertyId).setValue (String());
+    magicState.getPropertyAsValue (tuningKbmPropertyId).setValue (String());
+
+    if (paramStore != nullptr)
+        kick_synth::param_store_reset_tuning (paramStore.get());
+}
+
+// Like the drawn curves the Scala files live as properties in the state of the preset, without them the tuning is
+// equal temperament. The A4 reference is the a4_frequency parameter, which is saved with the others
+void Gruvah::loadTuning()
+{
+    if (paramStore == nullptr)
+        return;
+
+    auto scl = magicState.getPropertyAsValue (tuningSclPropertyId).toString();
+    auto kbm = magicState.getPropertyAsValue (tuningKbmPropertyId).toString();
+
+    if (! loadTuningIntoParamStore (paramStore.get(), scl, kbm))
+        kick_synth::param_store_reset_tuning (paramStore.get());
 }
 
 void Gruvah::setMidiNoteLabel(int block) {
@@ -169,6 +502,15 @@ void Gruvah::processBlock (AudioBuffer<float>& buffer, MidiBuffer& midiBuffer)
 {
     ScopedNoDenormals noDenormals;
 
+    // Synced times follow the tempo of the host, the last known tempo stays when the host doesn't report one
+    if (auto* playHead = getPlayHead())
+    {
+        if (auto position = playHead->getPosition())
+        {
+            if (auto bpm = position->getBpm())
+                kick_synth::set_transport (distProc.get(), *bpm, position->getPpqPosition().orFallback (0.0));
+        }
+    }
 
     if (midiBuffer.getNumEvents() > 0) {
         this->processMidi(midiBuffer, distProc.get());
@@ -182,13 +524,13 @@ void Gruvah::processBlock (AudioBuffer<float>& buffer, MidiBuffer& midiBuffer)
 }
 
 void Gruvah::updateParameter(const String &parameterID, float newValue) {
-    if (distProc == nullptr) {
+    if (paramStore == nullptr) {
         return;
     }
 
     // Setting the labels for the midi notes
-    if (parameterID.contains("octave_")) {
-        String block = parameterID.substring(parameterID.length() - 1);
+    if (parameterID.startsWith("octave_")) {
+        String block = parameterID.fromLastOccurrenceOf("_", false, false);
         String noteId = "note_" + block;
 
         int32_t octave = newValue;
@@ -200,8 +542,8 @@ void Gruvah::updateParameter(const String &parameterID, float newValue) {
         magicState.getPropertyAsValue("midi_note:" + block).setValue(midiNote);
     }
 
-    if (parameterID.contains("note_")) {
-        String block = parameterID.substring(parameterID.length() - 1);
+    if (parameterID.startsWith("note_")) {
+        String block = parameterID.fromLastOccurrenceOf("_", false, false);
         String octaveId = "octave_" + block;
 
         int32_t octave = getCorrectedValue(octaveId);
@@ -213,7 +555,21 @@ void Gruvah::updateParameter(const String &parameterID, float newValue) {
         magicState.getPropertyAsValue("midi_note:" + block).setValue(midiNote);
     }
 
-    kick_synth::update_param(distProc.get(), parameterID.toRawUTF8(), newValue);
+    if (! kick_synth::param_store_update(paramStore.get(), parameterID.toRawUTF8(), newValue))
+        DBG ("Invalid parameter: " << parameterID << " = " << newValue);
+
+    // Parameters can change on the audio thread, the host is only told about the new latency from the message thread
+    if (parameterID == oversamplingTag || parameterID == limiterTag) {
+        triggerAsyncUpdate();
+    }
+}
+
+void Gruvah::handleAsyncUpdate()
+{
+    if (paramStore == nullptr)
+        return;
+
+    setLatencySamples ((int) kick_synth::get_latency_samples (paramStore.get(), (size_t) getSampleRate()));
 }
 
 
@@ -18,7 +18,9 @@
 #include "gruvah.h"
 
 
-class Gruvah : public PluginBase<Gruvah>
+class Gruvah : public PluginBase<Gruvah>,
+               private Timer,
+               private AsyncUpdater
 {
 public:
     Gruvah();
@@ -32,9 +34,31 @@ public:
     void initUpdateParameters() override;
     void afterSetStateInformation() override;
 
+    // Replaces a drawn curve, the points are saved with the preset. Returns false when the points are invalid
+    bool setCurveEnvelope(kick_synth::CurveTarget target, const String& points);
+
+    // Loads a Scala tuning, kbm can be empty. The files are saved with the preset. Returns false when either is invalid
+    bool setTuning(const String& scl, const String& kbm);
+    // Back to equal temperament tuned by the A4 parameter
+    void resetTuning();
+
+    // Edit the pitch points as a whole, in the format of pitch_points_serialize. The changes are set on the parameters,
+    // so the host sees them and they are saved with the preset. Return false when nothing was changed
+    String getPitchPoints() const;
+    bool setPitchPoints(const String& points);
+    bool insertPitchPoint(int index, const String& point);
+    bool removePitchPoint(int index);
+
 private:
 
     std::unique_ptr<kick_synth::KickSynth, decltype(&kick_synth::destroy)> distProc { nullptr, &kick_synth::destroy};
+    std::unique_ptr<const kick_synth::Diagnostics, decltype(&kick_synth::destroy_diagnostics)> diagnostics { nullptr, &kick_synth::destroy_diagnostics};
+    std::unique_ptr<const kick_synth::ParamStore, decltype(&kick_synth::destroy_param_store)> paramStore { nullptr, &kick_synth::destroy_param_store};
+    std::unique_ptr<const kick_synth::SynthState, decltype(&kick_synth::destroy_synth_state)> synthState { nullptr, &kick_synth::destroy_synth_state};
+
+    void timerCallback() override;
+    // Reports the latency to the host, on the message thread
+    void handleAsyncUpdate() override;
 
     JUCE_DECLARE_NON_COPYABLE_WITH_LEAK_DETECTOR (Gruvah)
 
@@ -42,6 +66,9 @@ private:
     float getCorrectedValue(String param);
 
     void setMidiNoteLabel(int block);
+    static void setParameterFromPitchPoints(const char* parameterID, float newValue, void* processor);
+    void loadCurveEnvelopes();
+    void loadTuning();
 };
 
 #endif // GruvahPLUGIN_H_INCLUDED